@@ -1,89 +1,252 @@
 use std::sync::Arc;
 
-use dvorak_message::message::{Message, MessageType};
-use tokio::{
-    net::TcpStream,
-    sync::mpsc::{self, Receiver, Sender},
-};
+use dc_message_client::{DcClient, Event, Events, Record, Store};
+use ratatui::crossterm::event::KeyEvent;
+use tokio::sync::mpsc::{self, Receiver, Sender};
 
-use crate::input::Input;
+use crate::{
+    command::{Command, Commands},
+    input::{Input, InputType},
+    screen::{ConnectionState, Screen},
+};
 
-type Username = String;
+/// how many messages found by `/search` are shown at most, the latest ones
+const SEARCH_LIMIT: usize = 20;
 
+/// the client in terminal, built on [`DcClient`]
 pub(crate) struct Client {
-    tcp_stream: TcpStream,
+    dc_client: DcClient,
+    events: Events,
     inbox: Receiver<ClientMessage>,
-    username: Username,
-    receiver: Option<Username>,
     input_handler: Input,
+    commands: Arc<Commands>,
+    screen: Box<dyn Screen>,
+    /// where the messages sent and received are kept, [`None`] if they are not
+    store: Option<Store>,
 }
 
 #[derive(Debug)]
 pub(crate) enum ClientMessage {
     Text(String),
-    To(String),
-    Quit,
+    /// text sent to the user, without switching to
+    Addressed {
+        to: String,
+        text: String,
+    },
+    Command(Command),
+    /// a key pressed in the full screen
+    Key(KeyEvent),
+    /// the terminal is resized
+    Resize,
 }
 
 pub(crate) type ClientSender = Arc<Sender<ClientMessage>>;
 
 impl Client {
-    pub fn new(username: Username, tcp_stream: TcpStream) -> Self {
+    pub fn new(
+        dc_client: DcClient,
+        events: Events,
+        screen: Box<dyn Screen>,
+        commands: Arc<Commands>,
+    ) -> Self {
         let (tx, rx) = mpsc::channel(1);
         let sender = Arc::new(tx);
 
         Client {
-            tcp_stream,
+            input_handler: Input::new(sender, Arc::clone(&commands), dc_client.clone()),
+            dc_client,
+            events,
             inbox: rx,
-            username,
-            receiver: None,
-            input_handler: Input::new(sender),
+            commands,
+            screen,
+            store: None,
         }
     }
 
-    pub async fn listen(&mut self) {
+    /// keep the messages sent and received in the local store
+    pub fn with_store(mut self, store: Store) -> Self {
+        self.store = Some(store);
+        self
+    }
 
-        self.input_handler.listen().await;
+    pub async fn listen(&mut self) {
+        self.replay();
+        if self.screen.full_screen() {
+            self.input_handler.listen_keys().await;
+        } else {
+            self.input_handler.listen().await;
+        }
+        // for `/who`
+        let _ = self.dc_client.subscribe_presence().await;
 
         'listen: loop {
             tokio::select! {
                 client_message = self.inbox.recv() => {
-                    if let Some(msg) = client_message {
-                        match msg {
-                            ClientMessage::Text(data) => {
-                                if let Some(receiver_name) = &self.receiver {
-                                    let message = Message::new(
-                                        MessageType::Text(data),
-                                        self.username.clone(),
-                                        receiver_name.clone(),
-                                    );
-                                    Message::send(&mut self.tcp_stream, message).await.unwrap();
-                                }
-                            },
-                            ClientMessage::Quit => {
-                                println!("Received instruct: Quit");
-                                break 'listen;
-                            },
-                            ClientMessage::To(username) => {
-                                println!("Change receiver: {username}");
-                                self.receiver = Some(username)
-                            }
-                        }
+                    let Some(msg) = client_message else {
+                        continue;
+                    };
+                    if !self.handle(msg).await {
+                        break 'listen;
                     }
                 },
-                message = async {
-                    Message::read_from(&mut self.tcp_stream).await.unwrap()
-
-                } => {
-                    if message.is_none() {
-                        continue;
+                event = self.events.recv() => {
+                    let Some(event) = event else {
+                        self.screen.connection(ConnectionState::Disconnected);
+                        break 'listen;
+                    };
+                    match event {
+                        Event::Message { from, to, body } => {
+                            let room = (to != self.dc_client.username()).then_some(to.as_str());
+                            self.screen.message(&from, room, &body);
+                            self.keep(&from, &to, &body).await;
+                        }
+                        Event::Notice(text) => self.screen.announce(&text),
+                        Event::Error(report) => self.screen.error(&report),
+                        Event::Disconnected => self.screen.connection(ConnectionState::Reconnecting),
+                        Event::Reconnected => self.screen.connection(ConnectionState::Connected),
+                        Event::LoggedOut(reason) => self.screen.error(&reason),
+                        Event::Online(username) => self.screen.presence(&username, true),
+                        Event::Offline(username) => self.screen.presence(&username, false),
                     }
+                }
+            }
+        }
+    }
+
+    /// handle what is typed, false once the client quits
+    async fn handle(&mut self, message: ClientMessage) -> bool {
+        let message = match message {
+            ClientMessage::Key(key) => match self.screen.key(key) {
+                Some(InputType::Text(data)) => ClientMessage::Text(data),
+                Some(InputType::Addressed { to, text }) => ClientMessage::Addressed { to, text },
+                Some(InputType::Command(command)) => ClientMessage::Command(command),
+                None => return true,
+            },
+            ClientMessage::Resize => {
+                self.screen.resize();
+                return true;
+            }
+            message => message,
+        };
 
-                    let bytes = message.unwrap().message_type.as_bytes();
-                    let data: String = String::from_utf8(bytes.to_vec()).unwrap();
-                    println!("{data}");
+        match message {
+            ClientMessage::Text(data) if data.is_empty() => {}
+            ClientMessage::Text(data) => {
+                let Some(receiver) = self.screen.conversations().receiver().map(str::to_string)
+                else {
+                    self.screen.notice(
+                        "nobody to send to, choose one by /to <username>, \
+                         or send by @<username> <message>",
+                    );
+                    return true;
+                };
+                return self.send(&receiver, &data).await;
+            }
+            ClientMessage::Addressed { to, text } => return self.send(&to, &text).await,
+            ClientMessage::Command(Command::Quit) => {
+                tracing::debug!("received command: quit");
+                self.dc_client.close().await;
+                return false;
+            }
+            ClientMessage::Command(Command::To(username)) => self.screen.receiver(&username),
+            ClientMessage::Command(Command::Switch(None)) => {
+                let list = self.screen.conversations().list();
+                self.screen.notice(&list);
+            }
+            ClientMessage::Command(Command::Switch(Some(conversation))) => {
+                let found = self
+                    .screen
+                    .conversations()
+                    .find(&conversation)
+                    .map(str::to_string);
+                match found {
+                    Some(peer) => self.screen.receiver(&peer),
+                    None => self.screen.notice(&format!(
+                        "no conversation {conversation}, start one by /to <username>"
+                    )),
                 }
             }
+            ClientMessage::Command(Command::Search(text)) => {
+                let notice = match &self.store {
+                    Some(store) => found(&store.search(&text), &text),
+                    None => "no local store, start the client with --store".to_string(),
+                };
+                self.screen.notice(&notice);
+            }
+            ClientMessage::Command(Command::Who) => {
+                let online = self.dc_client.online_users().join(", ");
+                self.screen.notice(&format!("Online: {online}"));
+            }
+            ClientMessage::Command(Command::Help(name)) => {
+                self.screen.notice(&self.commands.help(name.as_deref()));
+            }
+            ClientMessage::Key(_) | ClientMessage::Resize => {}
+        }
+        true
+    }
+
+    /// send the text to the user, false if the client is closed
+    async fn send(&mut self, to: &str, text: &str) -> bool {
+        if self.dc_client.send_text(to, text).await.is_err() {
+            return false;
+        }
+        self.screen.sent(to, text);
+        let username = self.dc_client.username().to_string();
+        self.keep(&username, to, text).await;
+        true
+    }
+
+    /// keep the message in the local store, if there is one
+    async fn keep(&mut self, from: &str, to: &str, body: &str) {
+        let Some(store) = &mut self.store else {
+            return;
+        };
+        if let Err(e) = store.record(Record::new(from, to, body)).await {
+            tracing::warn!(%e, "keep message failure");
+            self.screen.error(&format!("keep the message failure: {e}"));
+        }
+    }
+
+    /// show the messages kept in the local store, in their conversations
+    fn replay(&mut self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let username = self.dc_client.username();
+        for record in store.records() {
+            let conversation = if record.from == username {
+                &record.to
+            } else if record.to == username {
+                &record.from
+            } else {
+                // sent to a room
+                &record.to
+            };
+            self.screen
+                .history(conversation, &record.from, &record.body);
         }
+        let kept = store.records().len();
+        self.screen.notice(&format!(
+            "{kept} messages kept in the local store, find them by /search <text>"
+        ));
+        let skipped = store.skipped();
+        if skipped > 0 {
+            self.screen.error(&format!(
+                "{skipped} messages in the local store could not be read, skipped"
+            ));
+        }
+    }
+}
+
+/// the messages found, the latest ones if there are too many
+fn found(records: &[&Record], text: &str) -> String {
+    let mut found = format!("Found {} messages with \"{text}\"", records.len());
+    for record in &records[records.len().saturating_sub(SEARCH_LIMIT)..] {
+        let at = humantime::format_rfc3339_seconds(record.at);
+        found += &format!(
+            "\n  [{at}] {} -> {}: {}",
+            record.from, record.to, record.body
+        );
     }
+    found
 }
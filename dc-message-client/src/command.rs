@@ -0,0 +1,423 @@
+use std::fmt::{self, Display, Formatter};
+
+/// a slash command typed in the terminal, like `/to alice`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Command {
+    /// send the following texts to the user
+    To(String),
+    /// send the following texts to the conversation opened, by its name or number,
+    /// or list the conversations if [`None`]
+    Switch(Option<String>),
+    /// find the messages kept in the local store
+    Search(String),
+    /// list the users online
+    Who,
+    /// show the usage of a command, or of all if [`None`]
+    Help(Option<String>),
+    Quit,
+}
+
+/// what an argument is, to check and complete it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ArgKind {
+    /// a username, completed by the users online
+    Username,
+    /// name of a command, completed by the commands
+    Command,
+    /// the rest of the line, spaces included
+    Text,
+}
+
+/// an argument of a command
+#[derive(Debug)]
+pub(crate) struct Arg {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub required: bool,
+}
+
+/// how a command is typed, and what it is built into
+#[derive(Debug)]
+pub(crate) struct CommandSpec {
+    pub name: &'static str,
+    pub args: &'static [Arg],
+    pub about: &'static str,
+    /// build the command with its arguments, already checked by their kinds
+    build: fn(Vec<String>) -> Command,
+}
+
+impl CommandSpec {
+    /// like `/to <username>`, optional arguments are in brackets
+    pub fn usage(&self) -> String {
+        self.args
+            .iter()
+            .fold(format!("/{}", self.name), |usage, arg| match arg.required {
+                true => format!("{usage} <{}>", arg.name),
+                false => format!("{usage} [{}]", arg.name),
+            })
+    }
+}
+
+/// the built-in commands, add a command here and to [`Command`]
+const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "to",
+        args: &[Arg {
+            name: "username",
+            kind: ArgKind::Username,
+            required: true,
+        }],
+        about: "send the following messages to the user",
+        build: |mut args| Command::To(args.remove(0)),
+    },
+    CommandSpec {
+        name: "switch",
+        args: &[Arg {
+            name: "conversation",
+            kind: ArgKind::Username,
+            required: false,
+        }],
+        about:
+            "send the following messages to the conversation, by its name or number, or list them",
+        build: |args| Command::Switch(args.into_iter().next()),
+    },
+    CommandSpec {
+        name: "search",
+        args: &[Arg {
+            name: "text",
+            kind: ArgKind::Text,
+            required: true,
+        }],
+        about: "find the messages kept in the local store, ignoring case",
+        build: |mut args| Command::Search(args.remove(0)),
+    },
+    CommandSpec {
+        name: "who",
+        args: &[],
+        about: "list the users online",
+        build: |_| Command::Who,
+    },
+    CommandSpec {
+        name: "help",
+        args: &[Arg {
+            name: "command",
+            kind: ArgKind::Command,
+            required: false,
+        }],
+        about: "show the usage of all commands, or of the command",
+        build: |args| Command::Help(args.into_iter().next()),
+    },
+    CommandSpec {
+        name: "quit",
+        args: &[],
+        about: "logout and exit",
+        build: |_| Command::Quit,
+    },
+];
+
+/// why a command could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CommandError {
+    /// no command named so, maybe the one similar is meant
+    Unknown {
+        name: String,
+        similar: Option<&'static str>,
+    },
+    /// the arguments do not fit the usage
+    Usage { usage: String, reason: String },
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Unknown {
+                name,
+                similar: Some(similar),
+            } => write!(f, "unknown command /{name}, did you mean /{similar}?"),
+            CommandError::Unknown { name, .. } => {
+                write!(f, "unknown command /{name}, see /help for all commands")
+            }
+            CommandError::Usage { usage, reason } => write!(f, "{reason}, usage: {usage}"),
+        }
+    }
+}
+
+/// the registry of commands, parsing and completing them
+#[derive(Debug)]
+pub(crate) struct Commands {
+    specs: &'static [CommandSpec],
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        Commands { specs: COMMANDS }
+    }
+}
+
+impl Commands {
+    pub fn get(&self, name: &str) -> Option<&CommandSpec> {
+        self.specs.iter().find(|spec| spec.name == name)
+    }
+
+    /// parse the line after `/`, the name could also be followed by `:` like `to:alice`
+    pub fn parse(&self, line: &str) -> Result<Command, CommandError> {
+        let line = line.trim();
+        let (name, rest) = line
+            .split_once(|c: char| c.is_whitespace() || c == ':')
+            .unwrap_or((line, ""));
+        let spec = self.get(name).ok_or_else(|| CommandError::Unknown {
+            name: name.to_string(),
+            similar: self.similar(name),
+        })?;
+        let usage_error = |reason: String| CommandError::Usage {
+            usage: spec.usage(),
+            reason,
+        };
+
+        let mut rest = rest.trim();
+        let mut args = Vec::with_capacity(spec.args.len());
+        for arg in spec.args {
+            let value = match arg.kind {
+                ArgKind::Text => std::mem::take(&mut rest),
+                _ => {
+                    let (value, left) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    rest = left.trim_start();
+                    value
+                }
+            };
+            match value {
+                "" if arg.required => return Err(usage_error(format!("missing <{}>", arg.name))),
+                "" => break,
+                value => {
+                    self.check(arg, value).map_err(usage_error)?;
+                    args.push(value.to_string());
+                }
+            }
+        }
+        if let Some(extra) = rest.split_whitespace().next() {
+            return Err(usage_error(format!("unexpected argument {extra}")));
+        }
+        Ok((spec.build)(args))
+    }
+
+    /// check the argument by its kind
+    fn check(&self, arg: &Arg, value: &str) -> Result<(), String> {
+        match arg.kind {
+            // the length of username is a byte in the frame
+            ArgKind::Username if value.len() > u8::MAX as usize => {
+                Err(format!("<{}> is too long", arg.name))
+            }
+            ArgKind::Username | ArgKind::Text => Ok(()),
+            ArgKind::Command => match self.get(value.trim_start_matches('/')) {
+                Some(_) => Ok(()),
+                None => Err(format!("unknown command /{value}")),
+            },
+        }
+    }
+
+    /// the command a mistyped name most likely means, by the edit distance
+    fn similar(&self, name: &str) -> Option<&'static str> {
+        self.specs
+            .iter()
+            .map(|spec| (distance(name, spec.name), spec.name))
+            .filter(|(distance, _)| *distance <= 2)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, name)| name)
+    }
+
+    /// the usage of all commands, or of the command named
+    pub fn help(&self, name: Option<&str>) -> String {
+        let specs = match name.and_then(|name| self.get(name.trim_start_matches('/'))) {
+            Some(spec) => std::slice::from_ref(spec),
+            None => self.specs,
+        };
+        let width = specs
+            .iter()
+            .map(|spec| spec.usage().len())
+            .max()
+            .unwrap_or(0);
+        let mut help = String::from("Commands:");
+        for spec in specs {
+            help += &format!("\n  {:width$}  {}", spec.usage(), spec.about);
+        }
+        if name.is_none() {
+            help += "\n`@<username> <message>` sends the message to the user, without switching";
+            help += "\nother lines are sent to the receiver, start with `//` or `@@` to send a leading `/` or `@`";
+        }
+        help
+    }
+
+    /// complete the line before the cursor,
+    /// the start of the word to replace and the candidates, with the users online
+    pub fn complete(&self, line: &str, online: &[String]) -> (usize, Vec<String>) {
+        // like `@alice hello`
+        if let Some(username) = line.strip_prefix('@') {
+            if line.starts_with("@@") || username.contains(char::is_whitespace) {
+                return (0, Vec::new());
+            }
+            let candidates = online
+                .iter()
+                .filter(|candidate| candidate.starts_with(username))
+                .map(|candidate| format!("{candidate} "))
+                .collect();
+            return (1, candidates);
+        }
+        let Some(command) = line.strip_prefix('/').filter(|_| !line.starts_with("//")) else {
+            return (0, Vec::new());
+        };
+        let Some(split) = command.find(|c: char| c.is_whitespace() || c == ':') else {
+            let candidates = self
+                .specs
+                .iter()
+                .filter(|spec| spec.name.starts_with(command))
+                .map(|spec| match spec.args.is_empty() {
+                    true => spec.name.to_string(),
+                    false => format!("{} ", spec.name),
+                })
+                .collect();
+            return (1, candidates);
+        };
+
+        let Some(spec) = self.get(&command[..split]) else {
+            return (0, Vec::new());
+        };
+        let rest = &command[split + 1..];
+        let word_start = rest.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &rest[word_start..];
+        let Some(arg) = spec.args.get(rest[..word_start].split_whitespace().count()) else {
+            return (0, Vec::new());
+        };
+        let candidates = match arg.kind {
+            ArgKind::Username => online
+                .iter()
+                .filter(|username| username.starts_with(word))
+                .cloned()
+                .collect(),
+            ArgKind::Command => self
+                .specs
+                .iter()
+                .filter(|spec| spec.name.starts_with(word))
+                .map(|spec| spec.name.to_string())
+                .collect(),
+            ArgKind::Text => Vec::new(),
+        };
+        (line.len() - word.len(), candidates)
+    }
+}
+
+/// the Levenshtein distance of two words
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = previous[j] + usize::from(ca != *cb);
+            current.push(substitute.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands_with_args() {
+        let commands = Commands::default();
+        assert_eq!(Ok(Command::To("alice".into())), commands.parse("to alice"));
+        assert_eq!(Ok(Command::To("alice".into())), commands.parse("to:alice"));
+        assert_eq!(Ok(Command::Who), commands.parse("who"));
+        assert_eq!(
+            Ok(Command::Search("see you  soon".into())),
+            commands.parse("search  see you  soon ")
+        );
+        assert_eq!(Ok(Command::Switch(None)), commands.parse("switch"));
+        assert_eq!(
+            Ok(Command::Switch(Some("2".into()))),
+            commands.parse("switch 2")
+        );
+        assert_eq!(Ok(Command::Help(None)), commands.parse("help"));
+        assert_eq!(
+            Ok(Command::Help(Some("to".into()))),
+            commands.parse("help to")
+        );
+    }
+
+    #[test]
+    fn parse_failure_is_explained() {
+        let commands = Commands::default();
+        assert_eq!(
+            Err(CommandError::Unknown {
+                name: "qiut".into(),
+                similar: Some("quit")
+            }),
+            commands.parse("qiut")
+        );
+        assert_eq!(
+            "unknown command /dance, see /help for all commands",
+            commands.parse("dance").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "missing <username>, usage: /to <username>",
+            commands.parse("to").unwrap_err().to_string()
+        );
+        assert_eq!(
+            "unexpected argument now, usage: /quit",
+            commands.parse("quit now").unwrap_err().to_string()
+        );
+        assert!(commands.parse("help dance").is_err());
+    }
+
+    #[test]
+    fn help_lists_usages() {
+        let commands = Commands::default();
+        let help = commands.help(None);
+        for usage in [
+            "/to <username>",
+            "/switch [conversation]",
+            "/search <text>",
+            "/who",
+            "/help [command]",
+            "/quit",
+        ] {
+            assert!(help.contains(usage), "{usage} missing in {help}");
+        }
+        assert!(!commands.help(Some("to")).contains("/who"));
+    }
+
+    #[test]
+    fn complete_commands_and_usernames() {
+        let commands = Commands::default();
+        let online = vec!["alice".to_string(), "albert".to_string(), "bob".to_string()];
+        assert_eq!(
+            (1, vec!["who".to_string()]),
+            commands.complete("/w", &online)
+        );
+        assert_eq!(
+            (1, vec!["to ".to_string()]),
+            commands.complete("/t", &online)
+        );
+        assert_eq!(
+            (1, vec!["bob ".to_string()]),
+            commands.complete("@b", &online)
+        );
+        assert_eq!((0, vec![]), commands.complete("@bob hi", &online));
+        assert_eq!(
+            (4, vec!["alice".to_string(), "albert".to_string()]),
+            commands.complete("/to al", &online)
+        );
+        assert_eq!(
+            (4, vec!["bob".to_string()]),
+            commands.complete("/to:b", &online)
+        );
+        assert_eq!(
+            (6, vec!["quit".to_string()]),
+            commands.complete("/help q", &online)
+        );
+        assert_eq!((0, vec![]), commands.complete("/to bob a", &online));
+        assert_eq!((0, vec![]), commands.complete("hello", &online));
+    }
+}
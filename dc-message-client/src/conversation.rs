@@ -0,0 +1,199 @@
+//! the conversations of the terminal client, the same for the lines and the full screen
+
+/// a line in the scrollback
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Entry {
+    /// a text message, sent by this user or the peer, or by anyone in a room
+    Text { sender: String, body: String },
+    /// told by the client, like the users online
+    Info(String),
+    /// sent by the server itself, like an announcement
+    Notice(String),
+    /// the server reported what the client did wrong
+    Error(String),
+}
+
+/// the messages with a peer, or in a room
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Conversation {
+    /// [`None`] for the server, shown before talking with anyone
+    pub peer: Option<String>,
+    /// kept by the full screen only
+    pub entries: Vec<Entry>,
+    /// messages received since it is looked at
+    pub unread: usize,
+}
+
+impl Conversation {
+    fn new(peer: Option<String>) -> Self {
+        Conversation {
+            peer,
+            entries: Vec::new(),
+            unread: 0,
+        }
+    }
+
+    /// name shown in the list
+    pub fn name(&self) -> &str {
+        self.peer.as_deref().unwrap_or("server")
+    }
+}
+
+/// the conversations, the server first and then the peers in the order they are opened
+///
+/// the peers are numbered from 1 by this order, the texts are sent to the peer of the active one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Conversations {
+    conversations: Vec<Conversation>,
+    /// index of the active conversation
+    active: usize,
+}
+
+impl Default for Conversations {
+    fn default() -> Self {
+        Conversations {
+            conversations: vec![Conversation::new(None)],
+            active: 0,
+        }
+    }
+}
+
+impl Conversations {
+    /// index of the conversation with the peer, opened if there is none
+    pub fn open(&mut self, peer: &str) -> usize {
+        match self
+            .conversations
+            .iter()
+            .position(|conversation| conversation.peer.as_deref() == Some(peer))
+        {
+            Some(index) => index,
+            None => {
+                self.conversations
+                    .push(Conversation::new(Some(peer.to_string())));
+                self.conversations.len() - 1
+            }
+        }
+    }
+
+    /// look at the conversation, and send the following texts to its peer
+    pub fn select(&mut self, index: usize) {
+        self.active = index;
+        self.conversations[index].unread = 0;
+    }
+
+    /// open the conversation with the peer, and select it
+    pub fn activate(&mut self, peer: &str) {
+        let index = self.open(peer);
+        self.select(index);
+    }
+
+    pub fn active(&self) -> &Conversation {
+        &self.conversations[self.active]
+    }
+
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    /// the peer the texts are sent to, [`None`] while the server is active
+    pub fn receiver(&self) -> Option<&str> {
+        self.active().peer.as_deref()
+    }
+
+    /// add the entry to the conversation, counted as unread unless it is active
+    pub fn push(&mut self, index: usize, entry: Entry) {
+        if index != self.active {
+            self.conversations[index].unread += 1;
+        }
+        self.push_read(index, entry);
+    }
+
+    /// add the entry to the conversation, never counted as unread, like a message kept before
+    pub fn push_read(&mut self, index: usize, entry: Entry) {
+        self.conversations[index].entries.push(entry);
+    }
+
+    /// the peer of the conversation, by its name or number from 1
+    pub fn find(&self, conversation: &str) -> Option<&str> {
+        let by_number = conversation
+            .parse::<usize>()
+            .ok()
+            .filter(|number| *number > 0)
+            .and_then(|number| self.conversations.get(number));
+        by_number
+            .or_else(|| {
+                self.conversations
+                    .iter()
+                    .find(|opened| opened.peer.as_deref() == Some(conversation))
+            })
+            .and_then(|found| found.peer.as_deref())
+    }
+
+    /// the conversations numbered, the active one marked
+    pub fn list(&self) -> String {
+        if self.conversations.len() == 1 {
+            return "No conversation yet, start one by /to <username>".to_string();
+        }
+        let mut list = String::from("Conversations:");
+        for (i, conversation) in self.conversations.iter().enumerate().skip(1) {
+            let mark = if self.active == i { " *" } else { "" };
+            list += &format!("\n  {i}. {}{mark}", conversation.name());
+        }
+        list
+    }
+
+    /// the server first, and then the peers
+    pub fn iter(&self) -> impl Iterator<Item = &Conversation> {
+        self.conversations.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.conversations.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_and_switch_conversations() {
+        let mut conversations = Conversations::default();
+        assert_eq!(None, conversations.receiver());
+        assert!(conversations.list().starts_with("No conversation"));
+
+        conversations.open("alice");
+        conversations.activate("bob");
+        conversations.open("alice");
+        assert_eq!(Some("bob"), conversations.receiver());
+        assert_eq!(
+            "Conversations:\n  1. alice\n  2. bob *",
+            conversations.list()
+        );
+
+        assert_eq!(Some("alice"), conversations.find("1"));
+        assert_eq!(Some("bob"), conversations.find("bob"));
+        assert_eq!(None, conversations.find("3"));
+        assert_eq!(None, conversations.find("0"));
+        assert_eq!(None, conversations.find("carol"));
+    }
+
+    #[test]
+    fn unread_until_selected() {
+        let mut conversations = Conversations::default();
+        let alice = conversations.open("alice");
+        conversations.push(alice, Entry::Info("hi".to_string()));
+        conversations.push(0, Entry::Info("welcome".to_string()));
+        let unread = |conversations: &Conversations| -> Vec<usize> {
+            conversations
+                .iter()
+                .map(|conversation| conversation.unread)
+                .collect()
+        };
+        assert_eq!([0, 1], unread(&conversations)[..]);
+
+        conversations.select(alice);
+        assert_eq!([0, 0], unread(&conversations)[..]);
+        assert_eq!(1, conversations.active().entries.len());
+    }
+}
@@ -0,0 +1,682 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use dvorak_message::message::{Message, MessageReader, MessageType, SERVER_NAME};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::{mpsc, oneshot},
+    time::timeout,
+};
+use tracing::Instrument;
+
+use crate::event::{Event, Events};
+
+/// how many commands and events could be queued
+const CHANNEL_CAPACITY: usize = 100;
+
+/// how many messages could wait for the connection while reconnecting,
+/// the oldest ones are dropped beyond it
+const MAX_PENDING_MESSAGES: usize = 1000;
+
+/// how long the server has to accept the login
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// who the client logs in as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+}
+
+impl Credentials {
+    pub fn new(username: impl Into<String>) -> Self {
+        Credentials {
+            username: username.into(),
+        }
+    }
+}
+
+impl From<&str> for Credentials {
+    fn from(username: &str) -> Self {
+        Credentials::new(username)
+    }
+}
+
+impl From<String> for Credentials {
+    fn from(username: String) -> Self {
+        Credentials::new(username)
+    }
+}
+
+/// how the client reconnects once the connection is lost
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// how long to wait before every attempt
+    pub interval: Duration,
+    /// give up after so many failed attempts, [`None`] to never give up
+    pub max_attempts: Option<usize>,
+}
+
+impl ReconnectPolicy {
+    /// never reconnect, the events end once the connection is lost
+    pub fn never() -> Self {
+        ReconnectPolicy {
+            interval: Duration::ZERO,
+            max_attempts: Some(0),
+        }
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            interval: Duration::from_secs(1),
+            max_attempts: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Send(Message),
+    Subscribe,
+    /// logout, replied once the connection is closed
+    Close(oneshot::Sender<()>),
+}
+
+/// a client of Dvorak Message, for bots and other integrations
+///
+/// the connection is served in its own task, which logs in again once reconnected.
+/// the handle could be cloned, the client is closed once every handle is dropped
+///
+/// # example
+/// ```no_run
+/// use dc_message_client::{DcClient, Event};
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let (client, mut events) = DcClient::connect("127.0.0.1:8233", "echo").await?;
+/// while let Some(event) = events.recv().await {
+///     if let Event::Message { from, body, .. } = event {
+///         client.send_text(&from, &body).await?;
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DcClient {
+    username: String,
+    commands: mpsc::Sender<Command>,
+    /// users told online by the presence
+    online: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl DcClient {
+    /// connect to the server at `addr` and login, reconnect by [`ReconnectPolicy::default`]
+    pub async fn connect(
+        addr: &str,
+        credentials: impl Into<Credentials>,
+    ) -> io::Result<(DcClient, Events)> {
+        Self::connect_with(addr, credentials, ReconnectPolicy::default()).await
+    }
+
+    /// connect to the server at `addr` and login, reconnect by `reconnect`
+    ///
+    /// it returns once the server accepted the login,
+    /// a login refused fails with [`io::ErrorKind::PermissionDenied`] and the reason
+    pub async fn connect_with(
+        addr: &str,
+        credentials: impl Into<Credentials>,
+        reconnect: ReconnectPolicy,
+    ) -> io::Result<(DcClient, Events)> {
+        let credentials = credentials.into();
+        let connected = match login(addr, &credentials.username).await {
+            Ok(connected) => connected,
+            Err(LoginFailure::Refused(reason)) => {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, reason))
+            }
+            Err(LoginFailure::Lost(e)) => return Err(e),
+        };
+
+        let (commands, commands_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (events, events_rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let online = Arc::new(Mutex::new(BTreeSet::new()));
+        let worker = Worker {
+            addr: addr.to_string(),
+            username: credentials.username.clone(),
+            reconnect,
+            commands: commands_rx,
+            events,
+            online: online.clone(),
+            subscribed: false,
+            pending: VecDeque::new(),
+        };
+        let span = tracing::info_span!("client", user = %credentials.username);
+        tokio::spawn(worker.run(connected).instrument(span));
+
+        let client = DcClient {
+            username: credentials.username,
+            commands,
+            online,
+        };
+        Ok((client, Events::new(events_rx)))
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// send the text to the user, it is queued while reconnecting,
+    /// the oldest ones queued are dropped if too many are waiting
+    pub async fn send_text(&self, to: &str, body: &str) -> io::Result<()> {
+        let text = MessageType::Text(body.to_string());
+        let message = Message::new(text, self.username.clone(), to.to_string());
+        self.command(Command::Send(message)).await
+    }
+
+    /// be told who is online now, and who comes or goes since then,
+    /// by [`Event::Online`] and [`Event::Offline`]
+    pub async fn subscribe_presence(&self) -> io::Result<()> {
+        self.command(Command::Subscribe).await
+    }
+
+    /// the users online, known since the presence is subscribed
+    pub fn online_users(&self) -> Vec<String> {
+        self.online.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// logout, and wait until the connection is closed
+    pub async fn close(&self) {
+        let (reply, closed) = oneshot::channel();
+        if self.command(Command::Close(reply)).await.is_ok() {
+            let _ = closed.await;
+        }
+    }
+
+    async fn command(&self, command: Command) -> io::Result<()> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::NotConnected, "client closed"))
+    }
+}
+
+/// the connection once the login is accepted
+struct Connected {
+    reader: MessageReader<OwnedReadHalf>,
+    write_half: OwnedWriteHalf,
+}
+
+/// why a login failed
+enum LoginFailure {
+    /// the server refused it, trying again is no use
+    Refused(String),
+    Lost(io::Error),
+}
+
+/// connect to the server at `addr`, and login until the server accepts it
+///
+/// the server accepts the login by a Login message of its own,
+/// or closes the connection after telling why it refused
+async fn login(addr: &str, username: &str) -> Result<Connected, LoginFailure> {
+    let stream = TcpStream::connect(addr).await.map_err(LoginFailure::Lost)?;
+    let (read_half, mut write_half) = stream.into_split();
+    let mut reader = MessageReader::new(read_half);
+    let login = Message::new(MessageType::Login, username.to_string(), String::new());
+    Message::send(&mut write_half, login)
+        .await
+        .map_err(|e| LoginFailure::Lost(io::Error::other(e.to_string())))?;
+
+    let accepted = async {
+        let mut reason = None;
+        loop {
+            let message = match reader.read().await {
+                Ok(Some(message)) => message,
+                Ok(None) => {
+                    return Err(match reason {
+                        Some(reason) => LoginFailure::Refused(reason),
+                        None => LoginFailure::Lost(io::ErrorKind::UnexpectedEof.into()),
+                    })
+                }
+                Err(e) => return Err(LoginFailure::Lost(io::Error::other(e.to_string()))),
+            };
+            match message.message_type {
+                MessageType::Login if message.username == SERVER_NAME => return Ok(()),
+                MessageType::Text(notice) if message.username == SERVER_NAME => {
+                    reason = Some(notice)
+                }
+                MessageType::Error(report) => reason = Some(report),
+                _ => {}
+            }
+        }
+    };
+    match timeout(LOGIN_TIMEOUT, accepted).await {
+        Ok(Ok(())) => Ok(Connected { reader, write_half }),
+        Ok(Err(failure)) => Err(failure),
+        Err(_) => Err(LoginFailure::Lost(io::ErrorKind::TimedOut.into())),
+    }
+}
+
+/// why a connection is not served any more
+enum Ended {
+    Closed,
+    Lost,
+    /// logged out by the server, with the reason
+    LoggedOut(String),
+}
+
+/// the task serving the connection of a [`DcClient`]
+struct Worker {
+    addr: String,
+    username: String,
+    reconnect: ReconnectPolicy,
+    commands: mpsc::Receiver<Command>,
+    events: mpsc::Sender<Event>,
+    online: Arc<Mutex<BTreeSet<String>>>,
+    /// subscribe the presence again once reconnected
+    subscribed: bool,
+    /// messages waiting for the connection
+    pending: VecDeque<Message>,
+}
+
+impl Worker {
+    async fn run(mut self, mut connected: Connected) {
+        loop {
+            match self.serve(connected).await {
+                Ended::Closed => return,
+                Ended::LoggedOut(reason) => {
+                    tracing::info!(%reason, "logged out by the server");
+                    self.online.lock().unwrap().clear();
+                    let _ = self.events.send(Event::LoggedOut(reason)).await;
+                    return;
+                }
+                Ended::Lost => {}
+            }
+            tracing::info!("connection lost");
+            self.online.lock().unwrap().clear();
+            let _ = self.events.send(Event::Disconnected).await;
+
+            connected = match self.reconnect().await {
+                Ok(connected) => connected,
+                Err(Some(reason)) => {
+                    tracing::warn!(%reason, "login refused");
+                    let _ = self.events.send(Event::LoggedOut(reason)).await;
+                    return;
+                }
+                Err(None) => return,
+            };
+            tracing::info!("reconnected");
+            let _ = self.events.send(Event::Reconnected).await;
+        }
+    }
+
+    /// pass frames and commands until the connection is lost or closed
+    async fn serve(&mut self, connected: Connected) -> Ended {
+        let Connected {
+            mut reader,
+            mut write_half,
+        } = connected;
+
+        if self.subscribed
+            && Message::send(&mut write_half, self.presence())
+                .await
+                .is_err()
+        {
+            return Ended::Lost;
+        }
+        while let Some(message) = self.pending.pop_front() {
+            if let Err(message) = Self::send(&mut write_half, message).await {
+                self.pending.push_front(message);
+                return Ended::Lost;
+            }
+        }
+
+        // the last notice of the server, the reason if it logs the user out
+        let mut notice = String::new();
+        loop {
+            tokio::select! {
+                incoming = reader.read() => match incoming {
+                    Ok(Some(message)) if message.username == SERVER_NAME => match message.message_type {
+                        MessageType::Logout => return Ended::LoggedOut(notice),
+                        MessageType::Text(ref body) | MessageType::Error(ref body) => {
+                            notice.clone_from(body);
+                            self.receive(message).await;
+                        }
+                        _ => self.receive(message).await,
+                    },
+                    Ok(Some(message)) => self.receive(message).await,
+                    Ok(None) => return Ended::Lost,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "read message failure");
+                        return Ended::Lost;
+                    }
+                },
+                command = self.commands.recv() => {
+                    let message = match command {
+                        Some(Command::Send(message)) => message,
+                        Some(Command::Subscribe) => {
+                            self.subscribed = true;
+                            self.presence()
+                        }
+                        Some(Command::Close(closed)) => {
+                            self.logout(&mut write_half).await;
+                            let _ = closed.send(());
+                            return Ended::Closed;
+                        }
+                        None => {
+                            self.logout(&mut write_half).await;
+                            return Ended::Closed;
+                        }
+                    };
+                    if let Err(message) = Self::send(&mut write_half, message).await {
+                        self.queue(message);
+                        return Ended::Lost;
+                    }
+                }
+            }
+        }
+    }
+
+    /// dial the server and login again by the policy,
+    /// the reason if the login is refused, [`None`] if gave up or closed meanwhile
+    async fn reconnect(&mut self) -> Result<Connected, Option<String>> {
+        let mut attempts = 0;
+        while self.reconnect.max_attempts.is_none_or(|max| attempts < max) {
+            attempts += 1;
+            let wait = tokio::time::sleep(self.reconnect.interval);
+            tokio::pin!(wait);
+            // keep the messages sent meanwhile
+            loop {
+                tokio::select! {
+                    _ = &mut wait => break,
+                    command = self.commands.recv() => match command {
+                        Some(Command::Send(message)) => self.queue(message),
+                        Some(Command::Subscribe) => self.subscribed = true,
+                        Some(Command::Close(closed)) => {
+                            let _ = closed.send(());
+                            return Err(None);
+                        }
+                        None => return Err(None),
+                    },
+                }
+            }
+
+            match login(&self.addr, &self.username).await {
+                Ok(connected) => return Ok(connected),
+                Err(LoginFailure::Refused(reason)) => return Err(Some(reason)),
+                Err(LoginFailure::Lost(e)) => {
+                    tracing::debug!(error = %e, attempts, "reconnect failure")
+                }
+            }
+        }
+        tracing::warn!(attempts, "give up reconnecting");
+        Err(None)
+    }
+
+    /// keep the message until reconnected, the oldest one is dropped if too many are waiting
+    fn queue(&mut self, message: Message) {
+        if self.pending.len() >= MAX_PENDING_MESSAGES {
+            self.pending.pop_front();
+            tracing::warn!("too many messages waiting for the connection, the oldest dropped");
+        }
+        self.pending.push_back(message);
+    }
+
+    /// send the message, give it back if failed,
+    /// a message which could never be encoded is dropped instead
+    async fn send(write_half: &mut OwnedWriteHalf, message: Message) -> Result<(), Message> {
+        let frame = match message.to_bytes() {
+            Ok(frame) => frame,
+            Err(e) => {
+                tracing::warn!(error = %e, "message dropped");
+                return Ok(());
+            }
+        };
+        match write_half.write_all(&frame).await {
+            Ok(()) => Ok(()),
+            Err(_) => Err(message),
+        }
+    }
+
+    async fn logout(&self, write_half: &mut OwnedWriteHalf) {
+        let logout = Message::new(MessageType::Logout, self.username.clone(), String::new());
+        let _ = Message::send(write_half, logout).await;
+        let _ = write_half.shutdown().await;
+    }
+
+    fn presence(&self) -> Message {
+        Message::new(MessageType::Presence, self.username.clone(), String::new())
+    }
+
+    /// turn the frame into an event, the events are dropped if nobody listens
+    async fn receive(&mut self, message: Message) {
+        let event = match message.message_type {
+            MessageType::Text(body) if message.username == SERVER_NAME => Event::Notice(body),
+            MessageType::Text(body) => Event::Message {
+                from: message.username,
+                to: message.receiver,
+                body,
+            },
+            MessageType::Error(report) => Event::Error(report),
+            MessageType::Login => {
+                self.online.lock().unwrap().insert(message.username.clone());
+                Event::Online(message.username)
+            }
+            MessageType::Logout => {
+                self.online.lock().unwrap().remove(&message.username);
+                Event::Offline(message.username)
+            }
+            MessageType::Heart | MessageType::Presence => return,
+        };
+        let _ = self.events.send(event).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// accept the client, read its Login message and accept the login
+    async fn accept(listener: &TcpListener) -> MessageReader<TcpStream> {
+        let mut server = accept_connection(listener).await;
+        let accepted = Message::new(
+            MessageType::Login,
+            SERVER_NAME.to_string(),
+            "alice".to_string(),
+        );
+        Message::send(server.get_mut(), accepted).await.unwrap();
+        server
+    }
+
+    /// accept the client, and read its Login message
+    async fn accept_connection(listener: &TcpListener) -> MessageReader<TcpStream> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = MessageReader::new(stream);
+        let login = server.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Login, login.message_type);
+        assert_eq!("alice", login.username);
+        server
+    }
+
+    /// tell the client why, and close the connection
+    async fn refuse(mut server: MessageReader<TcpStream>, reason: &str) {
+        let notice = MessageType::Error(reason.to_string());
+        let message = Message::new(notice, SERVER_NAME.to_string(), "alice".to_string());
+        Message::send(server.get_mut(), message).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn texts_and_presence() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (connected, mut server) =
+            tokio::join!(DcClient::connect(&addr, "alice"), accept(&listener));
+        let (client, mut events) = connected.unwrap();
+
+        client.send_text("bob", "hi").await.unwrap();
+        let message = server.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Text("hi".to_string()), message.message_type);
+        assert_eq!("bob", message.receiver);
+
+        client.subscribe_presence().await.unwrap();
+        let message = server.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Presence, message.message_type);
+
+        let frames = [
+            (MessageType::Login, "bob"),
+            (MessageType::Login, "carol"),
+            (MessageType::Logout, "carol"),
+            (MessageType::Text("hello".to_string()), "bob"),
+            (
+                MessageType::Text("restarting soon".to_string()),
+                SERVER_NAME,
+            ),
+        ];
+        for (message_type, username) in frames {
+            let message = Message::new(message_type, username.to_string(), "alice".to_string());
+            Message::send(server.get_mut(), message).await.unwrap();
+        }
+        assert_eq!(Some(Event::Online("bob".to_string())), events.recv().await);
+        assert_eq!(
+            Some(Event::Online("carol".to_string())),
+            events.recv().await
+        );
+        assert_eq!(
+            Some(Event::Offline("carol".to_string())),
+            events.recv().await
+        );
+        let message = Event::Message {
+            from: "bob".to_string(),
+            to: "alice".to_string(),
+            body: "hello".to_string(),
+        };
+        assert_eq!(Some(message), events.recv().await);
+        assert_eq!(
+            Some(Event::Notice("restarting soon".to_string())),
+            events.recv().await
+        );
+        assert_eq!(vec!["bob".to_string()], client.online_users());
+
+        client.close().await;
+        let logout = server.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Logout, logout.message_type);
+        assert_eq!(None, events.recv().await);
+        assert!(client.send_text("bob", "bye").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reconnect_and_login_again() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let reconnect = ReconnectPolicy {
+            interval: Duration::from_millis(10),
+            max_attempts: Some(3),
+        };
+        let (connected, server) = tokio::join!(
+            DcClient::connect_with(&addr, "alice", reconnect),
+            accept(&listener)
+        );
+        let (client, mut events) = connected.unwrap();
+        client.subscribe_presence().await.unwrap();
+
+        drop(server);
+        assert_eq!(Some(Event::Disconnected), events.recv().await);
+        client.send_text("bob", "missed you").await.unwrap();
+
+        let mut server = accept(&listener).await;
+        assert_eq!(Some(Event::Reconnected), events.recv().await);
+        // subscribed again, and the text queued meanwhile is sent
+        let message = server.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Presence, message.message_type);
+        let message = server.read().await.unwrap().unwrap();
+        assert_eq!(
+            MessageType::Text("missed you".to_string()),
+            message.message_type
+        );
+
+        // give up once the server is gone
+        drop(server);
+        drop(listener);
+        assert_eq!(Some(Event::Disconnected), events.recv().await);
+        assert_eq!(None, events.recv().await);
+    }
+
+    #[tokio::test]
+    async fn refused_login_is_never_retried() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let reconnect = ReconnectPolicy {
+            interval: Duration::from_millis(10),
+            max_attempts: None,
+        };
+
+        let refused = async {
+            let server = accept_connection(&listener).await;
+            refuse(server, "banned").await;
+        };
+        let (connected, ()) =
+            tokio::join!(DcClient::connect_with(&addr, "alice", reconnect), refused);
+        let error = connected.unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, error.kind());
+        assert_eq!("banned", error.to_string());
+
+        // refused once reconnected
+        let (connected, server) = tokio::join!(
+            DcClient::connect_with(&addr, "alice", reconnect),
+            accept(&listener)
+        );
+        let (_client, mut events) = connected.unwrap();
+        drop(server);
+        assert_eq!(Some(Event::Disconnected), events.recv().await);
+        let server = accept_connection(&listener).await;
+        refuse(server, "banned").await;
+        assert_eq!(
+            Some(Event::LoggedOut("banned".to_string())),
+            events.recv().await
+        );
+        assert_eq!(None, events.recv().await);
+    }
+
+    #[tokio::test]
+    async fn logged_out_by_server_is_never_retried() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let reconnect = ReconnectPolicy {
+            interval: Duration::from_millis(10),
+            max_attempts: None,
+        };
+        let (connected, mut server) = tokio::join!(
+            DcClient::connect_with(&addr, "alice", reconnect),
+            accept(&listener)
+        );
+        let (client, mut events) = connected.unwrap();
+
+        let reason = "logged in again elsewhere";
+        let notice = MessageType::Text(reason.to_string());
+        let message = Message::new(notice, SERVER_NAME.to_string(), "alice".to_string());
+        Message::send(server.get_mut(), message).await.unwrap();
+        let logout = Message::new(
+            MessageType::Logout,
+            SERVER_NAME.to_string(),
+            "alice".to_string(),
+        );
+        Message::send(server.get_mut(), logout).await.unwrap();
+
+        assert_eq!(Some(Event::Notice(reason.to_string())), events.recv().await);
+        assert_eq!(
+            Some(Event::LoggedOut(reason.to_string())),
+            events.recv().await
+        );
+        assert_eq!(None, events.recv().await);
+        assert!(client.send_text("bob", "hi").await.is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use tokio::sync::mpsc::Receiver;
+
+/// something happened to the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// a text message sent by a user
+    Message {
+        from: String,
+        /// this user for a direct message, otherwise the room it is sent to
+        to: String,
+        body: String,
+    },
+    /// a text sent by the server itself, like an announcement or why the user is kicked
+    Notice(String),
+    /// the server reported what the client did wrong, or why it is refused
+    Error(String),
+    /// a user comes, told once the presence is subscribed
+    Online(String),
+    /// a user goes, told once the presence is subscribed
+    Offline(String),
+    /// the connection is lost, the client is reconnecting
+    Disconnected,
+    /// the client is connected again, and logs in as before
+    Reconnected,
+    /// the server refused the login, or logged the user out, with the reason.
+    /// the client does not reconnect, the events end after it
+    LoggedOut(String),
+}
+
+/// the events of a [`DcClient`], ended once the client is closed or gave up reconnecting
+///
+/// [`DcClient`]: crate::DcClient
+#[derive(Debug)]
+pub struct Events {
+    receiver: Receiver<Event>,
+}
+
+impl Events {
+    pub(crate) fn new(receiver: Receiver<Event>) -> Self {
+        Events { receiver }
+    }
+
+    /// the next event, [`None`] if the client is closed
+    pub async fn recv(&mut self) -> Option<Event> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for Events {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
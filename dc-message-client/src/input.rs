@@ -1,89 +1,184 @@
-use std::sync::Arc;
+use std::{sync::Arc, thread};
 
-use super::client::{ClientMessage, ClientSender};
-use tokio::io::{self, AsyncBufReadExt, BufReader};
+use dc_message_client::DcClient;
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::DefaultHistory, validate::Validator, Editor, Helper,
+};
+
+use super::{
+    client::{ClientMessage, ClientSender},
+    command::{Command, CommandError, Commands},
+};
 
 ///  the Actor that listen text input
 pub(crate) struct Input {
     client_sender: ClientSender,
+    commands: Arc<Commands>,
+    /// for completing the usernames online
+    dc_client: DcClient,
 }
 
 impl Input {
-    pub fn new(client_sender: ClientSender) -> Self {
-        Input { client_sender }
+    pub fn new(client_sender: ClientSender, commands: Arc<Commands>, dc_client: DcClient) -> Self {
+        Input {
+            client_sender,
+            commands,
+            dc_client,
+        }
     }
 
     /// listen input from terminal,
-    /// running into spread thread
+    /// running into spread thread, since editing the line blocks
     pub async fn listen(&self) {
         let client_sender = Arc::clone(&self.client_sender);
-        let mut stdin = BufReader::new(io::stdin()).lines();
+        let commands = Arc::clone(&self.commands);
+        let completion = Completion {
+            commands: Arc::clone(&commands),
+            dc_client: self.dc_client.clone(),
+        };
+
+        thread::spawn(move || {
+            let mut editor = match Editor::<Completion, DefaultHistory>::new() {
+                Ok(editor) => editor,
+                Err(e) => {
+                    eprintln!("open terminal failure: {e}");
+                    let _ = client_sender.blocking_send(ClientMessage::Command(Command::Quit));
+                    return;
+                }
+            };
+            editor.set_helper(Some(completion));
 
-        tokio::spawn(async move {
             loop {
-                if let Some(line) = stdin.next_line().await.unwrap() {
-                    if let Ok(input) = InputType::parse(&line) {
-                        match input {
-                            InputType::Text(data) => {
-                                client_sender.send(ClientMessage::Text(data)).await.unwrap();
-                            }
-                            InputType::Instruct(instruct) => match instruct {
-                                Instruct::Quit => {
-                                    client_sender.send(ClientMessage::Quit).await.unwrap();
-                                    break;
-                                }
-                                Instruct::To(username) => {
-                                    client_sender
-                                        .send(ClientMessage::To(username))
-                                        .await
-                                        .unwrap();
-                                }
-                            },
-                        }
+                let line = match editor.readline("") {
+                    Ok(line) => line,
+                    // ctrl-c or ctrl-d
+                    Err(ReadlineError::Interrupted | ReadlineError::Eof) => {
+                        let _ = client_sender.blocking_send(ClientMessage::Command(Command::Quit));
+                        break;
+                    }
+                    Err(e) => {
+                        tracing::warn!(%e, "read line failure");
+                        let _ = client_sender.blocking_send(ClientMessage::Command(Command::Quit));
+                        break;
                     }
+                };
+                let _ = editor.add_history_entry(line.as_str());
+
+                let message = match InputType::parse(&line, &commands) {
+                    Ok(InputType::Text(data)) => ClientMessage::Text(data),
+                    Ok(InputType::Addressed { to, text }) => ClientMessage::Addressed { to, text },
+                    Ok(InputType::Command(Command::Quit)) => {
+                        let _ = client_sender.blocking_send(ClientMessage::Command(Command::Quit));
+                        break;
+                    }
+                    Ok(InputType::Command(command)) => ClientMessage::Command(command),
+                    Err(e) => {
+                        println!("{e}");
+                        continue;
+                    }
+                };
+                if client_sender.blocking_send(message).is_err() {
+                    break;
                 }
             }
         });
     }
-}
 
-/// the input line from IO
-pub(crate) enum InputType {
-    /// pure text, like message to client else
-    Text(String),
-    /// some special input, representing some special ability
-    Instruct(Instruct),
+    /// listen the keys pressed for the full screen,
+    /// running into spread thread, since reading the terminal blocks
+    pub async fn listen_keys(&self) {
+        let client_sender = Arc::clone(&self.client_sender);
+
+        thread::spawn(move || loop {
+            let message = match event::read() {
+                // the releases are reported on Windows
+                Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => ClientMessage::Key(key),
+                Ok(Event::Resize(..)) => ClientMessage::Resize,
+                Ok(_) => continue,
+                Err(e) => {
+                    tracing::warn!(%e, "read terminal failure");
+                    let _ = client_sender.blocking_send(ClientMessage::Command(Command::Quit));
+                    break;
+                }
+            };
+            if client_sender.blocking_send(message).is_err() {
+                break;
+            }
+        });
+    }
 }
 
-pub(crate) enum Instruct {
-    To(String),
-    Quit,
+/// complete the commands and the usernames online by tab
+struct Completion {
+    commands: Arc<Commands>,
+    dc_client: DcClient,
 }
 
-impl Instruct {
-    fn new(text: &str) -> Result<Instruct, String> {
-        match text {
-            "quit" => Ok(Instruct::Quit),
-            t if t.starts_with("to:") => {
-                let username = t[3..].trim();
-                Ok(Instruct::To(username.to_string()))
-            }
-            _ => Err(format!("unknow text: {text}")),
-        }
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let online = self.dc_client.online_users();
+        Ok(self.commands.complete(&line[..pos], &online))
     }
 }
 
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+/// the input line from IO
+pub(crate) enum InputType {
+    /// pure text, like message to client else
+    Text(String),
+    /// text sent to the user named inline, like `@alice hello`
+    Addressed { to: String, text: String },
+    /// a slash command, representing some special ability
+    Command(Command),
+}
+
 impl InputType {
     /// parse line into Input
-    pub fn parse(line: &str) -> Result<Self, String> {
+    pub fn parse(line: &str, commands: &Commands) -> Result<Self, CommandError> {
         let line = line.trim();
-        //  if input line start with '/' and not '//' there will be Instruct
+        //  if input line start with '/' and not '//' there will be a command
         if line.starts_with("/") && !line.starts_with("//") {
-            let line = &line[1..];
-            let instruct = Instruct::new(line)?;
-            Ok(InputType::Instruct(instruct))
+            let command = commands.parse(&line[1..])?;
+            Ok(InputType::Command(command))
+        } else if let Some(addressed) = line.strip_prefix('@').filter(|_| !line.starts_with("@@")) {
+            let (to, text) = addressed
+                .split_once(char::is_whitespace)
+                .unwrap_or((addressed, ""));
+            let usage_error = |reason: &str| CommandError::Usage {
+                usage: "@<username> <message>".to_string(),
+                reason: reason.to_string(),
+            };
+            // the length of username is a byte in the frame
+            if to.is_empty() || to.len() > u8::MAX as usize {
+                return Err(usage_error("invalid <username>"));
+            }
+            match text.trim() {
+                "" => Err(usage_error("missing <message>")),
+                text => Ok(InputType::Addressed {
+                    to: to.to_string(),
+                    text: text.to_string(),
+                }),
+            }
         } else {
-            let text_line = if line.starts_with("//") {
+            let text_line = if line.starts_with("//") || line.starts_with("@@") {
                 &line[1..]
             } else {
                 line
@@ -93,3 +188,32 @@ impl InputType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<InputType, CommandError> {
+        InputType::parse(line, &Commands::default())
+    }
+
+    #[test]
+    fn parse_texts_commands_and_addressed() {
+        assert!(matches!(parse("hello"), Ok(InputType::Text(text)) if text == "hello"));
+        assert!(matches!(parse("//slash"), Ok(InputType::Text(text)) if text == "/slash"));
+        assert!(matches!(parse("@@at"), Ok(InputType::Text(text)) if text == "@at"));
+        assert!(matches!(
+            parse("/who"),
+            Ok(InputType::Command(Command::Who))
+        ));
+        assert!(matches!(
+            parse("@alice how are you"),
+            Ok(InputType::Addressed { to, text }) if to == "alice" && text == "how are you"
+        ));
+        assert_eq!(
+            "missing <message>, usage: @<username> <message>",
+            parse("@alice").err().unwrap().to_string()
+        );
+        assert!(parse("@ hi").is_err());
+    }
+}
@@ -0,0 +1,12 @@
+//! the client of Dvorak Message
+//!
+//! it runs as the `dc_message_client` binary in terminal,
+//! or as a library for bots and other integrations by [`DcClient`]
+
+mod dc_client;
+mod event;
+mod store;
+
+pub use dc_client::{Credentials, DcClient, ReconnectPolicy};
+pub use event::{Event, Events};
+pub use store::{Record, Store};
@@ -1,42 +1,113 @@
+use std::{io, path::PathBuf, sync::Arc};
+
 use client::Client;
-use tokio::net::TcpStream;
+use command::Commands;
+use screen::{Lines, Screen};
+use tui::Tui;
 
 use clap::Parser;
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
 
-use dvorak_message::message::{Message, MessageType};
+use dc_message_client::{DcClient, Store};
 
-mod input;
 mod client;
+mod command;
+mod conversation;
+mod input;
+mod screen;
+mod tui;
 
 #[derive(Parser, Debug)]
 struct Args {
     #[arg(short, long)]
     username: String,
+    /// address of the server
+    #[arg(short, long, default_value = "127.0.0.1:8233")]
+    server: String,
+    /// filter of logs written to stderr, a level or directives like `dc_message_client=debug`
+    #[arg(long, default_value = "warn")]
+    log_level: String,
+    /// take the whole terminal, with a pane for each conversation,
+    /// the logs are not written since they would break the screen
+    #[arg(long)]
+    tui: bool,
+    /// keep the messages sent and received in a local file encrypted by a passphrase,
+    /// read from `DC_STORE_PASSPHRASE`, or asked
+    #[arg(long)]
+    store: bool,
+    /// directory of the local stores, one for each server and username,
+    /// the data directory of the user by default
+    #[arg(long, value_name = "DIR")]
+    store_dir: Option<PathBuf>,
+}
+
+/// open the local store of the user on the server, asking the passphrase if it is not set
+fn open_store(arg: &Args) -> io::Result<Store> {
+    let dir = match &arg.store_dir {
+        Some(dir) => dir.clone(),
+        None => dirs::data_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory"))?
+            .join("dc-message"),
+    };
+    let passphrase = match std::env::var("DC_STORE_PASSPHRASE") {
+        Ok(passphrase) => passphrase,
+        Err(_) => rpassword::prompt_password("Passphrase of the local store: ")?,
+    };
+    Store::open(dir, &arg.server, &arg.username, &passphrase)
 }
 
 #[tokio::main]
 async fn main() {
     let arg = Args::parse();
-    let username = arg.username.clone();
 
-    //  连接服务器 ::8233
-    let mut stream = TcpStream::connect("127.0.0.1:8233").await.unwrap();
-    login(&mut stream, username.clone()).await;
+    if !arg.tui {
+        tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::new(&arg.log_level))
+            .with_writer(std::io::stderr)
+            .init();
+    }
 
-    let mut client = Client::new(username, stream);
+    let store = match arg.store.then(|| open_store(&arg)).transpose() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("open local store failure: {e}");
+            std::process::exit(1);
+        }
+    };
 
-    let handler = tokio::spawn(async move {
-        client.listen().await;
-    });
+    let (dc_client, events) = match DcClient::connect(&arg.server, arg.username.clone()).await {
+        Ok(connected) => connected,
+        Err(e) => {
+            eprintln!("connect to {} failure: {e}", arg.server);
+            std::process::exit(1);
+        }
+    };
 
-    handler.await.unwrap();
-}
+    let commands = Arc::new(Commands::default());
+    let screen: Box<dyn Screen> = if arg.tui {
+        let tui = Tui::new(
+            arg.username.clone(),
+            arg.server.clone(),
+            Arc::clone(&commands),
+        );
+        match tui {
+            Ok(tui) => Box::new(tui),
+            Err(e) => {
+                eprintln!("open terminal failure: {e}");
+                std::process::exit(1);
+            }
+        }
+    } else {
+        Box::new(Lines::default())
+    };
+    let mut client = Client::new(dc_client, events, screen, commands);
+    if let Some(store) = store {
+        client = client.with_store(store);
+    }
 
-async fn login(stream: &mut TcpStream, username: String) {
-    Message::send(
-        stream,
-        Message::new(MessageType::Login, username, String::new()),
-    )
-    .await
-    .unwrap();
+    let span = tracing::info_span!("terminal", user = %arg.username);
+    let handler = tokio::spawn(async move { client.listen().await }.instrument(span));
+
+    handler.await.unwrap();
 }
@@ -0,0 +1,107 @@
+use ratatui::crossterm::event::KeyEvent;
+
+use crate::{conversation::Conversations, input::InputType};
+
+/// the state of the connection to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConnectionState {
+    Connected,
+    /// the connection is lost, the client is reconnecting
+    Reconnecting,
+    /// the client gave up, or is closed
+    Disconnected,
+}
+
+/// where the terminal client shows what happened
+///
+/// the conversations are kept by the screen, a text or a conversation opens its conversation
+pub(crate) trait Screen: Send {
+    fn conversations(&mut self) -> &mut Conversations;
+
+    /// a text message from the user, sent to this user directly if `room` is [`None`]
+    fn message(&mut self, from: &str, room: Option<&str>, body: &str);
+
+    /// a text sent by the server itself, like an announcement
+    fn announce(&mut self, text: &str);
+
+    /// the server reported what the client did wrong
+    fn error(&mut self, text: &str);
+
+    /// a text message sent to the user by this client
+    fn sent(&mut self, to: &str, _body: &str) {
+        self.conversations().open(to);
+    }
+
+    /// a text message kept in the local store before, shown in its conversation,
+    /// the screen needs not be drawn for every one, a notice follows the whole history
+    fn history(&mut self, conversation: &str, _from: &str, _body: &str) {
+        self.conversations().open(conversation);
+    }
+
+    /// something told by the client, like the users online
+    fn notice(&mut self, text: &str);
+
+    /// the following texts are sent to the user
+    fn receiver(&mut self, username: &str) {
+        self.conversations().activate(username);
+        self.notice(&format!("Change receiver: {username}"));
+    }
+
+    /// a user comes or goes
+    fn presence(&mut self, _username: &str, _online: bool) {}
+
+    fn connection(&mut self, state: ConnectionState);
+
+    /// the keys are read for the screen, instead of the lines
+    fn full_screen(&self) -> bool {
+        false
+    }
+
+    /// a key pressed in the full screen, what is typed once it is submitted
+    fn key(&mut self, _key: KeyEvent) -> Option<InputType> {
+        None
+    }
+
+    /// the terminal is resized
+    fn resize(&mut self) {}
+}
+
+/// print everything line by line, under what is typed
+#[derive(Debug, Default)]
+pub(crate) struct Lines {
+    conversations: Conversations,
+}
+
+impl Screen for Lines {
+    fn conversations(&mut self) -> &mut Conversations {
+        &mut self.conversations
+    }
+
+    fn message(&mut self, from: &str, room: Option<&str>, body: &str) {
+        self.conversations.open(room.unwrap_or(from));
+        match room {
+            Some(room) => println!("[{room}] {from}: {body}"),
+            None => println!("{from}: {body}"),
+        }
+    }
+
+    fn announce(&mut self, text: &str) {
+        println!("[server] {text}");
+    }
+
+    fn error(&mut self, text: &str) {
+        println!("[error] {text}");
+    }
+
+    fn notice(&mut self, text: &str) {
+        println!("{text}");
+    }
+
+    fn connection(&mut self, state: ConnectionState) {
+        match state {
+            ConnectionState::Connected => println!("Reconnected"),
+            ConnectionState::Reconnecting => println!("Connection lost, reconnecting"),
+            ConnectionState::Disconnected => println!("Disconnected"),
+        }
+    }
+}
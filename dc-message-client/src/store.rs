@@ -0,0 +1,485 @@
+//! the messages sent and received, kept in a local file encrypted by a passphrase
+//!
+//! the file starts with a header, and the records are appended after it:
+//!
+//! |"DCS2"|salt(16)|nonce(12)|sealed check(32)|
+//! |record length(u32)|nonce(12)|sealed record as JSON| ...
+//!
+//! the key is derived from the passphrase and the salt by Argon2,
+//! and every record is sealed by AES-256-GCM,
+//! bound to the header and its index by the associated data
+//!
+//! the file is locked while it is open, so no two clients append to it
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use argon2::Argon2;
+use serde::{Deserialize, Serialize};
+
+const MAGIC: &[u8; 4] = b"DCS2";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// sealed in the header, to tell a wrong passphrase before reading any record
+const CHECK: &[u8; 16] = b"dvorak message!!";
+/// the check sealed with its tag
+const SEALED_CHECK_LEN: usize = CHECK.len() + TAG_LEN;
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + NONCE_LEN + SEALED_CHECK_LEN;
+/// the longest record, a message of the largest frame with its names and time
+const MAX_RECORD_LEN: usize = 2 * 1024 * 1024;
+
+/// a message sent or received
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub at: SystemTime,
+    pub from: String,
+    /// the receiver, a user or a room
+    pub to: String,
+    pub body: String,
+}
+
+impl Record {
+    /// the message sent or received now
+    pub fn new(from: impl Into<String>, to: impl Into<String>, body: impl Into<String>) -> Self {
+        Record {
+            at: SystemTime::now(),
+            from: from.into(),
+            to: to.into(),
+            body: body.into(),
+        }
+    }
+}
+
+/// the local store of messages of a user on a server, encrypted at rest
+pub struct Store {
+    /// the end of the file, shared with the record being written off the async runtime
+    appender: Arc<Mutex<Appender>>,
+    cipher: Aes256Gcm,
+    /// bound to every record
+    header: [u8; HEADER_LEN],
+    /// in the order of time
+    records: Vec<Record>,
+    /// records which could not be decrypted or read, left in the file
+    skipped: usize,
+}
+
+impl Store {
+    /// open the store of the user on the server under `dir`, created if there is none,
+    /// [`io::ErrorKind::InvalidData`] if the passphrase is wrong
+    pub fn open(
+        dir: impl AsRef<Path>,
+        server: &str,
+        username: &str,
+        passphrase: &str,
+    ) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let name = format!("{}@{}.store", file_safe(username), file_safe(server));
+        Self::open_file(dir.join(name), passphrase)
+    }
+
+    /// open the store in the file, created if there is none
+    ///
+    /// a record written partly at the end of the file is cut,
+    /// a record corrupt anywhere else fails with [`io::ErrorKind::InvalidData`],
+    /// and the file is left as it is.
+    /// a store open by another client fails with [`io::ErrorKind::WouldBlock`]
+    pub fn open_file(path: impl AsRef<Path>, passphrase: &str) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        lock(&file)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        if bytes.is_empty() {
+            let mut salt = [0; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            let cipher = derive(passphrase, &salt)?;
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let check = cipher.encrypt(&nonce, &CHECK[..]).map_err(invalid)?;
+            let header: [u8; HEADER_LEN] = [&MAGIC[..], &salt, &nonce, &check]
+                .concat()
+                .try_into()
+                .expect("header of fixed length");
+            file.write_all(&header)?;
+            let appender = Appender {
+                file,
+                len: HEADER_LEN as u64,
+                next_index: 0,
+            };
+            return Ok(Store {
+                appender: Arc::new(Mutex::new(appender)),
+                cipher,
+                header,
+                records: Vec::new(),
+                skipped: 0,
+            });
+        }
+
+        if bytes.len() < HEADER_LEN || !bytes.starts_with(MAGIC) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a message store",
+            ));
+        }
+        let (header, mut rest) = bytes.split_at(HEADER_LEN);
+        let header: [u8; HEADER_LEN] = header.try_into().expect("header of fixed length");
+        let (salt, sealed) = header[MAGIC.len()..].split_at(SALT_LEN);
+        let (nonce, check) = sealed.split_at(NONCE_LEN);
+        let cipher = derive(passphrase, salt)?;
+        if cipher.decrypt(Nonce::from_slice(nonce), check).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "wrong passphrase",
+            ));
+        }
+
+        let appender = Appender {
+            file,
+            len: bytes.len() as u64,
+            next_index: 0,
+        };
+        let mut store = Store {
+            appender: Arc::new(Mutex::new(appender)),
+            cipher,
+            header,
+            records: Vec::new(),
+            skipped: 0,
+        };
+        let mut appender = store.appender.lock().unwrap();
+        while !rest.is_empty() {
+            let Some((len, body)) = rest.split_first_chunk::<4>() else {
+                break;
+            };
+            let len = u32::from_be_bytes(*len) as usize;
+            if !(NONCE_LEN + TAG_LEN..=MAX_RECORD_LEN).contains(&len) {
+                let offset = bytes.len() - rest.len();
+                let error = format!("corrupt record of {len} bytes at {offset}");
+                return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+            }
+            if body.len() < len {
+                break;
+            }
+            let (nonce, sealed) = body[..len].split_at(NONCE_LEN);
+            let index = appender.next_index;
+            match store.open_record(index, nonce, sealed) {
+                Ok(record) => store.records.push(record),
+                Err(e) => {
+                    tracing::warn!(index, %e, "skip unreadable record");
+                    store.skipped += 1;
+                }
+            }
+            appender.next_index += 1;
+            rest = &body[len..];
+        }
+        if !rest.is_empty() {
+            // the last record is written partly, cut it for the records appended later
+            tracing::warn!(bytes = rest.len(), "cut the record written partly");
+            appender.len -= rest.len() as u64;
+            appender.file.set_len(appender.len)?;
+        }
+        drop(appender);
+        store.records.sort_by_key(|record| record.at);
+        Ok(store)
+    }
+
+    /// every record, in the order of time
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// how many records could not be decrypted or read, they are skipped
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// keep the message, the file is written off the async runtime
+    ///
+    /// the record is written whole even if the future is dropped meanwhile
+    pub async fn record(&mut self, record: Record) -> io::Result<()> {
+        let plain = serde_json::to_vec(&record)?;
+        let (appender, cipher, header) = (self.appender.clone(), self.cipher.clone(), self.header);
+        let written = tokio::task::spawn_blocking(move || {
+            appender.lock().unwrap().append(&cipher, &header, &plain)
+        });
+
+        let index = self.records.partition_point(|kept| kept.at <= record.at);
+        self.records.insert(index, record);
+        let written = written.await.map_err(io::Error::other).and_then(|r| r);
+        if written.is_err() {
+            self.records.remove(index);
+        }
+        written
+    }
+
+    /// the records whose body contains the text, ignoring case
+    pub fn search(&self, text: &str) -> Vec<&Record> {
+        let text = text.to_lowercase();
+        self.records
+            .iter()
+            .filter(|record| record.body.to_lowercase().contains(&text))
+            .collect()
+    }
+
+    /// decrypt the record of the index
+    fn open_record(&self, index: u64, nonce: &[u8], sealed: &[u8]) -> io::Result<Record> {
+        let aad = associated_data(&self.header, index);
+        let payload = Payload {
+            msg: sealed,
+            aad: &aad,
+        };
+        let plain = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(invalid)?;
+        Ok(serde_json::from_slice(&plain)?)
+    }
+}
+
+/// the file of a store, a record is appended at a time
+struct Appender {
+    file: File,
+    /// length of the file, up to the last record written whole
+    len: u64,
+    /// index of the next record in the file
+    next_index: u64,
+}
+
+impl Appender {
+    /// seal the record as the next one and write it,
+    /// the length and index are updated only once it is written whole
+    fn append(&mut self, cipher: &Aes256Gcm, header: &[u8], plain: &[u8]) -> io::Result<()> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = associated_data(header, self.next_index);
+        let payload = Payload {
+            msg: plain,
+            aad: &aad,
+        };
+        let sealed = cipher.encrypt(&nonce, payload).map_err(invalid)?;
+        let len = ((NONCE_LEN + sealed.len()) as u32).to_be_bytes();
+        let bytes = [&len[..], &nonce, &sealed].concat();
+
+        if let Err(e) = self.file.write_all(&bytes) {
+            // never leave a record written partly before the next one
+            let _ = self.file.set_len(self.len);
+            return Err(e);
+        }
+        self.len += bytes.len() as u64;
+        self.next_index += 1;
+        Ok(())
+    }
+}
+
+/// the header and the index of the record, so no record is moved to another place or file
+fn associated_data(header: &[u8], index: u64) -> Vec<u8> {
+    [header, &index.to_be_bytes()].concat()
+}
+
+/// lock the file for this client only, it is unlocked once closed
+#[cfg(unix)]
+fn lock(file: &File) -> io::Result<()> {
+    use rustix::fs::{flock, FlockOperation};
+
+    flock(file, FlockOperation::NonBlockingLockExclusive).map_err(|e| match e {
+        rustix::io::Errno::WOULDBLOCK => io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "the store is in use by another client",
+        ),
+        e => e.into(),
+    })
+}
+
+/// files are not locked on this platform
+#[cfg(not(unix))]
+fn lock(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+impl Debug for Store {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Store")
+            .field("records", &self.records.len())
+            .field("skipped", &self.skipped)
+            .finish()
+    }
+}
+
+/// the key of the passphrase
+fn derive(passphrase: &str, salt: &[u8]) -> io::Result<Aes256Gcm> {
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    Ok(Aes256Gcm::new(&key.into()))
+}
+
+fn invalid(e: aes_gcm::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// the name for a file name, letters, digits, `-` and `.` are kept,
+/// every other byte is percent-encoded, so no two names are the same
+fn file_safe(name: &str) -> String {
+    let mut safe = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' => safe.push(byte as char),
+            _ => safe += &format!("%{byte:02X}"),
+        }
+    }
+    safe
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("dc-{name}-{}.store", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    /// a store of two records, and where the second one starts
+    async fn two_records(path: &Path) -> u64 {
+        let mut store = Store::open_file(path, "secret").unwrap();
+        store
+            .record(Record::new("alice", "bob", "first"))
+            .await
+            .unwrap();
+        let second = fs::metadata(path).unwrap().len();
+        store
+            .record(Record::new("bob", "alice", "second"))
+            .await
+            .unwrap();
+        second
+    }
+
+    #[tokio::test]
+    async fn records_are_kept_and_searched() {
+        let path = temp_store("kept");
+        let mut store = Store::open_file(&path, "secret").unwrap();
+        store
+            .record(Record::new("alice", "bob", "Hello Bob"))
+            .await
+            .unwrap();
+        store
+            .record(Record::new("bob", "alice", "hi"))
+            .await
+            .unwrap();
+        drop(store);
+
+        let bytes = fs::read(&path).unwrap();
+        assert!(!bytes.windows(5).any(|window| window == b"Hello"));
+
+        let store = Store::open_file(&path, "secret").unwrap();
+        assert_eq!(2, store.records().len());
+        assert_eq!(0, store.skipped());
+        let found = store.search("hello");
+        assert_eq!(1, found.len());
+        assert_eq!("alice", found[0].from);
+        drop(store);
+
+        let wrong = Store::open_file(&path, "guess").unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, wrong.kind());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn record_written_partly_is_cut() {
+        let path = temp_store("partly");
+        let mut store = Store::open_file(&path, "secret").unwrap();
+        store
+            .record(Record::new("alice", "bob", "whole"))
+            .await
+            .unwrap();
+        drop(store);
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[0, 0, 1, 0, 7])
+            .unwrap();
+
+        let mut store = Store::open_file(&path, "secret").unwrap();
+        assert_eq!(len, fs::metadata(&path).unwrap().len());
+        store
+            .record(Record::new("bob", "alice", "after"))
+            .await
+            .unwrap();
+        drop(store);
+        let store = Store::open_file(&path, "secret").unwrap();
+        assert_eq!(2, store.records().len());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn corrupt_length_is_refused_and_left() {
+        let path = temp_store("corrupt");
+        let second = two_records(&path).await;
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[second as usize..second as usize + 4].copy_from_slice(&[0, 0, 0, 3]);
+        fs::write(&path, &bytes).unwrap();
+
+        let error = Store::open_file(&path, "secret").unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!(bytes, fs::read(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unreadable_record_is_skipped() {
+        let path = temp_store("unreadable");
+        let second = two_records(&path).await;
+        let mut bytes = fs::read(&path).unwrap();
+        // the first record is tampered, the second one is copied to the end
+        bytes[HEADER_LEN + 4 + NONCE_LEN] ^= 1;
+        let copied = bytes[second as usize..].to_vec();
+        bytes.extend_from_slice(&copied);
+        fs::write(&path, &bytes).unwrap();
+
+        let store = Store::open_file(&path, "secret").unwrap();
+        assert_eq!(2, store.skipped());
+        assert_eq!(1, store.records().len());
+        assert_eq!("second", store.records()[0].body);
+        assert_eq!(bytes, fs::read(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn store_in_use_is_refused() {
+        let path = temp_store("in-use");
+        let store = Store::open_file(&path, "secret").unwrap();
+        let error = Store::open_file(&path, "secret").unwrap_err();
+        assert_eq!(io::ErrorKind::WouldBlock, error.kind());
+        drop(store);
+        Store::open_file(&path, "secret").unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_names_never_collide() {
+        let names: Vec<String> = ["a_b", "a/b", "a%2Fb", "a:b"]
+            .iter()
+            .map(|name| file_safe(name))
+            .collect();
+        assert_eq!(["a%5Fb", "a%2Fb", "a%252Fb", "a%3Ab"], names[..]);
+        assert_eq!("127.0.0.1%3A8233", file_safe("127.0.0.1:8233"));
+    }
+}
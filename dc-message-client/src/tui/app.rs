@@ -0,0 +1,425 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use super::view;
+use crate::{
+    command::{Command, Commands},
+    conversation::{Conversations, Entry},
+    input::InputType,
+    screen::ConnectionState,
+};
+
+/// how many rows a page up or down scrolls
+const PAGE: usize = 10;
+
+/// the line being typed, edited by char, with the lines submitted before
+#[derive(Debug, Default)]
+pub(crate) struct Editor {
+    pub text: String,
+    /// in chars
+    pub cursor: usize,
+    history: Vec<String>,
+    /// the line of history shown, [`None`] for the line being typed
+    recalled: Option<usize>,
+}
+
+impl Editor {
+    fn byte_index(&self, cursor: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(cursor)
+            .map_or(self.text.len(), |(i, _)| i)
+    }
+
+    fn insert(&mut self, s: &str) {
+        let index = self.byte_index(self.cursor);
+        self.text.insert_str(index, s);
+        self.cursor += s.chars().count();
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.delete();
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.text.chars().count() {
+            let index = self.byte_index(self.cursor);
+            self.text.remove(index);
+        }
+    }
+
+    fn set(&mut self, text: String) {
+        self.cursor = text.chars().count();
+        self.text = text;
+    }
+
+    /// take the line typed, and remember it
+    fn submit(&mut self) -> String {
+        let line = std::mem::take(&mut self.text);
+        self.cursor = 0;
+        self.recalled = None;
+        if !line.trim().is_empty() && self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        line
+    }
+
+    /// show the line submitted before, or after if not `back`
+    fn recall(&mut self, back: bool) {
+        let recalled = match (self.recalled, back) {
+            (None, true) => self.history.len().checked_sub(1),
+            (None, false) => return,
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) => Some(i + 1).filter(|i| *i < self.history.len()),
+        };
+        self.recalled = recalled;
+        let text = recalled.map_or_else(String::new, |i| self.history[i].clone());
+        self.set(text);
+    }
+}
+
+/// what the full screen shows, changed by the events and the keys
+#[derive(Debug)]
+pub(crate) struct App {
+    pub username: String,
+    pub server: String,
+    /// the conversation shown is the one the texts are sent to
+    pub conversations: Conversations,
+    /// rows scrolled up from the bottom of the conversation shown, a long line wraps to several
+    pub scroll: usize,
+    /// rows fitting the pane, as of the last draw
+    pub visible: usize,
+    /// columns of the pane, as of the last draw
+    pub width: u16,
+    pub online: BTreeSet<String>,
+    pub connection: ConnectionState,
+    /// the last notice or the completions, shown in the status bar
+    pub status: Option<String>,
+    pub editor: Editor,
+    commands: Arc<Commands>,
+}
+
+impl App {
+    pub fn new(username: String, server: String, commands: Arc<Commands>) -> Self {
+        App {
+            username,
+            server,
+            conversations: Conversations::default(),
+            scroll: 0,
+            visible: 0,
+            width: 0,
+            online: BTreeSet::new(),
+            connection: ConnectionState::Connected,
+            status: None,
+            editor: Editor::default(),
+            commands,
+        }
+    }
+
+    fn push(&mut self, index: usize, entry: Entry) {
+        if index == self.conversations.active_index() && self.scroll > 0 {
+            // keep the lines looked at still
+            self.scroll += view::entry_rows(self, &entry, self.width);
+        }
+        self.conversations.push(index, entry);
+    }
+
+    fn select(&mut self, index: usize) {
+        self.conversations.select(index);
+        self.scroll = 0;
+    }
+
+    /// a message from the peer, or in the room
+    pub fn message(&mut self, from: &str, room: Option<&str>, body: &str) {
+        let index = self.conversations.open(room.unwrap_or(from));
+        let text = Entry::Text {
+            sender: from.to_string(),
+            body: body.to_string(),
+        };
+        self.push(index, text);
+    }
+
+    pub fn sent(&mut self, to: &str, body: &str) {
+        let index = self.conversations.open(to);
+        let text = Entry::Text {
+            sender: self.username.clone(),
+            body: body.to_string(),
+        };
+        self.push(index, text);
+    }
+
+    /// a message kept before, not counted as unread
+    pub fn history(&mut self, conversation: &str, from: &str, body: &str) {
+        let index = self.conversations.open(conversation);
+        let text = Entry::Text {
+            sender: from.to_string(),
+            body: body.to_string(),
+        };
+        self.conversations.push_read(index, text);
+    }
+
+    /// told in the conversation shown, and in the status bar
+    pub fn notice(&mut self, entry: Entry) {
+        if let Entry::Info(text) | Entry::Notice(text) | Entry::Error(text) = &entry {
+            self.status = text.lines().next().map(str::to_string);
+        }
+        self.push(self.conversations.active_index(), entry);
+    }
+
+    pub fn receiver(&mut self, username: &str) {
+        let index = self.conversations.open(username);
+        self.select(index);
+    }
+
+    pub fn presence(&mut self, username: &str, online: bool) {
+        if online {
+            self.online.insert(username.to_string());
+        } else {
+            self.online.remove(username);
+        }
+    }
+
+    /// handle the key, what is typed once it is submitted
+    pub fn key(&mut self, key: KeyEvent) -> Option<InputType> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c') if ctrl => return Some(InputType::Command(Command::Quit)),
+            KeyCode::Char('d') if ctrl && self.editor.text.is_empty() => {
+                return Some(InputType::Command(Command::Quit))
+            }
+            KeyCode::Char('n') if ctrl => self.switch(true),
+            KeyCode::Char('p') if ctrl => self.switch(false),
+            KeyCode::Char('a') if ctrl => self.editor.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.editor.cursor = self.editor.text.chars().count(),
+            KeyCode::Char('u') if ctrl => {
+                let index = self.editor.byte_index(self.editor.cursor);
+                self.editor.text.drain(..index);
+                self.editor.cursor = 0;
+            }
+            KeyCode::Char(c) if !ctrl => self.editor.insert(c.encode_utf8(&mut [0; 4])),
+            KeyCode::Backspace => self.editor.backspace(),
+            KeyCode::Delete => self.editor.delete(),
+            KeyCode::Left => self.editor.cursor = self.editor.cursor.saturating_sub(1),
+            KeyCode::Right => {
+                self.editor.cursor = (self.editor.cursor + 1).min(self.editor.text.chars().count())
+            }
+            KeyCode::Home => self.editor.cursor = 0,
+            KeyCode::End => self.editor.cursor = self.editor.text.chars().count(),
+            KeyCode::Up => self.editor.recall(true),
+            KeyCode::Down => self.editor.recall(false),
+            KeyCode::PageUp => {
+                let rows: usize = (self.conversations.active().entries.iter())
+                    .map(|entry| view::entry_rows(self, entry, self.width))
+                    .sum();
+                let top = rows.saturating_sub(self.visible);
+                self.scroll = (self.scroll + PAGE).min(top);
+            }
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(PAGE),
+            KeyCode::Esc => self.editor.set(String::new()),
+            KeyCode::Tab => self.complete(),
+            KeyCode::Enter => return self.submit(),
+            _ => {}
+        }
+        None
+    }
+
+    fn submit(&mut self) -> Option<InputType> {
+        let line = self.editor.submit();
+        if line.trim().is_empty() {
+            return None;
+        }
+        match InputType::parse(&line, &self.commands) {
+            Ok(input) => Some(input),
+            Err(e) => {
+                self.notice(Entry::Info(e.to_string()));
+                None
+            }
+        }
+    }
+
+    /// show the next conversation, or the previous, and send the texts to its peer
+    fn switch(&mut self, next: bool) {
+        let (len, active) = (self.conversations.len(), self.conversations.active_index());
+        let index = match next {
+            true => (active + 1) % len,
+            false => (active + len - 1) % len,
+        };
+        self.select(index);
+    }
+
+    /// complete the word before the cursor,
+    /// or as much as the candidates share, and show them
+    fn complete(&mut self) {
+        let cursor = self.editor.byte_index(self.editor.cursor);
+        let online: Vec<String> = self.online.iter().cloned().collect();
+        let (start, candidates) = self.commands.complete(&self.editor.text[..cursor], &online);
+        let Some(first) = candidates.first() else {
+            return;
+        };
+        let shared = candidates.iter().fold(first.as_str(), |shared, candidate| {
+            let len = shared
+                .char_indices()
+                .zip(candidate.chars())
+                .take_while(|((_, a), b)| a == b)
+                .last()
+                .map_or(0, |((i, a), _)| i + a.len_utf8());
+            &shared[..len]
+        });
+        if shared.len() > cursor - start {
+            let completed = self.editor.text[..start].chars().count();
+            self.editor.text.replace_range(start..cursor, shared);
+            self.editor.cursor = completed + shared.chars().count();
+        }
+        if candidates.len() > 1 {
+            self.status = Some(candidates.join("  "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversation::Conversation;
+
+    fn app() -> App {
+        App::new(
+            "carol".into(),
+            "127.0.0.1:8233".into(),
+            Arc::new(Commands::default()),
+        )
+    }
+
+    fn press(app: &mut App, code: KeyCode) -> Option<InputType> {
+        app.key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn ctrl(app: &mut App, c: char) -> Option<InputType> {
+        app.key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL))
+    }
+
+    fn type_line(app: &mut App, line: &str) -> Option<InputType> {
+        for c in line.chars() {
+            press(app, KeyCode::Char(c));
+        }
+        press(app, KeyCode::Enter)
+    }
+
+    #[test]
+    fn unread_until_looked_at() {
+        let mut app = app();
+        app.receiver("alice");
+        app.message("alice", None, "hi");
+        app.message("bob", None, "hey");
+        app.message("bob", None, "are you there");
+        app.message("dave", Some("rust"), "hello all");
+        let names: Vec<&str> = app.conversations.iter().map(Conversation::name).collect();
+        assert_eq!(["server", "alice", "bob", "rust"], names[..]);
+        let unread: Vec<usize> = app.conversations.iter().map(|c| c.unread).collect();
+        assert_eq!([0, 0, 2, 1], unread[..]);
+        // numbered like the list of `/switch`
+        assert_eq!(Some("bob"), app.conversations.find("2"));
+
+        assert!(ctrl(&mut app, 'n').is_none());
+        assert_eq!(Some("bob"), app.conversations.receiver());
+        assert_eq!(0, app.conversations.active().unread);
+
+        app.sent("bob", "yes");
+        assert!(matches!(
+            app.conversations.active().entries.last(),
+            Some(Entry::Text { sender, .. }) if sender == "carol"
+        ));
+        ctrl(&mut app, 'n');
+        ctrl(&mut app, 'n');
+        // the server, where the texts are not sent to
+        assert_eq!(0, app.conversations.active_index());
+        assert_eq!(None, app.conversations.receiver());
+    }
+
+    #[test]
+    fn edit_and_recall_lines() {
+        let mut app = app();
+        assert!(matches!(
+            type_line(&mut app, "héllo"),
+            Some(InputType::Text(text)) if text == "héllo"
+        ));
+        assert!(matches!(
+            type_line(&mut app, "/to alice"),
+            Some(InputType::Command(Command::To(peer))) if peer == "alice"
+        ));
+
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Up);
+        assert_eq!("héllo", app.editor.text);
+        press(&mut app, KeyCode::Left);
+        press(&mut app, KeyCode::Left);
+        press(&mut app, KeyCode::Backspace);
+        press(&mut app, KeyCode::Char('L'));
+        assert_eq!("héLlo", app.editor.text);
+        press(&mut app, KeyCode::Down);
+        assert_eq!("/to alice", app.editor.text);
+        press(&mut app, KeyCode::Down);
+        assert_eq!("", app.editor.text);
+
+        // the failure is told, and nothing is submitted
+        assert!(type_line(&mut app, "/dance").is_none());
+        assert!(app.status.as_deref().unwrap().contains("unknown command"));
+    }
+
+    #[test]
+    fn complete_commands_and_usernames() {
+        let mut app = app();
+        app.presence("alice", true);
+        app.presence("albert", true);
+        for c in "/t".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        press(&mut app, KeyCode::Tab);
+        assert_eq!("/to ", app.editor.text);
+
+        press(&mut app, KeyCode::Char('a'));
+        press(&mut app, KeyCode::Tab);
+        assert_eq!("/to al", app.editor.text);
+        assert_eq!(Some("albert  alice"), app.status.as_deref());
+
+        press(&mut app, KeyCode::Char('i'));
+        press(&mut app, KeyCode::Tab);
+        assert_eq!("/to alice", app.editor.text);
+        assert_eq!(9, app.editor.cursor);
+    }
+
+    #[test]
+    fn page_up_stops_at_top() {
+        let mut app = app();
+        (app.visible, app.width) = (5, 40);
+        press(&mut app, KeyCode::PageUp);
+        assert_eq!(0, app.scroll);
+
+        for i in 0..12 {
+            app.notice(Entry::Info(format!("line {i}")));
+        }
+        press(&mut app, KeyCode::PageUp);
+        press(&mut app, KeyCode::PageUp);
+        assert_eq!(7, app.scroll);
+        press(&mut app, KeyCode::PageDown);
+        assert_eq!(0, app.scroll);
+    }
+
+    #[test]
+    fn scroll_by_wrapped_rows() {
+        let mut app = app();
+        (app.visible, app.width) = (5, 10);
+        // 3 rows each, wrapped in the pane
+        for i in 0..4 {
+            app.notice(Entry::Info(format!("line {i} wrapped twice over")));
+        }
+        press(&mut app, KeyCode::PageUp);
+        assert_eq!(7, app.scroll);
+
+        app.notice(Entry::Info("another line".to_string()));
+        assert_eq!(9, app.scroll);
+    }
+}
@@ -0,0 +1,119 @@
+//! the full-screen client, with a conversation list, a scrollback for each peer,
+//! an input line with editing and history, and a status bar of the connection
+
+use std::{io, sync::Arc};
+
+use ratatui::{crossterm::event::KeyEvent, DefaultTerminal};
+
+use crate::{
+    command::Commands,
+    conversation::{Conversations, Entry},
+    input::InputType,
+    screen::{ConnectionState, Screen},
+};
+
+mod app;
+mod view;
+
+use app::App;
+
+/// the [`Screen`] taking the whole terminal, restored once it is dropped
+pub(crate) struct Tui {
+    app: App,
+    terminal: DefaultTerminal,
+}
+
+impl Tui {
+    /// enter the full screen, a panic restores the terminal too
+    pub fn new(username: String, server: String, commands: Arc<Commands>) -> io::Result<Self> {
+        let terminal = ratatui::try_init()?;
+        let mut tui = Tui {
+            app: App::new(username, server, commands),
+            terminal,
+        };
+        tui.draw();
+        Ok(tui)
+    }
+
+    fn draw(&mut self) {
+        let (app, mut pane) = (&self.app, (self.app.visible, self.app.width));
+        if let Err(e) = self.terminal.draw(|frame| pane = view::render(frame, app)) {
+            tracing::warn!(%e, "draw terminal failure");
+        }
+        (self.app.visible, self.app.width) = pane;
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        ratatui::restore();
+        if self.app.connection == ConnectionState::Disconnected {
+            println!("Disconnected");
+        }
+    }
+}
+
+impl Screen for Tui {
+    fn message(&mut self, from: &str, room: Option<&str>, body: &str) {
+        self.app.message(from, room, body);
+        self.draw();
+    }
+
+    fn announce(&mut self, text: &str) {
+        self.app.notice(Entry::Notice(text.to_string()));
+        self.draw();
+    }
+
+    fn error(&mut self, text: &str) {
+        self.app.notice(Entry::Error(text.to_string()));
+        self.draw();
+    }
+
+    fn sent(&mut self, to: &str, body: &str) {
+        self.app.sent(to, body);
+        self.draw();
+    }
+
+    /// drawn once the whole history is replayed, by the notice following it
+    fn history(&mut self, conversation: &str, from: &str, body: &str) {
+        self.app.history(conversation, from, body);
+    }
+
+    fn notice(&mut self, text: &str) {
+        self.app.notice(Entry::Info(text.to_string()));
+        self.draw();
+    }
+
+    fn conversations(&mut self) -> &mut Conversations {
+        &mut self.app.conversations
+    }
+
+    fn receiver(&mut self, username: &str) {
+        self.app.receiver(username);
+        self.draw();
+    }
+
+    fn presence(&mut self, username: &str, online: bool) {
+        self.app.presence(username, online);
+        self.draw();
+    }
+
+    fn connection(&mut self, state: ConnectionState) {
+        self.app.connection = state;
+        self.draw();
+    }
+
+    fn full_screen(&self) -> bool {
+        true
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Option<InputType> {
+        let input = self.app.key(key);
+        self.draw();
+        input
+    }
+
+    fn resize(&mut self) {
+        self.draw();
+    }
+}
@@ -0,0 +1,241 @@
+use ratatui::{
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, Paragraph, Wrap},
+    Frame,
+};
+
+use super::app::App;
+use crate::conversation::Entry;
+use crate::screen::ConnectionState;
+
+/// width of the conversation list
+const LIST_WIDTH: u16 = 24;
+
+/// draw the conversations, the one shown, the input line and the status bar
+///
+/// # Return
+/// the size of the pane of the conversation shown, tuple: (rows, columns)
+pub(crate) fn render(frame: &mut Frame, app: &App) -> (usize, u16) {
+    let [main, input, status] = Layout::vertical([
+        Constraint::Min(3),
+        Constraint::Length(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [list, pane] =
+        Layout::horizontal([Constraint::Length(LIST_WIDTH), Constraint::Min(1)]).areas(main);
+
+    render_list(frame, app, list);
+    let pane = render_pane(frame, app, pane);
+    render_input(frame, app, input);
+    render_status(frame, app, status);
+    pane
+}
+
+fn render_list(frame: &mut Frame, app: &App, area: Rect) {
+    let items = app
+        .conversations
+        .iter()
+        .enumerate()
+        .map(|(i, conversation)| {
+            let online = match &conversation.peer {
+                Some(peer) if app.online.contains(peer) => Span::from("● ").green(),
+                Some(_) => Span::from("○ ").dark_gray(),
+                None => Span::from("  "),
+            };
+            let mut line = Line::from(vec![online, Span::from(conversation.name().to_string())]);
+            if conversation.unread > 0 {
+                line.push_span(Span::from(format!(" ({})", conversation.unread)).bold());
+            }
+            let item = ListItem::new(line);
+            match i == app.conversations.active_index() {
+                true => item.style(Style::new().add_modifier(Modifier::REVERSED)),
+                false => item,
+            }
+        });
+    frame.render_widget(
+        List::new(items).block(Block::bordered().title("Conversations")),
+        area,
+    );
+}
+
+fn render_pane(frame: &mut Frame, app: &App, area: Rect) -> (usize, u16) {
+    let block = Block::bordered().title(app.conversations.active().name().to_string());
+    let inner = block.inner(area);
+    let lines: Vec<Line> = app
+        .conversations
+        .active()
+        .entries
+        .iter()
+        .flat_map(|entry| entry_lines(app, entry))
+        .collect();
+
+    // the rows fitting the pane, up to the bottom scrolled to,
+    // the top line is cut if it does not fit, even if it is taller than the pane
+    let height = usize::from(inner.height);
+    let wanted = app.scroll + height;
+    let (mut top, mut rows) = (lines.len(), 0);
+    while top > 0 && rows < wanted {
+        top -= 1;
+        rows += line_rows(lines[top].clone(), inner.width);
+    }
+    let offset = u16::try_from(rows.saturating_sub(wanted)).unwrap_or(u16::MAX);
+    let paragraph = Paragraph::new(lines[top..].to_vec())
+        .block(block)
+        .wrap(Wrap { trim: false })
+        .scroll((offset, 0));
+    frame.render_widget(paragraph, area);
+    (height, inner.width)
+}
+
+/// rows the entry takes in a pane of `width` columns, wrapped as it is drawn
+pub(crate) fn entry_rows(app: &App, entry: &Entry, width: u16) -> usize {
+    entry_lines(app, entry)
+        .into_iter()
+        .map(|line| line_rows(line, width))
+        .sum()
+}
+
+fn line_rows(line: Line, width: u16) -> usize {
+    Paragraph::new(line)
+        .wrap(Wrap { trim: false })
+        .line_count(width.max(1))
+}
+
+/// a message as `sender: body`, the others by their kinds, one line for each line of the body
+fn entry_lines<'a>(app: &App, entry: &'a Entry) -> Vec<Line<'a>> {
+    let (prefix, body, style) = match entry {
+        Entry::Text { sender, body } => {
+            let style = match *sender == app.username {
+                true => Style::new().cyan().bold(),
+                false => Style::new().green().bold(),
+            };
+            let prefix = Span::styled(format!("{sender}: "), style);
+            return body
+                .lines()
+                .map(|line| Line::from(vec![prefix.clone(), Span::from(line)]))
+                .collect();
+        }
+        Entry::Info(body) => ("", body, Style::new().dark_gray().italic()),
+        Entry::Notice(body) => ("server: ", body, Style::new().yellow()),
+        Entry::Error(body) => ("error: ", body, Style::new().red()),
+    };
+    body.lines()
+        .map(|line| Line::styled(format!("{prefix}{line}"), style))
+        .collect()
+}
+
+fn render_input(frame: &mut Frame, app: &App, area: Rect) {
+    let title = match app.conversations.receiver() {
+        Some(receiver) => format!("to {receiver}"),
+        None => "/to <username> to choose who to talk with, /help for all commands".to_string(),
+    };
+    let block = Block::bordered().title(title);
+    let inner = block.inner(area);
+
+    // keep the cursor in sight
+    let before: String = app.editor.text.chars().take(app.editor.cursor).collect();
+    let cursor = Line::from(before).width() as u16;
+    let offset = cursor.saturating_sub(inner.width.saturating_sub(1));
+    let paragraph = Paragraph::new(app.editor.text.as_str())
+        .block(block)
+        .scroll((0, offset));
+    frame.render_widget(paragraph, area);
+    frame.set_cursor_position(Position::new(inner.x + cursor - offset, inner.y));
+}
+
+fn render_status(frame: &mut Frame, app: &App, area: Rect) {
+    let connection = match app.connection {
+        ConnectionState::Connected => Span::from("connected").green(),
+        ConnectionState::Reconnecting => Span::from("reconnecting").yellow(),
+        ConnectionState::Disconnected => Span::from("disconnected").red(),
+    };
+    let mut line = Line::from(vec![
+        Span::from(format!(" {}@{} ", app.username, app.server)).bold(),
+        connection,
+    ]);
+    if let Some(status) = &app.status {
+        line.push_span(Span::from(format!(" | {status}")));
+    }
+    frame.render_widget(
+        Paragraph::new(line).style(Style::new().bg(Color::DarkGray)),
+        area,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ratatui::{backend::TestBackend, Terminal};
+
+    use super::*;
+    use crate::command::Commands;
+
+    #[test]
+    fn render_conversations_and_status() {
+        let mut app = App::new(
+            "carol".into(),
+            "127.0.0.1:8233".into(),
+            Arc::new(Commands::default()),
+        );
+        app.presence("alice", true);
+        app.receiver("alice");
+        app.message("alice", None, "hi carol");
+        app.sent("alice", "hi alice");
+        app.message("bob", None, "ping");
+        app.notice(Entry::Error("message too long".into()));
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 12)).unwrap();
+        let mut pane = (0, 0);
+        terminal.draw(|frame| pane = render(frame, &app)).unwrap();
+        // the terminal but the list, the input, the status and the borders
+        assert_eq!((6, 54), pane);
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+
+        for shown in [
+            "● alice",
+            "○ bob (1)",
+            "alice: hi carol",
+            "carol: hi alice",
+            "error: message too long",
+            "to alice",
+            "carol@127.0.0.1:8233 connected",
+        ] {
+            assert!(screen.contains(shown), "{shown} not shown in {screen}");
+        }
+        assert!(!screen.contains("ping"));
+    }
+
+    #[test]
+    fn line_taller_than_pane_is_shown() {
+        let mut app = App::new(
+            "carol".into(),
+            "127.0.0.1:8233".into(),
+            Arc::new(Commands::default()),
+        );
+        let long = format!("{}the end", "word ".repeat(100));
+        app.message("alice", None, &long);
+        app.receiver("alice");
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 12)).unwrap();
+        terminal.draw(|frame| _ = render(frame, &app)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("word the end"), "{screen}");
+        assert!(!screen.contains("alice: word"), "{screen}");
+    }
+}
@@ -1,8 +1,65 @@
-use clap::{Arg, Command};
+use std::{path::PathBuf, time::Duration};
 
+use clap::{value_parser, Arg, ArgAction, Command, ValueEnum};
+use dc_message_server::{
+    default_control_socket, AdmissionPolicy, Cidr, Ctl, Echo, OverflowPolicy, RateLimits,
+    ServerBuilder,
+};
+use dvorak_message::message::DecodeLimits;
+use tracing_subscriber::EnvFilter;
+
+/// the command line of server
 #[derive(Debug)]
-pub(crate) struct Args {
+pub struct Args {
     pub host: String,
+    /// address listening the browser clients over WebSocket, [`None`] if not listened
+    pub websocket_addr: Option<String>,
+    /// address listening the clients speaking JSON lines, [`None`] if not listened
+    pub json_addr: Option<String>,
+    /// path of the Unix-domain socket listening the local clients, [`None`] if not listened
+    pub unix_socket: Option<PathBuf>,
+    /// permission mode of the Unix-domain socket, who could connect to it
+    pub unix_socket_mode: u32,
+    /// how long an incoming client may take to send its Login message
+    pub login_timeout: Duration,
+    /// what to do when the inbox of a client is full
+    pub overflow_policy: OverflowPolicy,
+    /// the users not following [`Self::overflow_policy`], tuple: (username, policy)
+    pub overflow_policies: Vec<(String, OverflowPolicy)>,
+    /// count of supervisor shards routing the messages
+    pub shards: usize,
+    /// address listening other nodes of the cluster, [`None`] if the server runs alone
+    pub cluster_listen: Option<String>,
+    /// cluster addresses of other nodes
+    pub peers: Vec<String>,
+    /// secret shared by all nodes of the cluster, a node not knowing it is refused
+    pub cluster_secret: String,
+    /// filter of logs, a level or directives like `dc_message_server=debug`
+    pub log_level: String,
+    /// write logs as JSON lines
+    pub log_json: bool,
+    /// show the bodies of messages in logs, they are redacted by default
+    pub log_bodies: bool,
+    /// address serving the metrics over HTTP, [`None`] if not served
+    pub metrics_addr: Option<String>,
+    /// read the admin commands from stdin
+    pub console: bool,
+    /// username of the echo bot, [`None`] if not running
+    pub echo_bot: Option<String>,
+    /// file of the banned users and ips, read on start and `reload`
+    pub ban_file: Option<PathBuf>,
+    /// path of the control socket, [`None`] if not listened
+    pub control_socket: Option<PathBuf>,
+    /// limits of the messages from every user
+    pub user_limits: RateLimits,
+    /// limits of the messages from every ip, shared by its users
+    pub ip_limits: RateLimits,
+    /// limits of the frames from clients, a client sending a larger one is disconnected
+    pub decode_limits: DecodeLimits,
+    /// which incoming connections are accepted
+    pub admission_policy: AdmissionPolicy,
+    /// run as the client of control socket instead of the server
+    pub ctl: Option<Ctl>,
 }
 
 impl Args {
@@ -15,18 +72,371 @@ impl Args {
                     .long("listen")
                     .default_value("127.0.0.1:8233"),
             )
+            .arg(
+                Arg::new("websocket addr")
+                    .long("websocket-addr")
+                    .help("address listening the browser clients, carrying the frames as binary WebSocket messages"),
+            )
+            .arg(
+                Arg::new("json addr")
+                    .long("json-addr")
+                    .help("address listening the clients speaking JSON lines, for debugging with `nc` or scripts"),
+            )
+            .arg(
+                Arg::new("unix socket")
+                    .long("unix-socket")
+                    .help("path of the Unix-domain socket listening the local clients, only a stale socket left there is replaced")
+                    .value_parser(value_parser!(PathBuf)),
+            )
+            .arg(
+                Arg::new("unix socket mode")
+                    .long("unix-socket-mode")
+                    .help("permission mode of the Unix-domain socket in octal, who could connect to it")
+                    .value_parser(|mode: &str| match u32::from_str_radix(mode, 8) {
+                        Ok(mode) if mode <= 0o777 => Ok(mode),
+                        _ => Err(format!("invalid mode {mode}, expect octal like 660")),
+                    })
+                    .default_value("660"),
+            )
+            .arg(
+                Arg::new("login timeout")
+                    .long("login-timeout")
+                    .help("seconds to wait for the Login message of an incoming client")
+                    .value_parser(value_parser!(u64))
+                    .default_value("10"),
+            )
+            .arg(
+                Arg::new("overflow policy")
+                    .long("overflow-policy")
+                    .help("what to do when the inbox of a slow client is full")
+                    .value_parser(value_parser!(OverflowPolicy))
+                    .default_value("spill"),
+            )
+            .arg(
+                Arg::new("overflow policy of")
+                    .long("overflow-policy-of")
+                    .help("the policy of a user instead of --overflow-policy, like `bot=drop-oldest`, could be repeated")
+                    .value_parser(|value: &str| {
+                        let (username, policy) = value
+                            .split_once('=')
+                            .ok_or_else(|| format!("invalid {value}, expect <USER>=<POLICY>"))?;
+                        let policy = OverflowPolicy::from_str(policy, false)?;
+                        Ok::<_, String>((username.to_string(), policy))
+                    })
+                    .action(ArgAction::Append),
+            )
+            .arg(
+                Arg::new("shards")
+                    .long("shards")
+                    .help("count of supervisor shards routing the messages")
+                    .value_parser(value_parser!(usize))
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::new("cluster listen")
+                    .long("cluster-listen")
+                    .help(
+                        "address listening other nodes of the cluster, it is also the name of this node",
+                    )
+                    .requires("cluster secret file"),
+            )
+            .arg(
+                Arg::new("cluster secret file")
+                    .long("cluster-secret-file")
+                    .help("file of the secret shared by all nodes of the cluster")
+                    .value_parser(|path: &str| match std::fs::read_to_string(path) {
+                        Ok(secret) if !secret.trim().is_empty() => Ok(secret.trim().to_string()),
+                        Ok(_) => Err("the secret is empty".to_string()),
+                        Err(e) => Err(e.to_string()),
+                    }),
+            )
+            .arg(
+                Arg::new("peer")
+                    .long("peer")
+                    .help("cluster address of another node, could be repeated")
+                    .action(ArgAction::Append)
+                    .requires("cluster listen"),
+            )
+            .arg(
+                Arg::new("log level")
+                    .long("log-level")
+                    .help("filter of logs, a level or directives like `dc_message_server=debug`")
+                    .value_parser(|filter: &str| {
+                        EnvFilter::try_new(filter).map(|_| filter.to_string())
+                    })
+                    .default_value("info"),
+            )
+            .arg(
+                Arg::new("log json")
+                    .long("log-json")
+                    .help("write logs as JSON lines")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("log bodies")
+                    .long("log-bodies")
+                    .help("show the bodies of messages in logs, they are redacted by default")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("metrics addr")
+                    .long("metrics-addr")
+                    .help("address serving the metrics of Prometheus over HTTP, at `/metrics`"),
+            )
+            .arg(
+                Arg::new("no console")
+                    .long("no-console")
+                    .help("do not read the admin commands from stdin, like running as a daemon")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("echo bot")
+                    .long("echo-bot")
+                    .help("run the echo bot as a virtual user of this name, `echo` by default")
+                    .num_args(0..=1)
+                    .default_missing_value("echo"),
+            )
+            .arg(
+                Arg::new("ban file")
+                    .long("ban-file")
+                    .help("file of the banned users and ips, one on every line, read again by `reload`")
+                    .value_parser(value_parser!(PathBuf)),
+            )
+            .arg(
+                Arg::new("control socket")
+                    .long("control-socket")
+                    .help(
+                        "listen the Unix-domain socket for the admin commands of `ctl`, \
+                         in `$XDG_RUNTIME_DIR` if no path given",
+                    )
+                    .value_parser(value_parser!(PathBuf))
+                    .num_args(0..=1),
+            )
+            .arg(
+                Arg::new("user rate")
+                    .long("user-rate")
+                    .help("messages per second a user could send, 0 for unlimited")
+                    .value_parser(value_parser!(f64))
+                    .default_value("10"),
+            )
+            .arg(
+                Arg::new("user byte rate")
+                    .long("user-byte-rate")
+                    .help("bytes of messages per second a user could send, 0 for unlimited")
+                    .value_parser(value_parser!(f64))
+                    .default_value("65536"),
+            )
+            .arg(
+                Arg::new("ip rate")
+                    .long("ip-rate")
+                    .help("messages per second the users of an ip could send, 0 for unlimited")
+                    .value_parser(value_parser!(f64))
+                    .default_value("50"),
+            )
+            .arg(
+                Arg::new("ip byte rate")
+                    .long("ip-byte-rate")
+                    .help("bytes of messages per second the users of an ip could send, 0 for unlimited")
+                    .value_parser(value_parser!(f64))
+                    .default_value("262144"),
+            )
+            .arg(
+                Arg::new("max frame size")
+                    .long("max-frame-size")
+                    .help("max bytes of a frame, 1 MiB by default, the client sending a larger one is disconnected")
+                    .value_parser(value_parser!(usize))
+                    .default_value("1048576"),
+            )
+            .arg(
+                Arg::new("max name length")
+                    .long("max-name-length")
+                    .help("max bytes of username and receiver")
+                    .value_parser(value_parser!(u8).range(1..))
+                    .default_value("64"),
+            )
+            .arg(
+                Arg::new("allow")
+                    .long("allow")
+                    .help("accept only the connections from this CIDR block, like `10.0.0.0/8`, could be repeated")
+                    .value_parser(value_parser!(Cidr))
+                    .action(ArgAction::Append),
+            )
+            .arg(
+                Arg::new("deny")
+                    .long("deny")
+                    .help("refuse the connections from this CIDR block even if allowed, could be repeated")
+                    .value_parser(value_parser!(Cidr))
+                    .action(ArgAction::Append),
+            )
+            .arg(
+                Arg::new("max connections")
+                    .long("max-connections")
+                    .help("max connections of the server, 0 for unlimited")
+                    .value_parser(value_parser!(usize))
+                    .default_value("10000"),
+            )
+            .arg(
+                Arg::new("max connections per ip")
+                    .long("max-connections-per-ip")
+                    .help("max connections from an ip, 0 for unlimited")
+                    .value_parser(value_parser!(usize))
+                    .default_value("64"),
+            )
+            .subcommand(
+                Command::new("ctl")
+                    .about("send an admin command to the running server")
+                    .arg(
+                        Arg::new("socket")
+                            .long("socket")
+                            .help("path of the control socket, in `$XDG_RUNTIME_DIR` by default")
+                            .value_parser(value_parser!(PathBuf)),
+                    )
+                    .arg(
+                        Arg::new("command")
+                            .help("list | kick <user> [reason] | broadcast <text> | stats | shutdown")
+                            .required(true)
+                            .num_args(1..)
+                            .trailing_var_arg(true),
+                    ),
+            )
             .get_matches();
 
         let host = cmd.get_one::<String>("listen lost").cloned().unwrap();
+        let websocket_addr = cmd.get_one::<String>("websocket addr").cloned();
+        let json_addr = cmd.get_one::<String>("json addr").cloned();
+        let unix_socket = cmd.get_one::<PathBuf>("unix socket").cloned();
+        let unix_socket_mode = *cmd.get_one::<u32>("unix socket mode").unwrap();
+        let login_timeout = *cmd.get_one::<u64>("login timeout").unwrap();
+        let overflow_policy = *cmd.get_one::<OverflowPolicy>("overflow policy").unwrap();
+        let overflow_policies = cmd
+            .get_many::<(String, OverflowPolicy)>("overflow policy of")
+            .map(|policies| policies.cloned().collect())
+            .unwrap_or_default();
+        let shards = *cmd.get_one::<usize>("shards").unwrap();
+        let cluster_listen = cmd.get_one::<String>("cluster listen").cloned();
+        let peers = cmd
+            .get_many::<String>("peer")
+            .map(|peers| peers.cloned().collect())
+            .unwrap_or_default();
+        let cluster_secret = cmd
+            .get_one::<String>("cluster secret file")
+            .cloned()
+            .unwrap_or_default();
+        let log_level = cmd.get_one::<String>("log level").cloned().unwrap();
+        let log_json = cmd.get_flag("log json");
+        let log_bodies = cmd.get_flag("log bodies");
+        let metrics_addr = cmd.get_one::<String>("metrics addr").cloned();
+        let console = !cmd.get_flag("no console");
+        let echo_bot = cmd.get_one::<String>("echo bot").cloned();
+        let ban_file = cmd.get_one::<PathBuf>("ban file").cloned();
+        let control_socket = cmd.contains_id("control socket").then(|| {
+            cmd.get_one::<PathBuf>("control socket")
+                .cloned()
+                .unwrap_or_else(default_control_socket)
+        });
+        let user_limits = RateLimits {
+            messages_per_second: *cmd.get_one::<f64>("user rate").unwrap(),
+            bytes_per_second: *cmd.get_one::<f64>("user byte rate").unwrap(),
+        };
+        let ip_limits = RateLimits {
+            messages_per_second: *cmd.get_one::<f64>("ip rate").unwrap(),
+            bytes_per_second: *cmd.get_one::<f64>("ip byte rate").unwrap(),
+        };
+        let decode_limits = DecodeLimits {
+            max_frame_size: *cmd.get_one::<usize>("max frame size").unwrap(),
+            max_name_length: *cmd.get_one::<u8>("max name length").unwrap() as usize,
+        };
+        let cidrs = |name| {
+            cmd.get_many::<Cidr>(name)
+                .map(|cidrs| cidrs.copied().collect())
+                .unwrap_or_default()
+        };
+        let admission_policy = AdmissionPolicy {
+            allow: cidrs("allow"),
+            deny: cidrs("deny"),
+            max_connections: *cmd.get_one::<usize>("max connections").unwrap(),
+            max_per_ip: *cmd.get_one::<usize>("max connections per ip").unwrap(),
+        };
+        let ctl = cmd.subcommand_matches("ctl").map(|ctl| Ctl {
+            socket: ctl
+                .get_one::<PathBuf>("socket")
+                .cloned()
+                .unwrap_or_else(default_control_socket),
+            words: ctl
+                .get_many::<String>("command")
+                .unwrap()
+                .cloned()
+                .collect(),
+        });
 
-        Args { host }
+        Args {
+            host,
+            websocket_addr,
+            json_addr,
+            unix_socket,
+            unix_socket_mode,
+            login_timeout: Duration::from_secs(login_timeout),
+            overflow_policy,
+            overflow_policies,
+            shards,
+            cluster_listen,
+            peers,
+            cluster_secret,
+            log_level,
+            log_json,
+            log_bodies,
+            metrics_addr,
+            console,
+            echo_bot,
+            ban_file,
+            control_socket,
+            user_limits,
+            ip_limits,
+            decode_limits,
+            admission_policy,
+            ctl,
+        }
     }
-}
 
-impl Default for Args {
-    fn default() -> Self {
-        Self {
-            host: Default::default(),
+    /// configure the server by the command line
+    pub fn into_builder(self) -> ServerBuilder {
+        let mut builder = ServerBuilder::new()
+            .listen(self.host)
+            .login_timeout(self.login_timeout)
+            .overflow_policy(self.overflow_policy)
+            .shards(self.shards)
+            .rate_limits(self.user_limits, self.ip_limits)
+            .decode_limits(self.decode_limits)
+            .admission(self.admission_policy)
+            .console(self.console)
+            .log_bodies(self.log_bodies);
+        for (username, policy) in self.overflow_policies {
+            builder = builder.overflow_policy_of(username, policy);
+        }
+        if let Some(addr) = self.websocket_addr {
+            builder = builder.websocket(addr);
+        }
+        if let Some(addr) = self.json_addr {
+            builder = builder.json(addr);
+        }
+        if let Some(path) = self.unix_socket {
+            builder = builder.unix_socket(path, self.unix_socket_mode);
+        }
+        if let Some(listen) = self.cluster_listen {
+            builder = builder.cluster(listen, self.peers, self.cluster_secret);
+        }
+        if let Some(addr) = self.metrics_addr {
+            builder = builder.metrics(addr);
+        }
+        if let Some(path) = self.ban_file {
+            builder = builder.ban_file(path);
+        }
+        if let Some(path) = self.control_socket {
+            builder = builder.control_socket(path);
+        }
+        if let Some(name) = self.echo_bot {
+            builder = builder.plugin(Echo::new(name));
         }
+        builder
     }
 }
@@ -0,0 +1,626 @@
+//! embed the server in another service
+//!
+//! ```no_run
+//! use dc_message_server::ServerBuilder;
+//!
+//! # async fn run() -> std::io::Result<()> {
+//! let server = ServerBuilder::new()
+//!     .listen("127.0.0.1:8233")
+//!     .auth(|username: &str, _peer| match username {
+//!         "root" => Err("reserved username".to_string()),
+//!         _ => Ok(()),
+//!     })
+//!     .handler(|_sender: &str, _receiver: &str, message: String| {
+//!         Some(message.replace("darn", "****"))
+//!     })
+//!     .build()
+//!     .await?;
+//!
+//! println!("{} users online", server.online_users().await.len());
+//! server.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use dvorak_message::message::DecodeLimits;
+use tokio::task::JoinHandle;
+
+use crate::dctor::{
+    admission::AdmissionPolicy,
+    connection::Connection,
+    dctor::{self, Addr, Context},
+    hooks::{Auth, Hooks, MessageHandler},
+    plugin::{self, Plugin},
+    rate_limit::RateLimits,
+    server::{Server, ServerMessage},
+    shard::Shards,
+    supervisor::{OnlineUser, OverflowPolicy, RoutingStats},
+};
+use crate::metrics::Counters;
+
+/// where the server listens, if no address given
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8233";
+
+/// how the server is configured by [`ServerBuilder`]
+#[derive(Debug)]
+pub(crate) struct Config {
+    pub host: String,
+    /// address listening the browser clients over WebSocket, [`None`] if not listened
+    pub websocket_addr: Option<String>,
+    /// address listening the clients speaking JSON lines, [`None`] if not listened
+    pub json_addr: Option<String>,
+    /// path of the Unix-domain socket listening the local clients, [`None`] if not listened
+    pub unix_socket: Option<PathBuf>,
+    /// permission mode of the Unix-domain socket, who could connect to it
+    pub unix_socket_mode: u32,
+    /// how long an incoming client may take to send its Login message
+    pub login_timeout: Duration,
+    /// what to do when the inbox of a client is full
+    pub overflow_policy: OverflowPolicy,
+    /// the policies of the users not following [`Self::overflow_policy`]
+    pub overflow_policies: HashMap<String, OverflowPolicy>,
+    /// count of supervisor shards routing the messages
+    pub shards: usize,
+    /// address listening other nodes of the cluster, [`None`] if the server runs alone
+    pub cluster_listen: Option<String>,
+    /// cluster addresses of other nodes
+    pub peers: Vec<String>,
+    /// secret shared by all nodes of the cluster, a node not knowing it is refused
+    pub cluster_secret: String,
+    /// show the bodies of messages in logs, they are redacted by default
+    pub log_bodies: bool,
+    /// address serving the metrics over HTTP, [`None`] if not served
+    pub metrics_addr: Option<String>,
+    /// read the admin commands from stdin
+    pub console: bool,
+    /// file of the banned users and ips, read on start and `reload`
+    pub ban_file: Option<PathBuf>,
+    /// path of the control socket, [`None`] if not listened
+    pub control_socket: Option<PathBuf>,
+    /// limits of the messages from every user
+    pub user_limits: RateLimits,
+    /// limits of the messages from every ip, shared by its users
+    pub ip_limits: RateLimits,
+    /// limits of the frames from clients, a client sending a larger one is disconnected
+    pub decode_limits: DecodeLimits,
+    /// which incoming connections are accepted
+    pub admission_policy: AdmissionPolicy,
+    /// counters of this server, served as metrics
+    pub counters: Arc<Counters>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            host: DEFAULT_LISTEN.to_string(),
+            websocket_addr: None,
+            json_addr: None,
+            unix_socket: None,
+            unix_socket_mode: 0o660,
+            login_timeout: Duration::from_secs(10),
+            overflow_policy: OverflowPolicy::default(),
+            overflow_policies: HashMap::new(),
+            shards: 1,
+            cluster_listen: None,
+            peers: vec![],
+            cluster_secret: String::new(),
+            log_bodies: false,
+            metrics_addr: None,
+            console: false,
+            ban_file: None,
+            control_socket: None,
+            user_limits: RateLimits::default(),
+            ip_limits: RateLimits::default(),
+            decode_limits: DecodeLimits::default(),
+            admission_policy: AdmissionPolicy::default(),
+            counters: Arc::default(),
+        }
+    }
+}
+
+/// configure and start a server
+///
+/// nothing is limited by default, except the frames by [`DecodeLimits::default`],
+/// and the admin console is never read from stdin
+#[derive(Debug, Default)]
+pub struct ServerBuilder {
+    config: Config,
+    hooks: Hooks,
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// address listening the clients over TCP, port 0 to pick a free one,
+    /// see [`ServerHandle::local_addr`]
+    pub fn listen(mut self, addr: impl Into<String>) -> Self {
+        self.config.host = addr.into();
+        self
+    }
+
+    /// address listening the browser clients over WebSocket
+    pub fn websocket(mut self, addr: impl Into<String>) -> Self {
+        self.config.websocket_addr = Some(addr.into());
+        self
+    }
+
+    /// address listening the clients speaking JSON lines
+    pub fn json(mut self, addr: impl Into<String>) -> Self {
+        self.config.json_addr = Some(addr.into());
+        self
+    }
+
+    /// path of the Unix-domain socket listening the local clients, and its permission mode
+    pub fn unix_socket(mut self, path: impl Into<PathBuf>, mode: u32) -> Self {
+        self.config.unix_socket = Some(path.into());
+        self.config.unix_socket_mode = mode;
+        self
+    }
+
+    /// how long an incoming client may take to send its Login message
+    pub fn login_timeout(mut self, login_timeout: Duration) -> Self {
+        self.config.login_timeout = login_timeout;
+        self
+    }
+
+    /// count of supervisor shards routing the messages
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.config.shards = shards;
+        self
+    }
+
+    /// what to do when the inbox of a client is full
+    pub fn overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.config.overflow_policy = overflow_policy;
+        self
+    }
+
+    /// what to do when the inbox of the user is full, instead of [`Self::overflow_policy`]
+    pub fn overflow_policy_of(
+        mut self,
+        username: impl Into<String>,
+        overflow_policy: OverflowPolicy,
+    ) -> Self {
+        self.config
+            .overflow_policies
+            .insert(username.into(), overflow_policy);
+        self
+    }
+
+    /// limits of the messages from every user and every ip
+    pub fn rate_limits(mut self, user_limits: RateLimits, ip_limits: RateLimits) -> Self {
+        self.config.user_limits = user_limits;
+        self.config.ip_limits = ip_limits;
+        self
+    }
+
+    /// limits of the frames from clients
+    pub fn decode_limits(mut self, decode_limits: DecodeLimits) -> Self {
+        self.config.decode_limits = decode_limits;
+        self
+    }
+
+    /// which incoming connections are accepted
+    pub fn admission(mut self, admission_policy: AdmissionPolicy) -> Self {
+        self.config.admission_policy = admission_policy;
+        self
+    }
+
+    /// file of the banned users and ips
+    pub fn ban_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.ban_file = Some(path.into());
+        self
+    }
+
+    /// path of the control socket for `ctl`
+    pub fn control_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.control_socket = Some(path.into());
+        self
+    }
+
+    /// read the admin commands from stdin
+    pub fn console(mut self, console: bool) -> Self {
+        self.config.console = console;
+        self
+    }
+
+    /// show the bodies of messages in the logs of this server, they are redacted by default
+    pub fn log_bodies(mut self, log_bodies: bool) -> Self {
+        self.config.log_bodies = log_bodies;
+        self
+    }
+
+    /// address serving the metrics over HTTP
+    pub fn metrics(mut self, addr: impl Into<String>) -> Self {
+        self.config.metrics_addr = Some(addr.into());
+        self
+    }
+
+    /// join a cluster, listening other nodes at `listen`,
+    /// the nodes prove to each other they know the `secret`
+    pub fn cluster(
+        mut self,
+        listen: impl Into<String>,
+        peers: Vec<String>,
+        secret: impl Into<String>,
+    ) -> Self {
+        self.config.cluster_listen = Some(listen.into());
+        self.config.peers = peers;
+        self.config.cluster_secret = secret.into();
+        self
+    }
+
+    /// decide who could login, anyone could by default
+    pub fn auth(mut self, auth: impl Auth) -> Self {
+        self.hooks.auth = Some(Arc::new(auth));
+        self
+    }
+
+    /// see every text message before it is routed,
+    /// the handlers run in the order they are added
+    pub fn handler(mut self, handler: impl MessageHandler) -> Self {
+        self.hooks.handlers.push(Arc::new(handler));
+        self
+    }
+
+    /// add a bot living in the server as a virtual user,
+    /// it sees the routed messages after the handlers added before it
+    pub fn plugin(mut self, plugin: impl Plugin) -> Self {
+        let plugin: Arc<dyn Plugin> = Arc::new(plugin);
+        self.hooks
+            .handlers
+            .push(Arc::new(plugin::Route(plugin.clone())));
+        self.hooks.plugins.push(plugin);
+        self
+    }
+
+    /// bind the listeners and start the server
+    pub async fn build(self) -> io::Result<ServerHandle> {
+        let server = Server::new(&self.config, self.hooks).await?;
+        let local_addr = server.local_addr()?;
+        let shards = server.shards().clone();
+
+        let ctx = Context::new(10);
+        let server_addr = ctx.addr();
+        let task = dctor::spawn(server, ctx);
+        Ok(ServerHandle {
+            server: server_addr,
+            shards,
+            local_addr,
+            task,
+        })
+    }
+}
+
+/// a running server, it keeps running even if the handle is dropped
+pub struct ServerHandle {
+    server: Addr<Server>,
+    shards: Shards,
+    local_addr: SocketAddr,
+    task: JoinHandle<Server>,
+}
+
+impl Debug for ServerHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerHandle")
+            .field("local_addr", &self.local_addr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ServerHandle {
+    /// address of the TCP listener
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// hand a client over a transport the server does not listen itself, like an in-memory pipe,
+    /// it logs in as the clients of listeners
+    pub async fn connect(&self, connection: Connection) -> io::Result<()> {
+        self.server
+            .send(ServerMessage::Connect(connection))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::NotConnected, "server quit"))
+    }
+
+    /// the users connected to this server
+    pub async fn online_users(&self) -> Vec<OnlineUser> {
+        self.shards.list().await
+    }
+
+    /// the counters of routing
+    pub async fn stats(&self) -> RoutingStats {
+        self.shards.stats().await
+    }
+
+    /// disconnect the user after telling the reason,
+    /// return whether the user is connected
+    pub async fn kick(&self, username: &str, reason: &str) -> bool {
+        self.shards.kick(username, reason).await
+    }
+
+    /// send the text from server to every user, return the count of users
+    pub async fn broadcast(&self, text: &str) -> usize {
+        self.shards.say(text).await
+    }
+
+    /// quit the server, and wait until every client is closed
+    pub async fn shutdown(self) {
+        let _ = self.server.send(ServerMessage::Quit).await;
+        self.wait().await;
+    }
+
+    /// wait until the server quits, by the console or the control socket
+    pub async fn wait(self) {
+        if let Err(e) = self.task.await {
+            if e.is_panic() {
+                std::panic::resume_unwind(e.into_panic());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dvorak_message::message::{Message, MessageReader, MessageType};
+    use tokio::io::DuplexStream;
+
+    use super::*;
+    use crate::dctor::{
+        plugin::{Echo, PluginHandle},
+        server::SERVER_NAME,
+    };
+
+    async fn send_login(server: &ServerHandle, username: &str) -> MessageReader<DuplexStream> {
+        let (client, pipe) = tokio::io::duplex(4096);
+        let peer = "127.0.0.1:50000".parse().ok();
+        server.connect(Connection::new(pipe, peer)).await.unwrap();
+        let mut client = MessageReader::new(client);
+        let login = Message::new(MessageType::Login, username.to_string(), String::new());
+        Message::send(client.get_mut(), login).await.unwrap();
+        client
+    }
+
+    /// login and wait until it is accepted
+    async fn login(server: &ServerHandle, username: &str) -> MessageReader<DuplexStream> {
+        let mut client = send_login(server, username).await;
+        let accepted = client.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Login, accepted.message_type);
+        assert_eq!(SERVER_NAME, accepted.username);
+        client
+    }
+
+    /// wait until the users are handed to their supervisors
+    async fn wait_online(server: &ServerHandle, count: usize) {
+        while server.online_users().await.len() < count {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+            .listen("127.0.0.1:0")
+            .login_timeout(Duration::from_secs(1))
+    }
+
+    #[test]
+    fn default_builder_is_usable() {
+        let config = ServerBuilder::default().config;
+        assert_eq!(DEFAULT_LISTEN, config.host);
+        assert_eq!(Duration::from_secs(10), config.login_timeout);
+        assert_eq!(1, config.shards);
+    }
+
+    #[tokio::test]
+    async fn auth_refuses_login() {
+        let server = builder()
+            .auth(|username: &str, _peer| match username {
+                "mallory" => Err("go away".to_string()),
+                _ => Ok(()),
+            })
+            .build()
+            .await
+            .unwrap();
+        assert_ne!(0, server.local_addr().port());
+
+        let mut mallory = send_login(&server, "mallory").await;
+        let notice = mallory.read().await.unwrap().unwrap();
+        assert_eq!(SERVER_NAME, notice.username);
+        assert_eq!(
+            MessageType::Error("go away".to_string()),
+            notice.message_type
+        );
+        assert!(mallory.read().await.unwrap().is_none());
+
+        let _alice = login(&server, "alice").await;
+        wait_online(&server, 1).await;
+        let users = server.online_users().await;
+        assert_eq!("alice", users[0].username);
+
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn handlers_rewrite_and_drop_messages() {
+        let server = builder()
+            .handler(|_: &str, receiver: &str, message: String| {
+                (receiver != "nobody").then_some(message)
+            })
+            .handler(|_: &str, _: &str, message: String| Some(message.replace("darn", "****")))
+            .build()
+            .await
+            .unwrap();
+        let mut alice = login(&server, "alice").await;
+        let mut bob = login(&server, "bob").await;
+        wait_online(&server, 2).await;
+
+        for (receiver, text) in [("nobody", "lost"), ("bob", "darn it")] {
+            let text = MessageType::Text(text.to_string());
+            let message = Message::new(text, "alice".to_string(), receiver.to_string());
+            Message::send(alice.get_mut(), message).await.unwrap();
+        }
+        let message = bob.read().await.unwrap().unwrap();
+        assert_eq!("alice", message.username);
+        assert_eq!(
+            MessageType::Text("**** it".to_string()),
+            message.message_type
+        );
+
+        // the message to nobody never reached the supervisor
+        let stats = server.stats().await;
+        assert_eq!(0, stats.undeliverable + stats.spilled);
+
+        assert!(server.kick("bob", "bye").await);
+        let notice = bob.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Text("bye".to_string()), notice.message_type);
+        let logout = bob.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Logout, logout.message_type);
+
+        server.shutdown().await;
+    }
+
+    struct NoSpam;
+
+    #[async_trait::async_trait]
+    impl Plugin for NoSpam {
+        fn name(&self) -> &str {
+            "nospam"
+        }
+
+        async fn on_message(&self, _handle: &PluginHandle, _sender: &str, _message: String) {}
+
+        fn on_route(&self, _sender: &str, _receiver: &str, message: String) -> Option<String> {
+            (!message.contains("spam")).then_some(message)
+        }
+    }
+
+    #[tokio::test]
+    async fn plugins_are_virtual_users() {
+        let server = builder()
+            .plugin(NoSpam)
+            .plugin(Echo::new("echo"))
+            .build()
+            .await
+            .unwrap();
+        let mut alice = login(&server, "alice").await;
+        wait_online(&server, 3).await;
+
+        for text in ["spam", "hello echo"] {
+            let text = MessageType::Text(text.to_string());
+            let message = Message::new(text, "alice".to_string(), "echo".to_string());
+            Message::send(alice.get_mut(), message).await.unwrap();
+        }
+        let message = alice.read().await.unwrap().unwrap();
+        assert_eq!("echo", message.username);
+        assert_eq!(
+            MessageType::Text("hello echo".to_string()),
+            message.message_type
+        );
+
+        // nobody could login as a plugin
+        let mut mallory = send_login(&server, "echo").await;
+        let notice = mallory.read().await.unwrap().unwrap();
+        assert_eq!(
+            MessageType::Error("echo is reserved".to_string()),
+            notice.message_type
+        );
+
+        server.shutdown().await;
+    }
+
+    /// replies every message, but panics on "panic"
+    struct Fragile;
+
+    #[async_trait::async_trait]
+    impl Plugin for Fragile {
+        fn name(&self) -> &str {
+            "fragile"
+        }
+
+        async fn on_message(&self, handle: &PluginHandle, sender: &str, message: String) {
+            assert_ne!("panic", message, "plugin panicked on purpose");
+            let _ = handle.send_text(sender, &message);
+        }
+    }
+
+    /// replies every message 30 times, more than a user is allowed to send at once
+    struct Chatty;
+
+    #[async_trait::async_trait]
+    impl Plugin for Chatty {
+        fn name(&self) -> &str {
+            "chatty"
+        }
+
+        async fn on_message(&self, handle: &PluginHandle, sender: &str, message: String) {
+            for _ in 0..30 {
+                let _ = handle.send_text(sender, &message);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn plugin_is_not_rate_limited() {
+        let limits = RateLimits {
+            messages_per_second: 1.0,
+            bytes_per_second: 0.0,
+        };
+        let server = builder()
+            .rate_limits(limits, RateLimits::default())
+            .plugin(Chatty)
+            .build()
+            .await
+            .unwrap();
+        let mut alice = login(&server, "alice").await;
+        wait_online(&server, 2).await;
+
+        let text = MessageType::Text("hi".to_string());
+        let message = Message::new(text.clone(), "alice".to_string(), "chatty".to_string());
+        Message::send(alice.get_mut(), message).await.unwrap();
+        for _ in 0..30 {
+            let message = alice.read().await.unwrap().unwrap();
+            assert_eq!("chatty", message.username);
+            assert_eq!(text, message.message_type);
+        }
+
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn panicked_plugin_is_restarted() {
+        let server = builder().plugin(Fragile).build().await.unwrap();
+        let mut alice = login(&server, "alice").await;
+        wait_online(&server, 2).await;
+
+        let text = MessageType::Text("panic".to_string());
+        let message = Message::new(text, "alice".to_string(), "fragile".to_string());
+        Message::send(alice.get_mut(), message).await.unwrap();
+
+        // the texts sent before the plugin is restarted are lost with it
+        let text = MessageType::Text("hello again".to_string());
+        let message = loop {
+            let message = Message::new(text.clone(), "alice".to_string(), "fragile".to_string());
+            Message::send(alice.get_mut(), message).await.unwrap();
+            let read = tokio::time::timeout(Duration::from_millis(100), alice.read()).await;
+            if let Ok(message) = read {
+                break message.unwrap().unwrap();
+            }
+        };
+        assert_eq!("fragile", message.username);
+        assert_eq!(text, message.message_type);
+
+        server.shutdown().await;
+    }
+}
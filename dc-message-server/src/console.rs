@@ -0,0 +1,252 @@
+//! the admin console, reading commands from the stdin of server
+//!
+//! every command is routed to the supervisors, and its result is printed to stdout
+
+use std::{
+    fmt::Write,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::{
+    fs,
+    io::{stdin, AsyncBufReadExt, AsyncWriteExt, BufReader},
+};
+
+use crate::dctor::{
+    dctor::Addr,
+    server::{Server, ServerMessage},
+    shard::Shards,
+    supervisor::Ban,
+};
+
+const HELP: &str = "\
+commands:
+  list                  online users with their addresses and connect time
+  kick <user> [reason]  disconnect the user, telling the reason
+  ban <user|ip>         disconnect and refuse the user or ip
+  say <text>            send the text to every user
+  stats                 counters of routing
+  reload                read the bans from the ban file again
+  quit                  quit the server";
+
+/// command typed in the console
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Command {
+    Help,
+    List,
+    Kick { username: String, reason: String },
+    Ban(Ban),
+    Say(String),
+    Stats,
+    Reload,
+    Quit,
+}
+
+impl Command {
+    /// parse a line of console, return `Ok(None)` if it is blank
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+
+        let command = match name {
+            "help" => Command::Help,
+            "list" => Command::List,
+            "kick" => {
+                let (username, reason) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                if username.is_empty() {
+                    return Err("usage: kick <user> [reason]".to_string());
+                }
+                let reason = match reason.trim() {
+                    "" => "kicked by admin",
+                    reason => reason,
+                };
+                Command::Kick {
+                    username: username.to_string(),
+                    reason: reason.to_string(),
+                }
+            }
+            "ban" if !rest.is_empty() && !rest.contains(char::is_whitespace) => {
+                Command::Ban(Ban::parse(rest))
+            }
+            "ban" => return Err("usage: ban <user|ip>".to_string()),
+            "say" if !rest.is_empty() => Command::Say(rest.to_string()),
+            "say" => return Err("usage: say <text>".to_string()),
+            "stats" => Command::Stats,
+            "reload" => Command::Reload,
+            "quit" => Command::Quit,
+            other => {
+                return Err(format!(
+                    "unknown command: {other}, type `help` for commands"
+                ))
+            }
+        };
+        Ok(Some(command))
+    }
+}
+
+/// read commands from stdin until `quit`
+pub(crate) async fn listen(server: Addr<Server>, shards: Shards, ban_file: Option<PathBuf>) {
+    let mut lines = BufReader::new(stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let output = match Command::parse(&line) {
+            Ok(None) => continue,
+            Ok(Some(Command::Quit)) => {
+                let _ = server.send(ServerMessage::Quit).await;
+                return;
+            }
+            Ok(Some(command)) => execute(command, &shards, ban_file.as_deref()).await,
+            Err(e) => e,
+        };
+        println!("{output}");
+    }
+}
+
+/// run the command, and return what to print
+async fn execute(command: Command, shards: &Shards, ban_file: Option<&Path>) -> String {
+    match command {
+        Command::Help => HELP.to_string(),
+        Command::List => {
+            let mut users = shards.list().await;
+            users.sort_by(|a, b| a.username.cmp(&b.username));
+
+            let mut output = format!("{} user(s) online", users.len());
+            for user in users {
+                let peer = user
+                    .peer
+                    .map_or_else(|| "unknown".to_string(), |peer| peer.to_string());
+                let online = format_duration(user.online);
+                let _ = write!(
+                    output,
+                    "\n  {}  {peer}  connected {online} ago",
+                    user.username
+                );
+            }
+            output
+        }
+        Command::Kick { username, reason } => match shards.kick(&username, &reason).await {
+            true => format!("{username} kicked: {reason}"),
+            false => format!("{username} is not online"),
+        },
+        Command::Ban(ban) => {
+            let kicked = shards.ban(&ban).await;
+            let saved = match ban_file {
+                Some(path) => match append_ban(path, &ban).await {
+                    Ok(()) => String::new(),
+                    Err(e) => format!(", but saving to ban file failed: {e}"),
+                },
+                None => String::new(),
+            };
+            format!("{ban} banned, {} client(s) kicked{saved}", kicked.len())
+        }
+        Command::Say(text) => format!("said to {} user(s)", shards.say(&text).await),
+        Command::Stats => {
+            let stats = shards.stats().await;
+            let online = shards.inboxes().await.len();
+            format!("{online} user(s) online, {stats:?}")
+        }
+        Command::Reload => {
+            let Some(path) = ban_file else {
+                return "no ban file given, start the server with --ban-file".to_string();
+            };
+            match load_bans(path).await {
+                Ok(bans) => {
+                    let kicked = shards.reload(&bans).await;
+                    format!(
+                        "{} ban(s) loaded, {} client(s) kicked",
+                        bans.len(),
+                        kicked.len()
+                    )
+                }
+                Err(e) => format!("reload failure: {e}"),
+            }
+        }
+        Command::Quit => unreachable!("quit is handled by listen"),
+    }
+}
+
+/// read the bans from file, a user or ip on every line,
+/// blank lines and lines starting with `#` are skipped
+pub(crate) async fn load_bans(path: &Path) -> io::Result<Vec<Ban>> {
+    let bans = match fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    Ok(bans
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Ban::parse)
+        .collect())
+}
+
+async fn append_ban(path: &Path, ban: &Ban) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(format!("{ban}\n").as_bytes()).await
+}
+
+/// format like `1h2m3s`
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match (hours, minutes) {
+        (0, 0) => format!("{seconds}s"),
+        (0, _) => format!("{minutes}m{seconds}s"),
+        _ => format!("{hours}h{minutes}m{seconds}s"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Ok(None), Command::parse("  "));
+        assert_eq!(Ok(Some(Command::List)), Command::parse("list"));
+        assert_eq!(
+            Ok(Some(Command::Kick {
+                username: "alice".to_string(),
+                reason: "too noisy".to_string(),
+            })),
+            Command::parse("kick alice  too noisy")
+        );
+        assert_eq!(
+            Ok(Some(Command::Ban(Ban::Ip("127.0.0.1".parse().unwrap())))),
+            Command::parse("ban 127.0.0.1")
+        );
+        assert_eq!(
+            Ok(Some(Command::Ban(Ban::User("bob".to_string())))),
+            Command::parse("ban bob")
+        );
+        assert_eq!(
+            Ok(Some(Command::Say("hello all".to_string()))),
+            Command::parse("say hello all")
+        );
+    }
+
+    #[test]
+    fn parse_wrong_commands() {
+        assert!(Command::parse("kick").is_err());
+        assert!(Command::parse("ban a b").is_err());
+        assert!(Command::parse("say").is_err());
+        assert!(Command::parse("dance").is_err());
+    }
+
+    #[test]
+    fn format_durations() {
+        assert_eq!("5s", format_duration(Duration::from_secs(5)));
+        assert_eq!("2m5s", format_duration(Duration::from_secs(125)));
+        assert_eq!("1h0m5s", format_duration(Duration::from_secs(3605)));
+    }
+}
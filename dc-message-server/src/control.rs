@@ -0,0 +1,368 @@
+//! the remote admin API, over a Unix-domain control socket
+//!
+//! # Protocol
+//! every request is a JSON object on a single line, tagged by `command`:
+//! - `{"command": "list"}`
+//! - `{"command": "kick", "user": "alice", "reason": "too noisy"}`, the reason is optional
+//! - `{"command": "broadcast", "text": "hello"}`
+//! - `{"command": "stats"}`
+//! - `{"command": "shutdown"}`
+//!
+//! every response is a JSON object on a single line,
+//! `{"ok": true, "result": ...}` or `{"ok": false, "error": "..."}`
+
+use std::{
+    io,
+    os::unix::fs::{DirBuilderExt, MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::dctor::{
+    connection::bind_unix,
+    dctor::Addr,
+    server::{Server, ServerMessage},
+    shard::Shards,
+};
+
+/// where the control socket is, if no path given:
+/// in `$XDG_RUNTIME_DIR`, otherwise in a directory of the user under the temporary directory
+pub fn default_socket() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("dc-message-server.sock"),
+        _ => user_dir().join("control.sock"),
+    }
+}
+
+/// the directory of the user under the temporary directory, named by `$USER`, or by the uid
+fn user_dir() -> PathBuf {
+    let user = std::env::var("USER")
+        .ok()
+        .filter(|user| !user.is_empty())
+        .unwrap_or_else(|| rustix::process::getuid().as_raw().to_string());
+    std::env::temp_dir().join(format!("dc-message-server-{user}"))
+}
+
+/// refuse the directory unless it is a directory of this user only, not a link,
+/// since anyone could make it first under the shared temporary directory
+fn check_private(dir: &Path) -> io::Result<()> {
+    let metadata = std::fs::symlink_metadata(dir)?;
+    let uid = rustix::process::getuid().as_raw();
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        let error = format!(
+            "{} is not a directory of uid {uid} only, refuse to bind the control socket in it",
+            dir.display()
+        );
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, error));
+    }
+    Ok(())
+}
+
+/// request of the control protocol
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub(crate) enum ControlRequest {
+    /// users online, with their addresses and connect time
+    List,
+    /// disconnect the user, telling the reason
+    Kick {
+        user: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// send the text to every user
+    Broadcast { text: String },
+    /// counters of routing
+    Stats,
+    /// quit the server
+    Shutdown,
+}
+
+impl ControlRequest {
+    /// parse the words of `ctl` subcommand, like `kick alice too noisy`
+    pub fn from_words(words: &[String]) -> Result<Self, String> {
+        let Some((command, rest)) = words.split_first() else {
+            return Err("missing command".to_string());
+        };
+        let request = match (command.as_str(), rest) {
+            ("list", []) => ControlRequest::List,
+            ("kick", [user, reason @ ..]) => ControlRequest::Kick {
+                user: user.clone(),
+                reason: (!reason.is_empty()).then(|| reason.join(" ")),
+            },
+            ("broadcast", [_, ..]) => ControlRequest::Broadcast {
+                text: rest.join(" "),
+            },
+            ("stats", []) => ControlRequest::Stats,
+            ("shutdown", []) => ControlRequest::Shutdown,
+            ("kick", _) => return Err("usage: kick <user> [reason]".to_string()),
+            ("broadcast", _) => return Err("usage: broadcast <text>".to_string()),
+            (command, _) => return Err(format!("unknown command: {command}")),
+        };
+        Ok(request)
+    }
+}
+
+/// the arguments of `ctl` subcommand
+#[derive(Debug)]
+pub struct Ctl {
+    pub socket: PathBuf,
+    pub words: Vec<String>,
+}
+
+/// bind the control socket, only the owner of server could connect to it,
+/// its directory is created if missing, only the owner could enter it.
+/// the directory of the user under the temporary directory is checked to be private
+pub(crate) fn bind(path: &Path) -> io::Result<UnixListener> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+            _ => {}
+        }
+        if dir == user_dir() {
+            check_private(dir)?;
+        }
+    }
+    bind_unix(path, 0o600)
+}
+
+/// answer the requests on `listener` until the server quits
+pub(crate) async fn serve(listener: UnixListener, server: Addr<Server>, shards: Shards) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let (server, shards) = (server.clone(), shards.clone());
+        tokio::spawn(async move {
+            if let Err(e) = answer(stream, &server, &shards).await {
+                tracing::debug!(error = %e, "control connection failure");
+            }
+        });
+    }
+}
+
+/// answer every request line of the connection
+async fn answer(stream: UnixStream, server: &Addr<Server>, shards: &Shards) -> io::Result<()> {
+    let (read_half, mut write_half) = stream.into_split();
+    let mut lines = BufReader::new(read_half).lines();
+    while let Some(line) = lines.next_line().await? {
+        let result = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => {
+                tracing::info!(?request, "control request");
+                execute(request, server, shards).await
+            }
+            Err(e) => Err(format!("invalid request: {e}")),
+        };
+        let response = match result {
+            Ok(result) => json!({ "ok": true, "result": result }),
+            Err(error) => json!({ "ok": false, "error": error }),
+        };
+        write_half
+            .write_all(format!("{response}\n").as_bytes())
+            .await?;
+    }
+    Ok(())
+}
+
+async fn execute(
+    request: ControlRequest,
+    server: &Addr<Server>,
+    shards: &Shards,
+) -> Result<Value, String> {
+    match request {
+        ControlRequest::List => {
+            let mut users = shards.list().await;
+            users.sort_by(|a, b| a.username.cmp(&b.username));
+            let users: Vec<Value> = users
+                .into_iter()
+                .map(|user| {
+                    json!({
+                        "user": user.username,
+                        "peer": user.peer,
+                        "online_secs": user.online.as_secs(),
+                    })
+                })
+                .collect();
+            Ok(Value::from(users))
+        }
+        ControlRequest::Kick { user, reason } => {
+            let reason = reason.unwrap_or_else(|| "kicked by admin".to_string());
+            match shards.kick(&user, &reason).await {
+                true => Ok(json!({ "user": user, "reason": reason })),
+                false => Err(format!("{user} is not online")),
+            }
+        }
+        ControlRequest::Broadcast { text } => Ok(json!({ "receivers": shards.say(&text).await })),
+        ControlRequest::Stats => {
+            let stats = shards.stats().await;
+            let online = shards.inboxes().await.len();
+            Ok(json!({ "online": online, "routing": stats }))
+        }
+        ControlRequest::Shutdown => {
+            server
+                .send(ServerMessage::Quit)
+                .await
+                .map_err(|_| "server is quitting already".to_string())?;
+            Ok(Value::Null)
+        }
+    }
+}
+
+/// send the request of `ctl` subcommand, and return the response
+pub(crate) async fn request(socket: &Path, request: &ControlRequest) -> io::Result<Value> {
+    let stream = UnixStream::connect(socket).await?;
+    let (read_half, mut write_half) = stream.into_split();
+    let request = serde_json::to_string(request)?;
+    write_half
+        .write_all(format!("{request}\n").as_bytes())
+        .await?;
+
+    let response = BufReader::new(read_half)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "no response"))?;
+    Ok(serde_json::from_str(&response)?)
+}
+
+/// run the `ctl` subcommand, print the result and return the exit code
+pub async fn ctl(ctl: Ctl) -> i32 {
+    let request = match ControlRequest::from_words(&ctl.words) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("{e}");
+            return 2;
+        }
+    };
+    match self::request(&ctl.socket, &request).await {
+        Ok(response) if response["ok"] == true => {
+            if !response["result"].is_null() {
+                println!("{:#}", response["result"]);
+            }
+            0
+        }
+        Ok(response) => {
+            eprintln!("{}", response["error"].as_str().unwrap_or("unknown error"));
+            1
+        }
+        Err(e) => {
+            eprintln!("connect to {} failure: {e}", ctl.socket.display());
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dctor::{
+        client::ClientConfig,
+        dctor::Context,
+        supervisor::{ClientSupervisor, OverflowPolicy},
+        testing::new_client,
+    };
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn parse_words() {
+        assert_eq!(
+            Ok(ControlRequest::Kick {
+                user: "alice".to_string(),
+                reason: Some("too noisy".to_string()),
+            }),
+            ControlRequest::from_words(&words("kick alice too noisy"))
+        );
+        assert_eq!(
+            Ok(ControlRequest::Broadcast {
+                text: "hello all".to_string()
+            }),
+            ControlRequest::from_words(&words("broadcast hello all"))
+        );
+        assert!(ControlRequest::from_words(&words("kick")).is_err());
+        assert!(ControlRequest::from_words(&words("list all")).is_err());
+    }
+
+    #[test]
+    fn requests_are_tagged_by_command() {
+        let request = ControlRequest::Kick {
+            user: "alice".to_string(),
+            reason: None,
+        };
+
+        assert_eq!(
+            r#"{"command":"kick","user":"alice"}"#,
+            serde_json::to_string(&request).unwrap()
+        );
+        assert_eq!(
+            Ok(ControlRequest::Stats),
+            serde_json::from_str(r#"{"command":"stats"}"#).map_err(|_| ())
+        );
+    }
+
+    #[test]
+    fn only_private_directory_is_trusted() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("dc-private-{}", std::process::id()));
+        let link = dir.with_extension("link");
+        let _ = std::fs::remove_dir(&dir);
+        let _ = std::fs::remove_file(&link);
+        std::fs::DirBuilder::new().mode(0o700).create(&dir).unwrap();
+        check_private(&dir).unwrap();
+
+        std::os::unix::fs::symlink(&dir, &link).unwrap();
+        let error = check_private(&link).unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, error.kind());
+
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let error = check_private(&dir).unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, error.kind());
+
+        std::fs::remove_file(&link).unwrap();
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn requests_over_control_socket() {
+        let shards =
+            ClientSupervisor::start_shards(1, OverflowPolicy::Spill, None, ClientConfig::default());
+        let _peer = new_client(&shards, "alice").await;
+
+        // the server is not running, Quit is kept in its inbox
+        let mut server_ctx: Context<Server> = Context::new(1);
+        let server = server_ctx.addr();
+
+        let socket = std::env::temp_dir().join(format!("dc-control-{}.sock", std::process::id()));
+        let listener = bind(&socket).unwrap();
+        tokio::spawn(serve(listener, server, shards));
+
+        let list = request(&socket, &ControlRequest::List).await.unwrap();
+        assert_eq!(true, list["ok"]);
+        assert_eq!("alice", list["result"][0]["user"]);
+
+        let kick = ControlRequest::Kick {
+            user: "nobody".to_string(),
+            reason: None,
+        };
+        let response = request(&socket, &kick).await.unwrap();
+        assert_eq!(false, response["ok"]);
+        assert_eq!("nobody is not online", response["error"]);
+
+        let stats = request(&socket, &ControlRequest::Stats).await.unwrap();
+        assert_eq!(1, stats["result"]["online"]);
+
+        let response = request(&socket, &ControlRequest::Shutdown).await.unwrap();
+        assert_eq!(true, response["ok"]);
+        assert!(matches!(server_ctx.recv().await, Some(ServerMessage::Quit)));
+        let _ = std::fs::remove_file(&socket);
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// a block of addresses, like `10.0.0.0/8` or `::1/128`,
+/// a single address is a block of itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // an ipv4 address may come as an ipv4-mapped ipv6 address
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                Self::mask(u32::from(network), u32::from(ip), self.prefix_len, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                Self::mask(u128::from(network), u128::from(ip), self.prefix_len, 128)
+            }
+            _ => false,
+        }
+    }
+
+    /// are the first `prefix_len` bits of both same?
+    fn mask<T>(network: T, ip: T, prefix_len: u8, bits: u32) -> bool
+    where
+        T: Into<u128>,
+    {
+        let shift = bits - prefix_len as u32;
+        let (network, ip) = (network.into(), ip.into());
+        shift == bits || network >> shift == ip >> shift
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (network, prefix_len) = s.split_once('/').unwrap_or((s, ""));
+        let network = network
+            .parse::<IpAddr>()
+            .map_err(|e| format!("invalid address {network}: {e}"))?
+            .to_canonical();
+        let max_len = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len = match prefix_len {
+            "" => max_len,
+            prefix_len => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| format!("invalid prefix length {prefix_len}"))?,
+        };
+        Ok(Cidr {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// which incoming connections are accepted
+#[derive(Debug, Clone, Default)]
+pub struct AdmissionPolicy {
+    /// only the addresses in these blocks are accepted, any address if empty
+    pub allow: Vec<Cidr>,
+    /// the addresses in these blocks are refused, even though they are allowed
+    pub deny: Vec<Cidr>,
+    /// max connections of the server, 0 for unlimited
+    pub max_connections: usize,
+    /// max connections from an ip, 0 for unlimited
+    pub max_per_ip: usize,
+}
+
+/// why an incoming connection is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    Denied,
+    ServerFull,
+    TooManyFromIp,
+}
+
+impl Refusal {
+    /// short reason for logs
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Refusal::Denied => "denied",
+            Refusal::ServerFull => "server full",
+            Refusal::TooManyFromIp => "too many from ip",
+        }
+    }
+}
+
+impl Display for Refusal {
+    /// the notice told to the refused client
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let notice = match self {
+            Refusal::Denied => "sorry, your address is not allowed to connect",
+            Refusal::ServerFull => "sorry, the server is full, please try again later",
+            Refusal::TooManyFromIp => {
+                "sorry, there are too many connections from your address, please close some of them"
+            }
+        };
+        f.write_str(notice)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Connections {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// admit the incoming connections by [`AdmissionPolicy`], and count them
+#[derive(Debug, Clone, Default)]
+pub struct Admission {
+    policy: Arc<AdmissionPolicy>,
+    connections: Arc<Mutex<Connections>>,
+}
+
+impl Admission {
+    pub fn new(policy: AdmissionPolicy) -> Self {
+        Admission {
+            policy: Arc::new(policy),
+            connections: Arc::default(),
+        }
+    }
+
+    /// admit a connection from `ip`,
+    /// it is counted until the returned [`Permit`] is dropped
+    pub fn admit(&self, ip: IpAddr) -> Result<Permit, Refusal> {
+        let ip = ip.to_canonical();
+        let policy = &self.policy;
+        let allowed = policy.allow.is_empty() || policy.allow.iter().any(|cidr| cidr.contains(ip));
+        if !allowed || policy.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return Err(Refusal::Denied);
+        }
+        self.count(Some(ip))
+    }
+
+    /// admit a local connection, like the one over a Unix-domain socket,
+    /// only the max connections of the server applies to it
+    pub fn admit_local(&self) -> Result<Permit, Refusal> {
+        self.count(None)
+    }
+
+    fn count(&self, ip: Option<IpAddr>) -> Result<Permit, Refusal> {
+        let policy = &self.policy;
+        let mut connections = self.connections.lock().unwrap();
+        if policy.max_connections != 0 && connections.total >= policy.max_connections {
+            return Err(Refusal::ServerFull);
+        }
+        if let Some(ip) = ip {
+            let from_ip = connections.per_ip.entry(ip).or_default();
+            if policy.max_per_ip != 0 && *from_ip >= policy.max_per_ip {
+                return Err(Refusal::TooManyFromIp);
+            }
+            *from_ip += 1;
+        }
+        connections.total += 1;
+
+        Ok(Permit {
+            connections: self.connections.clone(),
+            ip,
+        })
+    }
+}
+
+/// an admitted connection, it is not counted any more once dropped
+#[derive(Debug)]
+pub struct Permit {
+    connections: Arc<Mutex<Connections>>,
+    /// [`None`] for a local connection
+    ip: Option<IpAddr>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut connections = self.connections.lock().unwrap();
+        connections.total -= 1;
+        let Some(ip) = self.ip else {
+            return;
+        };
+        if let Some(from_ip) = connections.per_ip.get_mut(&ip) {
+            *from_ip -= 1;
+            if *from_ip == 0 {
+                connections.per_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_cidr() {
+        let cidr: Cidr = "127.0.0.0/8".parse().unwrap();
+        assert!(cidr.contains(ip("127.0.0.2")));
+        assert!(cidr.contains(ip("::ffff:127.0.0.1")));
+        assert!(!cidr.contains(ip("10.0.0.1")));
+        assert!(!cidr.contains(ip("::1")));
+
+        let single: Cidr = "127.0.0.1".parse().unwrap();
+        assert_eq!("127.0.0.1/32", single.to_string());
+        assert!(!single.contains(ip("127.0.0.2")));
+
+        let any: Cidr = "::/0".parse().unwrap();
+        assert!(any.contains(ip("::1")));
+
+        assert!("127.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("localhost".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let admission = Admission::new(AdmissionPolicy {
+            allow: vec!["127.0.0.0/8".parse().unwrap()],
+            deny: vec!["127.0.0.2".parse().unwrap()],
+            ..AdmissionPolicy::default()
+        });
+
+        assert!(admission.admit(ip("127.0.0.1")).is_ok());
+        assert_eq!(
+            Refusal::Denied,
+            admission.admit(ip("127.0.0.2")).unwrap_err()
+        );
+        assert_eq!(
+            Refusal::Denied,
+            admission.admit(ip("10.0.0.1")).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn connections_are_limited_until_closed() {
+        let admission = Admission::new(AdmissionPolicy {
+            max_connections: 3,
+            max_per_ip: 2,
+            ..AdmissionPolicy::default()
+        });
+
+        let first = admission.admit(ip("127.0.0.1")).unwrap();
+        let _second = admission.admit(ip("127.0.0.1")).unwrap();
+        assert_eq!(
+            Refusal::TooManyFromIp,
+            admission.admit(ip("127.0.0.1")).unwrap_err()
+        );
+        let _third = admission.admit(ip("127.0.0.2")).unwrap();
+        assert_eq!(
+            Refusal::ServerFull,
+            admission.admit(ip("127.0.0.3")).unwrap_err()
+        );
+
+        assert_eq!(Refusal::ServerFull, admission.admit_local().unwrap_err());
+
+        drop(first);
+        let local = admission.admit_local().unwrap();
+        assert_eq!(
+            Refusal::ServerFull,
+            admission.admit(ip("127.0.0.1")).unwrap_err()
+        );
+        drop(local);
+        assert!(admission.admit(ip("127.0.0.1")).is_ok());
+    }
+}
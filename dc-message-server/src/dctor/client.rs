@@ -1,83 +1,256 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Formatter},
+    net::IpAddr,
+    sync::Arc,
+    time::Instant,
+};
+
 use super::{
-    dctor::{Dctor, Inbox},
-    supervisor::{SupervisorMessage, SupervisorSender},
+    connection::{BoxedReader, BoxedWriter, Connection},
+    dctor::{Context, Dctor},
+    hooks::Handlers,
+    plugin::Plugins,
+    rate_limit::{Penalty, RateLimiter, Verdict},
+    server::SERVER_NAME,
+    supervisor::{OverflowPolicy, SupervisorMessage, SupervisorSender},
 };
+use crate::logging::{Body, Logged};
+use crate::metrics::Counters;
 use async_trait::async_trait;
-use dvorak_message::message::{Message, MessageType};
-use tokio::{
-    net::TcpStream,
-    sync::mpsc::{self, Sender},
-};
+use dvorak_message::message::{DecodeLimits, ErrorKind, Message, MessageReader, MessageType};
+use tokio::sync::watch;
+use tracing::Instrument;
+
+/// how many messages could be queued in the inbox of a client
+pub(crate) const CLIENT_INBOX_CAPACITY: usize = 100;
 
-#[derive(Debug)]
 pub(crate) enum ClientMessage {
     /// representing there is a message need send,
     /// tuple parameters: (sender, message)
     ReceiveMessage(String, String),
+    /// a user comes or goes, told to the client subscribing presence,
+    /// tuple parameters: (username, is online?)
+    Presence(String, bool),
+    /// the session is ended by the server for the reason, like the user logged in elsewhere,
+    /// the client is told not to come back, and terminated
+    LoggedOut(String),
     /// terminate current client
     Terminate,
 }
 
+impl Debug for ClientMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReceiveMessage(sender, message) => f
+                .debug_tuple("ReceiveMessage")
+                .field(sender)
+                .field(&Body(message))
+                .finish(),
+            Self::Presence(username, online) => f
+                .debug_tuple("Presence")
+                .field(username)
+                .field(online)
+                .finish(),
+            Self::LoggedOut(reason) => f.debug_tuple("LoggedOut").field(reason).finish(),
+            Self::Terminate => f.write_str("Terminate"),
+        }
+    }
+}
+
+/// how the clients are limited and their messages handled, the same for every supervisor
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    pub rate_limiter: RateLimiter,
+    pub decode_limits: DecodeLimits,
+    pub(crate) handlers: Handlers,
+    /// the virtual users, started by the supervisor owning their names
+    pub(crate) plugins: Plugins,
+    /// counters of the server, updated by every client
+    pub(crate) counters: Arc<Counters>,
+    /// show the bodies of messages in logs
+    pub(crate) log_bodies: bool,
+    /// the policies of the users not following the one of the server
+    pub(crate) overflow_policies: Arc<HashMap<String, OverflowPolicy>>,
+}
+
 pub(crate) struct Client {
-    tcp_stream: TcpStream,
-    inbox: Inbox<<Self as Dctor>::InboxItem>,
+    /// the user logged in, the receiver of every message written to the connection
+    username: String,
+    reader: MessageReader<BoxedReader>,
+    writer: BoxedWriter,
     supervisor_sender: SupervisorSender,
+    /// closed once the supervisor is gone, a restarted supervisor does not know this client
+    supervisor_alive: watch::Receiver<()>,
+    /// ip of the connection, [`None`] if unknown
+    ip: Option<IpAddr>,
+    /// limits of this user and its ip, shared with other clients
+    rate_limiter: RateLimiter,
+    /// a plugin is not limited, its names are reserved so no user could pretend it
+    is_plugin: bool,
+    /// see the text messages before they are routed
+    handlers: Handlers,
+    counters: Arc<Counters>,
+    log_bodies: bool,
 }
 
 impl Client {
     pub fn new(
-        tcp_stream: TcpStream,
+        username: String,
+        connection: Connection,
         supervisor_sender: SupervisorSender,
-    ) -> (Self, Sender<<Self as Dctor>::InboxItem>) {
-        let (tx, rx) = mpsc::channel(100);
-        println!("Client construct");
-        (
-            Client {
-                tcp_stream,
-                inbox: rx,
-                supervisor_sender,
-            },
-            tx,
-        )
+        supervisor_alive: watch::Receiver<()>,
+        config: ClientConfig,
+    ) -> Self {
+        let ip = connection.peer().map(|peer| peer.ip());
+        let is_plugin = config.plugins.get(&username).is_some();
+        let (read_half, writer) = connection.into_split();
+        Client {
+            username,
+            reader: MessageReader::with_limits(read_half, config.decode_limits),
+            writer,
+            supervisor_sender,
+            supervisor_alive,
+            ip,
+            rate_limiter: config.rate_limiter,
+            is_plugin,
+            handlers: config.handlers,
+            counters: config.counters,
+            log_bodies: config.log_bodies,
+        }
     }
 
-    /// handle incoming message
+    /// apply the rate limits of user and ip to an incoming message of `bytes`,
+    /// and report the violation to the client
+    ///
+    /// # Return
+    /// tuple: (is the message allowed?, is terminate the listen?)
+    async fn limit_rate(&mut self, bytes: usize) -> (bool, bool) {
+        let verdict = self
+            .rate_limiter
+            .check(&self.username, self.ip, bytes, Instant::now());
+
+        let (report, is_break) = match verdict {
+            Verdict::Exceeded(penalty) => {
+                tracing::warn!(?penalty, "rate limit exceeded");
+                match penalty {
+                    Penalty::Warning => ("rate limit exceeded, message dropped".to_string(), false),
+                    Penalty::Mute(duration) => (
+                        format!(
+                            "rate limit exceeded again, muted for {}s",
+                            duration.as_secs()
+                        ),
+                        false,
+                    ),
+                    Penalty::Disconnect => ("rate limit exceeded, disconnected".to_string(), true),
+                }
+            }
+            Verdict::Throttled => (
+                "too many messages from your address, message dropped".to_string(),
+                false,
+            ),
+            Verdict::Muted(left) => {
+                let left = left.as_secs().max(1);
+                (format!("muted for {left}s more, message dropped"), false)
+            }
+            Verdict::Allowed => return (true, false),
+        };
+
+        let is_broken = self.report(report).await;
+        // a client disconnected is told not to come back
+        if is_break && !is_broken {
+            self.send_logout().await;
+        }
+        (false, is_break || is_broken)
+    }
+
+    /// tell the client its session is ended by the server
+    ///
+    /// # Return
+    /// is the connection broken?
+    async fn send_logout(&mut self) -> bool {
+        let logout = Message::new(
+            MessageType::Logout,
+            SERVER_NAME.to_string(),
+            self.username.clone(),
+        );
+        Message::send(&mut self.writer, logout).await.is_err()
+    }
+
+    /// report what the client did wrong with an error message
+    ///
+    /// # Return
+    /// is the connection broken?
+    async fn report(&mut self, report: String) -> bool {
+        let report = Message::new(
+            MessageType::Error(report),
+            SERVER_NAME.to_string(),
+            self.username.clone(),
+        );
+        Message::send(&mut self.writer, report).await.is_err()
+    }
+
+    /// handle incoming message, a frame claiming to be from anyone but the user logged in
+    /// is reported and dropped
     ///
     /// # Return
     /// is terminate the listen?
     async fn handle_incoming_message(&mut self, message: Message) -> bool {
+        tracing::trace!(message = ?Logged(&message, self.log_bodies), "received");
+        self.counters.received(&message.message_type);
+        if !self.is_plugin && message.message_type != MessageType::Logout {
+            let bytes = message.message_type.body_length() as usize;
+            let (allowed, is_break) = self.limit_rate(bytes).await;
+            if !allowed {
+                return is_break;
+            }
+        }
+        // nobody speaks for the server, nor to it
+        if message.username == SERVER_NAME || message.receiver == SERVER_NAME {
+            tracing::warn!("message using the server name dropped");
+            return self
+                .report(format!("{SERVER_NAME} is reserved, message dropped"))
+                .await;
+        }
+        if message.username != self.username {
+            tracing::warn!(claimed = %message.username, "message from another user dropped");
+            let report = format!("you are logged in as {}, message dropped", self.username);
+            return self.report(report).await;
+        }
         match &message.message_type {
             MessageType::Text(data) => {
-                println!("Received type: Text");
                 let receiver = message.receiver.clone();
-                let sender = message.username.clone();
+                let sender = self.username.clone();
+                let Some(data) = self.handlers.handle(&sender, &receiver, data.clone()) else {
+                    tracing::debug!("message dropped by handler");
+                    return false;
+                };
 
                 self.supervisor_sender
                     .send(SupervisorMessage::Message {
                         sender,
-                        receiver: receiver.clone(),
-                        message: data.clone(),
+                        receiver,
+                        message: data,
                     })
                     .await
-                    .unwrap();
-
-                return false;
+                    .is_err()
             }
             MessageType::Logout => {
-                println!("Received type: Logout");
-
-                let username = message.username.clone();
+                tracing::info!("logout");
 
-                self.supervisor_sender
+                let username = self.username.clone();
+                let _ = self
+                    .supervisor_sender
                     .send(SupervisorMessage::DisconnectClient(username))
-                    .await
-                    .unwrap();
-                return true;
-            }
-            _ => {
-                println!("Received type: other");
-                return false;
+                    .await;
+                true
             }
+            MessageType::Presence => self
+                .supervisor_sender
+                .send(SupervisorMessage::Subscribe(self.username.clone()))
+                .await
+                .is_err(),
+            _ => false,
         }
     }
 }
@@ -86,48 +259,268 @@ impl Client {
 impl Dctor for Client {
     type InboxItem = ClientMessage;
 
-    async fn listen(&mut self) {
-        use ClientMessage::*;
+    /// the login is accepted, told by a Login frame from the server
+    async fn started(&mut self, _ctx: &mut Context<Self>) {
+        tracing::debug!("client started");
+        let accepted = Message::new(
+            MessageType::Login,
+            SERVER_NAME.to_string(),
+            self.username.clone(),
+        );
+        // a broken connection is noticed by listen
+        let _ = Message::send(&mut self.writer, accepted).await;
+    }
 
-        println!("Client listening...");
+    async fn listen(&mut self, ctx: &mut Context<Self>) {
+        use ClientMessage::*;
 
         loop {
             tokio::select! {
-                msg = Message::read_from(&mut self.tcp_stream) => {
-                    if msg.is_err() {
-                        continue;
-                    }
-                    let msg = msg.unwrap();
-                    if msg.is_none() {
-                        continue;
-                    }
-                    let message = msg.unwrap();
-                    println!("Client received message");
-                    let is_break = self.handle_incoming_message(message).await;
+                msg = self.reader.read() => {
+                    // the connection is closed or broken, the supervisor learns it once the client exits
+                    let message = match msg {
+                        Ok(Some(message)) => message,
+                        Ok(None) => {
+                            tracing::info!("connection closed");
+                            return;
+                        }
+                        Err(e) => {
+                            self.counters.decode_error();
+                            tracing::warn!(error = %e, "read message failure");
+                            // the rest of stream is not aligned to frames, disconnect the client
+                            if e.kind == ErrorKind::FrameTooLarge {
+                                self.report(e.description).await;
+                            }
+                            return;
+                        }
+                    };
+                    let span = tracing::debug_span!(
+                        "message",
+                        kind = message.message_type.name(),
+                        receiver = %message.receiver,
+                    );
+                    let is_break = self.handle_incoming_message(message).instrument(span).await;
 
                     if is_break {
                         break;
                     }
                 },
-                msg = self.inbox.recv() => {
-                    if let Some(msg) = msg {
-                        match msg {
-                            ReceiveMessage(sender, message) => {
-                                let message = Message::new(MessageType::Text(message), sender, String::from("Self"));
-                                Message::send(&mut self.tcp_stream, message).await.unwrap();
-                                // let data = format!("{{ sender: '{sender}', message: '{message}' }}");
-                                // println!("Debug: Client in Server ReceiveMessage: {data}");
-                                // let buf = data.as_bytes();
-                                // self.tcp_stream.write_all(buf).await.unwrap();
+                msg = ctx.recv() => {
+                    let Some(msg) = msg else {
+                        tracing::debug!("client inbox closed");
+                        return;
+                    };
+                    match msg {
+                        ReceiveMessage(sender, message) => {
+                            let message = Message::new(MessageType::Text(message), sender, self.username.clone());
+                            if let Err(e) = Message::send(&mut self.writer, message).await {
+                                tracing::warn!(error = %e, "connection broken");
+                                return;
                             }
-                            Terminate => {
-                                println!("Client terminated.");
+                        }
+                        Presence(username, online) => {
+                            let message_type = if online { MessageType::Login } else { MessageType::Logout };
+                            let message = Message::new(message_type, username, self.username.clone());
+                            if let Err(e) = Message::send(&mut self.writer, message).await {
+                                tracing::warn!(error = %e, "connection broken");
                                 return;
                             }
                         }
+                        LoggedOut(reason) => {
+                            tracing::info!(%reason, "logged out by server");
+                            let notice = Message::new(MessageType::Text(reason), SERVER_NAME.to_string(), self.username.clone());
+                            if Message::send(&mut self.writer, notice).await.is_ok() {
+                                self.send_logout().await;
+                            }
+                            return;
+                        }
+                        Terminate => return,
                     }
                 }
+                _ = self.supervisor_alive.changed() => {
+                    tracing::warn!("supervisor is gone, disconnect");
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn stopping(&mut self, _ctx: &mut Context<Self>) {
+        tracing::debug!("client stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::dctor::{dctor, rate_limit::RateLimits};
+
+    #[tokio::test]
+    async fn flooding_client_is_warned_muted_and_disconnected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        // the supervisor is not running, forwarded messages are kept in its inbox
+        let mut supervisor_ctx = Context::new(100);
+        let (_alive, alive) = watch::channel(());
+        let limits = RateLimits {
+            messages_per_second: 1.0,
+            bytes_per_second: 0.0,
+        };
+        let config = ClientConfig {
+            rate_limiter: RateLimiter::new(limits, RateLimits::default()),
+            ..ClientConfig::default()
+        };
+        let client = Client::new(
+            "alice".to_string(),
+            stream.into(),
+            supervisor_ctx.addr(),
+            alive,
+            config,
+        );
+        dctor::spawn(client, Context::new(CLIENT_INBOX_CAPACITY));
+
+        for _ in 0..6 {
+            let text = MessageType::Text("flood".to_string());
+            let message = Message::new(text, "alice".to_string(), "victim".to_string());
+            Message::send(&mut peer, message).await.unwrap();
+        }
+
+        let mut reader = MessageReader::new(peer);
+        let accepted = reader.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Login, accepted.message_type);
+        let mut reports = vec![];
+        while let Some(message) = reader.read().await.unwrap() {
+            if message.message_type == MessageType::Logout {
+                assert_eq!(SERVER_NAME, message.username);
+                break;
             }
+            let MessageType::Error(report) = message.message_type else {
+                panic!("unexpected message: {:?}", Logged(&message, true));
+            };
+            reports.push(report);
+        }
+        assert!(reader.read().await.unwrap().is_none());
+        assert_eq!(4, reports.len());
+        assert!(reports[0].contains("message dropped"));
+        assert!(reports[2].contains("muted"));
+        assert!(reports[3].contains("disconnected"));
+
+        // the burst is forwarded
+        for _ in 0..2 {
+            let msg = supervisor_ctx.recv().await;
+            assert!(matches!(msg, Some(SupervisorMessage::Message { .. })));
+        }
+    }
+
+    #[tokio::test]
+    async fn oversize_frame_is_reported_and_disconnected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        let supervisor_ctx = Context::new(100);
+        let (_alive, alive) = watch::channel(());
+        let config = ClientConfig {
+            decode_limits: DecodeLimits {
+                max_frame_size: 64,
+                ..DecodeLimits::default()
+            },
+            ..ClientConfig::default()
+        };
+        let client = Client::new(
+            "alice".to_string(),
+            stream.into(),
+            supervisor_ctx.addr(),
+            alive,
+            config,
+        );
+        dctor::spawn(client, Context::new(CLIENT_INBOX_CAPACITY));
+
+        let text = MessageType::Text("x".repeat(100));
+        let message = Message::new(text, "greedy".to_string(), "victim".to_string());
+        Message::send(&mut peer, message).await.unwrap();
+
+        let mut reader = MessageReader::new(peer);
+        let accepted = reader.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Login, accepted.message_type);
+        let message = reader.read().await.unwrap().unwrap();
+        assert!(
+            matches!(message.message_type, MessageType::Error(report) if report.contains("exceeds limit"))
+        );
+        assert!(reader.read().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn messages_are_sent_as_logged_in_user() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        let mut supervisor_ctx = Context::new(100);
+        let (_alive, alive) = watch::channel(());
+        let client = Client::new(
+            "alice".to_string(),
+            stream.into(),
+            supervisor_ctx.addr(),
+            alive,
+            ClientConfig::default(),
+        );
+        dctor::spawn(client, Context::new(CLIENT_INBOX_CAPACITY));
+
+        let frames = [
+            (MessageType::Text("spoofed".to_string()), "bob", "carol"),
+            (
+                MessageType::Text("spoofed".to_string()),
+                SERVER_NAME,
+                "carol",
+            ),
+            (
+                MessageType::Text("to server".to_string()),
+                "alice",
+                SERVER_NAME,
+            ),
+            (MessageType::Presence, "bob", ""),
+            (MessageType::Logout, "bob", ""),
+            (MessageType::Text("hi".to_string()), "alice", "carol"),
+            (MessageType::Presence, "alice", ""),
+            (MessageType::Logout, "alice", ""),
+        ];
+        for (message_type, username, receiver) in frames {
+            let message = Message::new(message_type, username.to_string(), receiver.to_string());
+            Message::send(&mut peer, message).await.unwrap();
+        }
+
+        let msg = supervisor_ctx.recv().await;
+        assert!(matches!(
+            msg,
+            Some(SupervisorMessage::Message { sender, receiver, .. }) if sender == "alice" && receiver == "carol"
+        ));
+        let msg = supervisor_ctx.recv().await;
+        assert!(matches!(msg, Some(SupervisorMessage::Subscribe(username)) if username == "alice"));
+        let msg = supervisor_ctx.recv().await;
+        assert!(
+            matches!(msg, Some(SupervisorMessage::DisconnectClient(username)) if username == "alice")
+        );
+
+        let mut reader = MessageReader::new(peer);
+        let accepted = reader.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Login, accepted.message_type);
+        let spoofed = "you are logged in as alice, message dropped".to_string();
+        let reserved = format!("{SERVER_NAME} is reserved, message dropped");
+        for report in [&spoofed, &reserved, &reserved, &spoofed, &spoofed] {
+            let message = reader.read().await.unwrap().unwrap();
+            assert_eq!(MessageType::Error(report.clone()), message.message_type);
         }
+        assert!(reader.read().await.unwrap().is_none());
     }
 }
@@ -0,0 +1,766 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use dvorak_message::message::{Message, MessageReader, MessageType};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::{
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    sync::mpsc::{self, Sender},
+    task::JoinHandle,
+};
+use tracing::Instrument;
+
+use super::dctor::{Addr, Context, Dctor};
+use super::shard::{ShardOutboxes, Shards};
+use super::supervisor::SupervisorMessage;
+use crate::logging::{Body, Frame, Logged};
+
+/// how long to wait before dialing a peer again
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+/// how many frames could be queued for a single link
+const LINK_CAPACITY: usize = 1000;
+/// how often the messages kept for the shards are sent again
+const OUTBOX_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+/// how long another node may take to prove it knows the secret
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+static NEXT_LINK_ID: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) type ClusterSender = Addr<Cluster>;
+
+/// Actor Message for Cluster
+pub enum ClusterMessage {
+    /// a user logins to this node
+    UserOnline(String),
+    /// a user disconnects from this node
+    UserOffline(String),
+    /// send the message to the node where the receiver lives
+    Forward {
+        sender: String,
+        receiver: String,
+        message: String,
+    },
+    /// a link to another node established
+    PeerConnected {
+        node: String,
+        link_id: u64,
+        link: Sender<Message>,
+    },
+    /// a frame received from another node
+    PeerMessage { node: String, message: Message },
+    /// a link to another node closed
+    PeerDisconnected { node: String, link_id: u64 },
+    /// stop linking to other nodes
+    Terminate,
+}
+
+/// the bodies of messages are redacted, see [`Body`]
+impl std::fmt::Debug for ClusterMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ClusterMessage::*;
+
+        match self {
+            UserOnline(username) => f.debug_tuple("UserOnline").field(username).finish(),
+            UserOffline(username) => f.debug_tuple("UserOffline").field(username).finish(),
+            Forward {
+                sender,
+                receiver,
+                message,
+            } => f
+                .debug_struct("Forward")
+                .field("sender", sender)
+                .field("receiver", receiver)
+                .field("message", &Body(message))
+                .finish(),
+            PeerConnected { node, link_id, .. } => f
+                .debug_struct("PeerConnected")
+                .field("node", node)
+                .field("link_id", link_id)
+                .finish(),
+            PeerMessage { node, message } => f
+                .debug_struct("PeerMessage")
+                .field("node", node)
+                .field("message", &Frame(message))
+                .finish(),
+            PeerDisconnected { node, link_id } => f
+                .debug_struct("PeerDisconnected")
+                .field("node", node)
+                .field("link_id", link_id)
+                .finish(),
+            Terminate => f.write_str("Terminate"),
+        }
+    }
+}
+
+/// this actor links the server node to other nodes of the cluster,
+/// so users connected to different nodes can message each other
+///
+/// # Protocol
+/// nodes talk with the frames of [`Message`]:
+/// - `Heart`: hello, the username is the name of node, the receiver is a random challenge,
+///   sent first on every link
+/// - `Heart`: the username is the proof of the shared secret in hex, HMAC-SHA256 of the role
+///   of the node, both challenges and both names, sent by the dialing node first,
+///   the dialed node answers with its own proof only after it checks the proof of the dialing one
+/// - `Login`: the user of username is online on the node
+/// - `Logout`: the user of username is offline
+/// - `Text`: a message for the receiver, who lives on the node reading the frame
+///
+/// a link is closed if the other node could not prove it knows the secret.
+/// a user logins on a node is kicked from other nodes
+pub struct Cluster {
+    /// name of this node, it is the address other nodes dial
+    name: String,
+    /// secret shared by all nodes of the cluster
+    secret: String,
+    listener: Option<TcpListener>,
+    /// addresses of other nodes
+    peers: Vec<String>,
+    /// links to other nodes, tuple: (link id, link)
+    links: HashMap<String, (u64, Sender<Message>)>,
+    /// users of other nodes, username -> node
+    remote_users: HashMap<String, String>,
+    local_users: HashSet<String>,
+    /// the shards receiving the messages from other nodes
+    shards: Shards,
+    /// messages to the shards, kept in order while their inboxes are full
+    outboxes: ShardOutboxes,
+    /// tasks accepting and dialing other nodes
+    tasks: Vec<JoinHandle<()>>,
+    /// show the bodies of messages in logs
+    log_bodies: bool,
+}
+
+impl Cluster {
+    pub fn new(
+        name: String,
+        secret: String,
+        listener: TcpListener,
+        peers: Vec<String>,
+        shards: Shards,
+        log_bodies: bool,
+    ) -> Self {
+        Cluster {
+            name,
+            secret,
+            listener: Some(listener),
+            peers,
+            links: HashMap::new(),
+            remote_users: HashMap::new(),
+            local_users: HashSet::new(),
+            outboxes: shards.outboxes(),
+            shards,
+            tasks: vec![],
+            log_bodies,
+        }
+    }
+
+    fn broadcast(&self, message_type: MessageType, username: &str) {
+        for (_, link) in self.links.values() {
+            let _ = link.try_send(Message::new(
+                message_type.clone(),
+                username.to_string(),
+                String::new(),
+            ));
+        }
+    }
+
+    fn handle_peer_message(&mut self, node: String, message: Message) {
+        tracing::trace!(%node, message = ?Logged(&message, self.log_bodies), "peer frame");
+        match message.message_type {
+            MessageType::Login => {
+                // the users are told again once the link is established again,
+                // a local user moving to another node is still online
+                if self.local_users.remove(&message.username) {
+                    self.moved(&message.username);
+                } else if !self.remote_users.contains_key(&message.username) {
+                    self.announce(&message.username, true);
+                }
+                self.remote_users.insert(message.username, node);
+            }
+            MessageType::Logout => {
+                if self.remote_users.get(&message.username) == Some(&node) {
+                    self.remote_users.remove(&message.username);
+                    self.announce(&message.username, false);
+                }
+            }
+            MessageType::Text(text) => {
+                let shard = self.shards.shard_of(&message.receiver);
+                let remote = SupervisorMessage::Remote {
+                    sender: message.username,
+                    receiver: message.receiver,
+                    message: text,
+                };
+                if !self.outboxes.send(shard, remote) {
+                    tracing::warn!(%node, "shard outbox is full, message dropped");
+                }
+            }
+            MessageType::Heart | MessageType::Error(_) | MessageType::Presence => {}
+        }
+    }
+
+    /// disconnect the local user, who logins on another node
+    fn moved(&mut self, username: &str) {
+        tracing::info!(user = %username, "user logins on another node");
+        let shard = self.shards.shard_of(username);
+        if !self
+            .outboxes
+            .send(shard, SupervisorMessage::Moved(username.to_string()))
+        {
+            tracing::warn!(user = %username, "shard outbox is full, user not moved");
+        }
+    }
+
+    fn announce(&mut self, username: &str, online: bool) {
+        if self.outboxes.announce(username, online) > 0 {
+            tracing::warn!(user = %username, online, "shard outbox is full, presence dropped");
+        }
+    }
+
+    async fn accept_peers(
+        listener: TcpListener,
+        name: String,
+        secret: String,
+        cluster: ClusterSender,
+    ) {
+        loop {
+            let Ok((stream, socket)) = listener.accept().await else {
+                continue;
+            };
+            tracing::debug!(peer = %socket, "cluster peer incoming");
+
+            let (name, secret, cluster) = (name.clone(), secret.clone(), cluster.clone());
+            let link = Self::run_link(stream, Role::Responder, name, secret, cluster);
+            tokio::spawn(link.in_current_span());
+        }
+    }
+
+    async fn dial_peer(peer: String, name: String, secret: String, cluster: ClusterSender) {
+        loop {
+            if let Ok(stream) = TcpStream::connect(&peer).await {
+                tracing::info!(%peer, "linked to peer");
+                let (name, secret, cluster) = (name.clone(), secret.clone(), cluster.clone());
+                Self::run_link(stream, Role::Initiator, name, secret, cluster).await;
+            }
+            if cluster.is_closed() {
+                return;
+            }
+            tokio::time::sleep(RECONNECT_INTERVAL).await;
+        }
+    }
+
+    /// exchange the names of nodes, and then pass frames in the span of link
+    async fn run_link(
+        stream: TcpStream,
+        role: Role,
+        name: String,
+        secret: String,
+        cluster: ClusterSender,
+    ) {
+        let peer = stream.peer_addr().ok();
+        let (read_half, mut write_half) = stream.into_split();
+        let mut reader = MessageReader::new(read_half);
+
+        let handshake = Self::handshake(&mut reader, &mut write_half, role, &name, &secret);
+        let node = match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+            Ok(Some(node)) => node,
+            _ => {
+                tracing::warn!(?peer, "peer refused");
+                return;
+            }
+        };
+        let span = tracing::info_span!("link", %node);
+        Self::pass_frames(reader, write_half, node, cluster)
+            .instrument(span)
+            .await;
+    }
+
+    /// exchange the names of nodes and challenges, and prove both know the secret
+    ///
+    /// the dialed node never proves before the dialing one, so it can not be asked to sign
+    /// a challenge for anyone who does not know the secret
+    ///
+    /// # Return
+    /// the name of the other node, [`None`] if it fails to prove
+    async fn handshake(
+        reader: &mut MessageReader<OwnedReadHalf>,
+        write_half: &mut OwnedWriteHalf,
+        role: Role,
+        name: &str,
+        secret: &str,
+    ) -> Option<String> {
+        let challenge = hex(&rand::random::<[u8; 16]>());
+        let hello = Message::new(MessageType::Heart, name.to_string(), challenge.clone());
+        Message::send(write_half, hello).await.ok()?;
+        let hello = reader.read().await.ok()??;
+        let node = hello.username;
+        if hello.message_type != MessageType::Heart || node == name {
+            return None;
+        }
+
+        let transcript = match role {
+            Role::Initiator => Transcript {
+                initiator: (name, &challenge),
+                responder: (&node, &hello.receiver),
+            },
+            Role::Responder => Transcript {
+                initiator: (&node, &hello.receiver),
+                responder: (name, &challenge),
+            },
+        };
+        if role == Role::Responder {
+            Self::check_proof(reader, secret, role.other(), &transcript).await?;
+        }
+        let proof = hex(&prove(secret, role, &transcript).finalize().into_bytes());
+        let proof = Message::new(MessageType::Heart, proof, String::new());
+        Message::send(write_half, proof).await.ok()?;
+        if role == Role::Initiator {
+            Self::check_proof(reader, secret, role.other(), &transcript).await?;
+        }
+
+        Some(node)
+    }
+
+    /// read the proof of the other node, made in its `role`
+    async fn check_proof(
+        reader: &mut MessageReader<OwnedReadHalf>,
+        secret: &str,
+        role: Role,
+        transcript: &Transcript<'_>,
+    ) -> Option<()> {
+        let proof = reader.read().await.ok()??;
+        if proof.message_type != MessageType::Heart {
+            return None;
+        }
+        prove(secret, role, transcript)
+            .verify_slice(&unhex(&proof.username)?)
+            .ok()
+    }
+
+    /// pass frames between the cluster and another node until the link closes
+    async fn pass_frames(
+        mut reader: MessageReader<OwnedReadHalf>,
+        mut write_half: OwnedWriteHalf,
+        node: String,
+        cluster: ClusterSender,
+    ) {
+        let link_id = NEXT_LINK_ID.fetch_add(1, Ordering::Relaxed);
+        let (link, mut outgoing) = mpsc::channel::<Message>(LINK_CAPACITY);
+        let connected = ClusterMessage::PeerConnected {
+            node: node.clone(),
+            link_id,
+            link,
+        };
+        if cluster.send(connected).await.is_err() {
+            return;
+        }
+
+        let writer = tokio::spawn(
+            async move {
+                while let Some(message) = outgoing.recv().await {
+                    if Message::send(&mut write_half, message).await.is_err() {
+                        return;
+                    }
+                }
+                // the link is replaced by a newer one, but the other node may still write to it,
+                // dropping the write half would close the link for that node
+                std::future::pending::<()>().await;
+            }
+            .in_current_span(),
+        );
+
+        while let Ok(Some(message)) = reader.read().await {
+            let node = node.clone();
+            if cluster
+                .send(ClusterMessage::PeerMessage { node, message })
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+
+        writer.abort();
+        let _ = cluster
+            .send(ClusterMessage::PeerDisconnected { node, link_id })
+            .await;
+    }
+}
+
+/// the side of a link, the node dialing is the initiator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Initiator,
+    Responder,
+}
+
+impl Role {
+    fn other(self) -> Role {
+        match self {
+            Role::Initiator => Role::Responder,
+            Role::Responder => Role::Initiator,
+        }
+    }
+}
+
+/// what both nodes said in hello, tuple: (name, challenge)
+struct Transcript<'a> {
+    initiator: (&'a str, &'a str),
+    responder: (&'a str, &'a str),
+}
+
+/// the proof of `secret` made by the node in `role`, over both hellos of the link
+fn prove(secret: &str, role: Role, transcript: &Transcript) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac takes any key size");
+    let role: &[u8] = match role {
+        Role::Initiator => b"initiator",
+        Role::Responder => b"responder",
+    };
+    let (initiator, initiator_challenge) = transcript.initiator;
+    let (responder, responder_challenge) = transcript.responder;
+    let fields = [
+        role,
+        initiator_challenge.as_bytes(),
+        responder_challenge.as_bytes(),
+        initiator.as_bytes(),
+        responder.as_bytes(),
+    ];
+    // every field is prefixed with its length, so no two transcripts have the same input
+    for field in fields {
+        mac.update(&(field.len() as u64).to_be_bytes());
+        mac.update(field);
+    }
+    mac
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[async_trait]
+impl Dctor for Cluster {
+    type InboxItem = ClusterMessage;
+
+    async fn started(&mut self, ctx: &mut Context<Self>) {
+        tracing::info!(peers = ?self.peers, "cluster listening");
+
+        if let Some(listener) = self.listener.take() {
+            let (name, secret, cluster) = (self.name.clone(), self.secret.clone(), ctx.addr());
+            let accept = Self::accept_peers(listener, name, secret, cluster);
+            self.tasks.push(tokio::spawn(accept.in_current_span()));
+        }
+        for peer in self.peers.iter().cloned() {
+            let (name, secret, cluster) = (self.name.clone(), self.secret.clone(), ctx.addr());
+            let dial = Self::dial_peer(peer, name, secret, cluster);
+            self.tasks.push(tokio::spawn(dial.in_current_span()));
+        }
+    }
+
+    async fn listen(&mut self, ctx: &mut Context<Self>) {
+        use ClusterMessage::*;
+
+        let mut flush_interval = tokio::time::interval(OUTBOX_FLUSH_INTERVAL);
+        'listen: loop {
+            let msg = tokio::select! {
+                msg = ctx.recv() => match msg {
+                    Some(msg) => msg,
+                    None => break 'listen,
+                },
+                _ = flush_interval.tick() => {
+                    self.outboxes.flush();
+                    continue 'listen;
+                }
+            };
+            match msg {
+                UserOnline(username) => {
+                    // the node where the user was is told to kick it
+                    self.remote_users.remove(&username);
+                    self.broadcast(MessageType::Login, &username);
+                    self.local_users.insert(username);
+                }
+                UserOffline(username) => {
+                    self.broadcast(MessageType::Logout, &username);
+                    self.local_users.remove(&username);
+                }
+                Forward {
+                    sender,
+                    receiver,
+                    message,
+                } => {
+                    let Some(node) = self.remote_users.get(&receiver) else {
+                        continue;
+                    };
+                    if let Some((_, link)) = self.links.get(node) {
+                        let message = Message::new(MessageType::Text(message), sender, receiver);
+                        let _ = link.try_send(message);
+                    }
+                }
+                PeerConnected {
+                    node,
+                    link_id,
+                    link,
+                } => {
+                    for username in self.local_users.iter() {
+                        let message =
+                            Message::new(MessageType::Login, username.clone(), String::new());
+                        let _ = link.try_send(message);
+                    }
+                    // the newer link replaces the older one if both nodes dialed each other
+                    self.links.insert(node, (link_id, link));
+                }
+                PeerMessage { node, message } => self.handle_peer_message(node, message),
+                PeerDisconnected { node, link_id } => {
+                    if !matches!(self.links.get(&node), Some((id, _)) if *id == link_id) {
+                        continue;
+                    }
+                    tracing::info!(%node, "peer disconnected");
+                    self.links.remove(&node);
+                    let gone: Vec<String> = self
+                        .remote_users
+                        .iter()
+                        .filter(|(_, user_node)| **user_node == node)
+                        .map(|(username, _)| username.clone())
+                        .collect();
+                    for username in gone {
+                        self.remote_users.remove(&username);
+                        self.announce(&username, false);
+                    }
+                }
+                Terminate => break 'listen,
+            }
+        }
+    }
+
+    async fn stopping(&mut self, _ctx: &mut Context<Self>) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+        tracing::debug!("cluster stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::dctor::client::ClientConfig;
+    use crate::dctor::dctor;
+    use crate::dctor::server::SERVER_NAME;
+    use crate::dctor::supervisor::{ClientSupervisor, OverflowPolicy};
+    use crate::dctor::testing::new_client;
+
+    const SECRET: &str = "open sesame";
+
+    /// start the supervisor and cluster of a node, without client listener
+    fn start_node(listener: TcpListener, peers: Vec<String>, secret: &str) -> Shards {
+        let name = listener.local_addr().unwrap().to_string();
+        let ctx = Context::new(100);
+        let shards = ClientSupervisor::start_shards(
+            1,
+            OverflowPolicy::Spill,
+            Some(ctx.addr()),
+            ClientConfig::default(),
+        );
+        let cluster = Cluster::new(
+            name,
+            secret.to_string(),
+            listener,
+            peers,
+            shards.clone(),
+            false,
+        );
+
+        dctor::spawn(cluster, ctx);
+        shards
+    }
+
+    async fn login(shards: &Shards, username: &str) -> MessageReader<TcpStream> {
+        let mut reader = MessageReader::new(new_client(shards, username).await);
+        let accepted = reader.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Login, accepted.message_type);
+        reader
+    }
+
+    #[tokio::test]
+    async fn message_between_users_of_different_nodes() {
+        let mut listeners = vec![];
+        for _ in 0..3 {
+            listeners.push(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        }
+        let addresses: Vec<String> = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap().to_string())
+            .collect();
+        let nodes: Vec<Shards> = listeners
+            .into_iter()
+            .enumerate()
+            .map(|(i, listener)| {
+                let mut peers = addresses.clone();
+                peers.remove(i);
+                start_node(listener, peers, SECRET)
+            })
+            .collect();
+
+        let _alice = login(&nodes[0], "alice").await;
+        let mut bob = login(&nodes[2], "bob").await;
+
+        // users are advertised once the links between nodes established
+        let mut received = None;
+        for _ in 0..50 {
+            nodes[0]
+                .sender_of("alice")
+                .send(SupervisorMessage::Message {
+                    sender: "alice".to_string(),
+                    receiver: "bob".to_string(),
+                    message: "hello".to_string(),
+                })
+                .await
+                .unwrap();
+            if let Ok(message) = timeout(Duration::from_millis(100), bob.read()).await {
+                received = message.unwrap();
+                break;
+            }
+        }
+
+        let message = received.expect("message is not forwarded to another node");
+        assert_eq!("alice", message.username);
+        assert_eq!(MessageType::Text("hello".to_string()), message.message_type);
+    }
+
+    #[tokio::test]
+    async fn message_to_unknown_user_is_not_forwarded() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let node = start_node(listener, vec![], SECRET);
+        let mut alice = login(&node, "alice").await;
+
+        node.sender_of("alice")
+            .send(SupervisorMessage::Message {
+                sender: "alice".to_string(),
+                receiver: "nobody".to_string(),
+                message: "hello".to_string(),
+            })
+            .await
+            .unwrap();
+
+        assert!(timeout(Duration::from_millis(200), alice.read())
+            .await
+            .is_err());
+    }
+
+    /// start two nodes, the second dials the first
+    async fn start_pair(secrets: [&str; 2]) -> [Shards; 2] {
+        let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = first.local_addr().unwrap().to_string();
+        [
+            start_node(first, vec![], secrets[0]),
+            start_node(second, vec![address], secrets[1]),
+        ]
+    }
+
+    /// send hello from alice to bob every 100ms until bob receives it
+    async fn hello_reaches_bob(
+        nodes: &[Shards; 2],
+        bob: &mut MessageReader<TcpStream>,
+    ) -> Option<Message> {
+        for _ in 0..30 {
+            nodes[0]
+                .sender_of("alice")
+                .send(SupervisorMessage::Message {
+                    sender: "alice".to_string(),
+                    receiver: "bob".to_string(),
+                    message: "hello".to_string(),
+                })
+                .await
+                .unwrap();
+            if let Ok(message) = timeout(Duration::from_millis(100), bob.read()).await {
+                return message.unwrap();
+            }
+        }
+        None
+    }
+
+    #[tokio::test]
+    async fn node_without_secret_is_refused() {
+        let nodes = start_pair([SECRET, "guess"]).await;
+        let _alice = login(&nodes[0], "alice").await;
+        let mut bob = login(&nodes[1], "bob").await;
+
+        assert!(hello_reaches_bob(&nodes, &mut bob).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn dialed_node_does_not_prove_first() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let _node = start_node(listener, vec![], SECRET);
+
+        let stranger = TcpStream::connect(address).await.unwrap();
+        let (read_half, mut write_half) = stranger.into_split();
+        let mut reader = MessageReader::new(read_half);
+        let hello = Message::new(MessageType::Heart, "stranger".to_string(), "0".repeat(32));
+        Message::send(&mut write_half, hello).await.unwrap();
+        let hello = reader.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Heart, hello.message_type);
+
+        // a proof the node could be asked for, while the stranger proves nothing
+        let proof = timeout(HANDSHAKE_TIMEOUT * 2, reader.read()).await.unwrap();
+        assert!(proof.ok().flatten().is_none());
+    }
+
+    #[test]
+    fn proofs_differ_by_role() {
+        let transcript = Transcript {
+            initiator: ("a", "1"),
+            responder: ("b", "2"),
+        };
+        let initiator = prove(SECRET, Role::Initiator, &transcript)
+            .finalize()
+            .into_bytes();
+        let responder = prove(SECRET, Role::Responder, &transcript)
+            .finalize()
+            .into_bytes();
+        assert_ne!(initiator, responder);
+    }
+
+    #[tokio::test]
+    async fn user_logins_on_another_node_is_kicked() {
+        let nodes = start_pair([SECRET, SECRET]).await;
+        let _alice = login(&nodes[0], "alice").await;
+        let mut bob = login(&nodes[1], "bob").await;
+        assert!(hello_reaches_bob(&nodes, &mut bob).await.is_some());
+
+        let _bob_again = login(&nodes[0], "bob").await;
+        let notice = timeout(Duration::from_secs(5), bob.read())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            MessageType::Text("logged in on another node".to_string()),
+            notice.message_type
+        );
+        let logout = bob.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Logout, logout.message_type);
+        assert_eq!(SERVER_NAME, logout.username);
+        assert!(bob.read().await.unwrap().is_none());
+        assert!(nodes[1].list().await.is_empty());
+    }
+}
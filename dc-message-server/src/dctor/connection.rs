@@ -0,0 +1,223 @@
+use std::{
+    fmt::{self, Debug, Formatter},
+    io,
+    net::SocketAddr,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::Path,
+    pin::Pin,
+    task::{self, Poll},
+};
+
+use bytes::BytesMut;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, DuplexStream, ReadBuf},
+    net::{TcpStream, UnixListener},
+};
+
+/// bytes buffered in every direction of the pipe of [`Connection::pipe`]
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+pub(crate) type BoxedReader = Pin<Box<dyn AsyncRead + Send>>;
+pub(crate) type BoxedWriter = Pin<Box<dyn AsyncWrite + Send>>;
+
+/// the connection of a client, whichever transport it comes from
+///
+/// the frames of [`Message`] are read from and written to it
+///
+/// [`Message`]: dvorak_message::message::Message
+pub struct Connection {
+    reader: BoxedReader,
+    writer: BoxedWriter,
+    /// address of the client, [`None`] if it is unknown
+    peer: Option<SocketAddr>,
+}
+
+impl Connection {
+    pub fn new<S>(stream: S, peer: Option<SocketAddr>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        Connection {
+            reader: Box::pin(reader),
+            writer: Box::pin(writer),
+            peer,
+        }
+    }
+
+    /// a connection over an in-memory pipe,
+    /// the returned end of pipe is bridged to the transport carrying the frames in its own way
+    pub fn pipe(peer: Option<SocketAddr>) -> (Self, DuplexStream) {
+        let (connection, pipe) = tokio::io::duplex(PIPE_CAPACITY);
+        (Connection::new(connection, peer), pipe)
+    }
+
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    /// put back the bytes read ahead, they are read again before the rest of connection
+    pub fn unread(self, bytes: BytesMut) -> Self {
+        if bytes.is_empty() {
+            return self;
+        }
+        Connection {
+            reader: Box::pin(io::Cursor::new(bytes).chain(self.reader)),
+            ..self
+        }
+    }
+
+    pub fn into_split(self) -> (BoxedReader, BoxedWriter) {
+        (self.reader, self.writer)
+    }
+}
+
+/// bind a Unix-domain socket, whoever could connect to it is decided by `mode` of the file
+///
+/// the socket is bound in a private directory, and linked to `path` once its mode is set,
+/// so nobody could connect to it before
+pub(crate) fn bind_unix(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    remove_stale_socket(path)?;
+
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let private = dir.join(format!(".{name}.{}", std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let bound = private.join("socket");
+    let listener = UnixListener::bind(&bound).and_then(|listener| {
+        std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(mode))?;
+        // fails if another server bound the path meanwhile, unlike rename
+        std::fs::hard_link(&bound, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&bound);
+    let _ = std::fs::remove_dir(&private);
+    listener
+}
+
+/// remove the socket left by a server not quitting normally,
+/// it fails if `path` is not a socket, or another server still listens to it
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        let error = format!("{} exists, and is not a socket", path.display());
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, error));
+    }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => {
+            let error = format!("another server listens to {}", path.display());
+            Err(io::Error::new(io::ErrorKind::AddrInUse, error))
+        }
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => std::fs::remove_file(path),
+        Err(e) => Err(e),
+    }
+}
+
+impl Debug for Connection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("peer", &self.peer)
+            .finish_non_exhaustive()
+    }
+}
+
+impl From<TcpStream> for Connection {
+    fn from(tcp_stream: TcpStream) -> Self {
+        let peer = tcp_stream.peer_addr().ok();
+        let (reader, writer) = tcp_stream.into_split();
+        Connection {
+            reader: Box::pin(reader),
+            writer: Box::pin(writer),
+            peer,
+        }
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.reader.as_mut().poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.writer.as_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        self.writer.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        self.writer.as_mut().poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dvorak_message::message::{Message, MessageReader, MessageType};
+    use tokio::net::UnixStream;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn frames_over_unix_socket() {
+        let path = std::env::temp_dir().join(format!("dc-clients-{}.sock", std::process::id()));
+        let listener = bind_unix(&path, 0o660).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o660, mode & 0o777);
+
+        let mut local_client = UnixStream::connect(&path).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let connection = Connection::new(stream, None);
+        assert_eq!(None, connection.peer());
+
+        let login = Message::new(MessageType::Login, "bot".to_string(), String::new());
+        Message::send(&mut local_client, login).await.unwrap();
+        let (reader, _) = connection.into_split();
+        let message = MessageReader::new(reader).read().await.unwrap().unwrap();
+        assert_eq!("bot", message.username);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn only_stale_socket_is_replaced() {
+        let path = std::env::temp_dir().join(format!("dc-stale-{}.sock", std::process::id()));
+        let listener = bind_unix(&path, 0o600).unwrap();
+
+        // another server listens to it
+        let error = bind_unix(&path, 0o600).unwrap_err();
+        assert_eq!(io::ErrorKind::AddrInUse, error.kind());
+
+        // the server quitted without removing it
+        drop(listener);
+        let listener = bind_unix(&path, 0o600).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+        assert!(UnixStream::connect(&path).await.is_ok());
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+
+        // not a socket, it is never removed
+        std::fs::write(&path, "precious").unwrap();
+        let error = bind_unix(&path, 0o600).unwrap_err();
+        assert_eq!(io::ErrorKind::AlreadyExists, error.kind());
+        assert_eq!("precious", std::fs::read_to_string(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,11 +1,437 @@
+use std::{collections::VecDeque, sync::Arc};
+
 use async_trait::async_trait;
-use tokio::sync::mpsc::Receiver;
+use tokio::{
+    sync::{
+        mpsc::{self, error::SendError, error::TrySendError, Receiver, Sender},
+        oneshot, Mutex,
+    },
+    task::JoinHandle,
+};
+use tracing::Instrument;
 
+/// the actor, running in its own task and talking with others by messages
+///
+/// # example
+/// ```ignore
+/// let ctx = Context::new(100);
+/// let addr = ctx.addr();
+/// dctor::spawn(actor, ctx);
+/// addr.send(message).await;
+/// ```
 #[async_trait]
-pub(crate) trait Dctor {
-    type InboxItem;
+pub(crate) trait Dctor: Send + Sized + 'static {
+    type InboxItem: Send + 'static;
+
+    /// called once before listening
+    async fn started(&mut self, _ctx: &mut Context<Self>) {}
+
+    /// the main loop of actor, the actor stops once it returns
+    async fn listen(&mut self, ctx: &mut Context<Self>);
 
-    async fn listen(&mut self);
+    /// called once after listening, even if the actor is stopped by its inbox closed
+    async fn stopping(&mut self, _ctx: &mut Context<Self>) {}
 }
 
 pub(crate) type Inbox<T> = Receiver<T>;
+
+/// the typed handle of actor, for sending messages to it
+pub(crate) struct Addr<A: Dctor> {
+    sender: Arc<Sender<A::InboxItem>>,
+}
+
+impl<A: Dctor> Clone for Addr<A> {
+    fn clone(&self) -> Self {
+        Addr {
+            sender: Arc::clone(&self.sender),
+        }
+    }
+}
+
+impl<A: Dctor> std::fmt::Debug for Addr<A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Addr")
+            .field("actor", &std::any::type_name::<A>())
+            .finish()
+    }
+}
+
+impl<A: Dctor> Addr<A> {
+    pub async fn send(&self, message: A::InboxItem) -> Result<(), SendError<A::InboxItem>> {
+        self.sender.send(message).await
+    }
+
+    /// send without waiting, fails if the inbox is full or closed
+    pub fn try_send(&self, message: A::InboxItem) -> Result<(), TrySendError<A::InboxItem>> {
+        self.sender.try_send(message)
+    }
+
+    /// send the message built with a reply channel, and wait for the reply
+    ///
+    /// return [`None`] if the actor stopped before replying
+    ///
+    /// # example
+    /// ```ignore
+    /// let stats = supervisor.request(SupervisorMessage::Stats).await;
+    /// ```
+    pub async fn request<R>(
+        &self,
+        message: impl FnOnce(oneshot::Sender<R>) -> A::InboxItem,
+    ) -> Option<R> {
+        let (tx, rx) = oneshot::channel();
+        self.send(message(tx)).await.ok()?;
+        rx.await.ok()
+    }
+
+    /// how many messages are queued in the inbox
+    pub fn queued(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    /// is the actor stopped?
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    /// are both addresses of the same actor?
+    pub fn same_actor(&self, other: &Self) -> bool {
+        self.sender.same_channel(&other.sender)
+    }
+}
+
+/// the messages to an actor sent without waiting,
+/// kept in order once its inbox is full, until [`Outbox::flush`] sends them
+///
+/// at most `capacity` messages are kept, messages beyond it are dropped
+pub(crate) struct Outbox<A: Dctor> {
+    addr: Addr<A>,
+    queue: VecDeque<A::InboxItem>,
+    capacity: usize,
+}
+
+impl<A: Dctor> Outbox<A> {
+    pub fn new(addr: Addr<A>, capacity: usize) -> Self {
+        Outbox {
+            addr,
+            queue: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// send the message after the kept ones, keep it if the inbox is full
+    ///
+    /// # Return
+    /// is the message sent or kept? false if it is dropped
+    pub fn send(&mut self, message: A::InboxItem) -> bool {
+        // keep the order, messages kept before should be sent first
+        if !self.queue.is_empty() {
+            return self.keep(message);
+        }
+        match self.addr.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(message)) => self.keep(message),
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+
+    fn keep(&mut self, message: A::InboxItem) -> bool {
+        if self.queue.len() >= self.capacity {
+            return false;
+        }
+        self.queue.push_back(message);
+        true
+    }
+
+    /// send the kept messages in order, as many as the inbox holds
+    ///
+    /// # Return
+    /// count of the messages dropped because the actor stopped
+    pub fn flush(&mut self) -> usize {
+        while let Some(message) = self.queue.pop_front() {
+            match self.addr.try_send(message) {
+                Ok(()) => {}
+                Err(TrySendError::Full(message)) => {
+                    self.queue.push_front(message);
+                    return 0;
+                }
+                Err(TrySendError::Closed(_)) => {
+                    let dropped = self.queue.len() + 1;
+                    self.queue.clear();
+                    return dropped;
+                }
+            }
+        }
+        0
+    }
+}
+
+/// the inbox and address of a running actor
+///
+/// the context keeps an address of the actor itself,
+/// so the actor keeps running until its [`Dctor::listen`] returns
+pub(crate) struct Context<A: Dctor> {
+    inbox: Inbox<A::InboxItem>,
+    addr: Addr<A>,
+}
+
+impl<A: Dctor> Context<A> {
+    /// construct the context of an actor, whose inbox holds `capacity` messages
+    pub fn new(capacity: usize) -> Self {
+        let (tx, rx) = mpsc::channel(capacity);
+        Context {
+            inbox: rx,
+            addr: Addr {
+                sender: Arc::new(tx),
+            },
+        }
+    }
+
+    /// address of the actor itself
+    pub fn addr(&self) -> Addr<A> {
+        self.addr.clone()
+    }
+
+    /// receive the next message, cancel safe
+    pub async fn recv(&mut self) -> Option<A::InboxItem> {
+        self.inbox.recv().await
+    }
+}
+
+/// what to do when a supervised actor panics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SupervisionStrategy {
+    /// let the actor stop
+    Stop,
+    /// construct the actor again and keep its address, at most `max_restarts` times
+    Restart { max_restarts: usize },
+}
+
+/// how a supervised actor stopped at last
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Exit {
+    /// its listen returned
+    Stopped,
+    /// it panicked, and is not restarted any more
+    Panicked,
+}
+
+async fn run<A: Dctor>(actor: &mut A, ctx: &mut Context<A>) {
+    actor.started(ctx).await;
+    actor.listen(ctx).await;
+    actor.stopping(ctx).await;
+}
+
+/// run the actor in a new task, the actor is given back once it stops
+///
+/// the actor runs in the current span of [`tracing`], where it is spawned
+pub(crate) fn spawn<A: Dctor>(mut actor: A, mut ctx: Context<A>) -> JoinHandle<A> {
+    tokio::spawn(
+        async move {
+            run(&mut actor, &mut ctx).await;
+            actor
+        }
+        .in_current_span(),
+    )
+}
+
+/// run the actor constructed by `factory` in a new task,
+/// and apply the `strategy` if it panics
+///
+/// the restarted actor keeps the same context and span,
+/// so the addresses held by others are still valid.
+/// the returned handle tells how the actor stopped at last
+pub(crate) fn spawn_supervised<A, F>(
+    mut factory: F,
+    ctx: Context<A>,
+    strategy: SupervisionStrategy,
+) -> JoinHandle<Exit>
+where
+    A: Dctor,
+    F: FnMut() -> A + Send + 'static,
+{
+    let ctx = Arc::new(Mutex::new(ctx));
+
+    tokio::spawn(
+        async move {
+            let mut restarts = 0;
+            loop {
+                let mut actor = factory();
+                let ctx = Arc::clone(&ctx).lock_owned().await;
+                let child = tokio::spawn(
+                    async move {
+                        let mut ctx = ctx;
+                        run(&mut actor, &mut ctx).await;
+                    }
+                    .in_current_span(),
+                );
+
+                match (child.await, strategy) {
+                    (Ok(()), _) => return Exit::Stopped,
+                    (Err(e), SupervisionStrategy::Restart { max_restarts })
+                        if e.is_panic() && restarts < max_restarts =>
+                    {
+                        restarts += 1;
+                        tracing::warn!(
+                            actor = std::any::type_name::<A>(),
+                            "actor panicked, restart {restarts}/{max_restarts}"
+                        );
+                    }
+                    (Err(_), _) => return Exit::Panicked,
+                }
+            }
+        }
+        .in_current_span(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    enum CounterMessage {
+        Add(usize),
+        Get(oneshot::Sender<usize>),
+        Panic,
+        Stop,
+    }
+
+    struct Counter {
+        count: usize,
+        started: Arc<AtomicUsize>,
+        stopping: Arc<AtomicUsize>,
+    }
+
+    impl Counter {
+        fn new(started: &Arc<AtomicUsize>, stopping: &Arc<AtomicUsize>) -> Self {
+            Counter {
+                count: 0,
+                started: Arc::clone(started),
+                stopping: Arc::clone(stopping),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Dctor for Counter {
+        type InboxItem = CounterMessage;
+
+        async fn started(&mut self, _ctx: &mut Context<Self>) {
+            self.started.fetch_add(1, Ordering::SeqCst);
+        }
+
+        async fn listen(&mut self, ctx: &mut Context<Self>) {
+            while let Some(msg) = ctx.recv().await {
+                match msg {
+                    CounterMessage::Add(n) => self.count += n,
+                    CounterMessage::Get(reply) => {
+                        let _ = reply.send(self.count);
+                    }
+                    CounterMessage::Panic => panic!("counter panicked"),
+                    CounterMessage::Stop => return,
+                }
+            }
+        }
+
+        async fn stopping(&mut self, _ctx: &mut Context<Self>) {
+            self.stopping.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn request_replies() {
+        let (started, stopping) = Default::default();
+        let ctx = Context::new(10);
+        let addr = ctx.addr();
+        let handler = spawn(Counter::new(&started, &stopping), ctx);
+
+        addr.send(CounterMessage::Add(2)).await.unwrap();
+        addr.send(CounterMessage::Add(3)).await.unwrap();
+
+        assert_eq!(Some(5), addr.request(CounterMessage::Get).await);
+
+        addr.send(CounterMessage::Stop).await.unwrap();
+        let counter = handler.await.unwrap();
+        assert_eq!(5, counter.count);
+        assert_eq!(1, started.load(Ordering::SeqCst));
+        assert_eq!(1, stopping.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn restart_panicked_actor() {
+        let (started, stopping) = (Arc::default(), Arc::default());
+        let ctx = Context::new(10);
+        let addr = ctx.addr();
+        let factory = {
+            let (started, stopping) = (Arc::clone(&started), Arc::clone(&stopping));
+            move || Counter::new(&started, &stopping)
+        };
+        let handler = spawn_supervised(
+            factory,
+            ctx,
+            SupervisionStrategy::Restart { max_restarts: 1 },
+        );
+
+        addr.send(CounterMessage::Add(2)).await.unwrap();
+        addr.send(CounterMessage::Panic).await.unwrap();
+
+        // the restarted actor keeps the address, but not the state
+        addr.send(CounterMessage::Add(3)).await.unwrap();
+        assert_eq!(Some(3), addr.request(CounterMessage::Get).await);
+        assert_eq!(2, started.load(Ordering::SeqCst));
+
+        // no more restart
+        addr.send(CounterMessage::Panic).await.unwrap();
+        assert_eq!(Exit::Panicked, handler.await.unwrap());
+        assert_eq!(None, addr.request(CounterMessage::Get).await);
+    }
+
+    #[tokio::test]
+    async fn stop_panicked_actor() {
+        let (started, stopping) = (Arc::default(), Arc::default());
+        let ctx = Context::new(10);
+        let addr = ctx.addr();
+        let factory = {
+            let (started, stopping) = (Arc::clone(&started), Arc::clone(&stopping));
+            move || Counter::new(&started, &stopping)
+        };
+        let handler = spawn_supervised(factory, ctx, SupervisionStrategy::Stop);
+
+        addr.send(CounterMessage::Panic).await.unwrap();
+
+        assert_eq!(Exit::Panicked, handler.await.unwrap());
+        assert!(addr.is_closed());
+        assert_eq!(1, started.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn supervised_actor_stopped() {
+        let (started, stopping) = (Arc::default(), Arc::default());
+        let ctx = Context::new(10);
+        let addr = ctx.addr();
+        let factory = move || Counter::new(&started, &stopping);
+        let handler = spawn_supervised(factory, ctx, SupervisionStrategy::Stop);
+
+        addr.send(CounterMessage::Stop).await.unwrap();
+
+        assert_eq!(Exit::Stopped, handler.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn outbox_keeps_order_and_bound() {
+        let mut ctx = Context::<Counter>::new(1);
+        let mut outbox = Outbox::new(ctx.addr(), 2);
+
+        for n in 1..=3 {
+            assert!(outbox.send(CounterMessage::Add(n)));
+        }
+        assert!(!outbox.send(CounterMessage::Add(4)));
+
+        for n in 1..=3 {
+            assert!(matches!(ctx.recv().await, Some(CounterMessage::Add(m)) if m == n));
+            assert_eq!(0, outbox.flush());
+        }
+        assert_eq!(0, ctx.addr().queued());
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    fmt::{self, Debug, Formatter},
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+
+use super::{plugin::Plugins, server::SERVER_NAME};
+
+/// decide who could login, before the client is handed to its supervisor
+///
+/// a closure `Fn(&str, Option<SocketAddr>) -> Result<(), String>` is an [`Auth`] too
+#[async_trait]
+pub trait Auth: Send + Sync + 'static {
+    /// Ok if the user could login from `peer`, otherwise the reason told to the client,
+    /// `peer` is [`None`] for a local client
+    async fn login(&self, username: &str, peer: Option<SocketAddr>) -> Result<(), String>;
+}
+
+#[async_trait]
+impl<F> Auth for F
+where
+    F: Fn(&str, Option<SocketAddr>) -> Result<(), String> + Send + Sync + 'static,
+{
+    async fn login(&self, username: &str, peer: Option<SocketAddr>) -> Result<(), String> {
+        self(username, peer)
+    }
+}
+
+/// see every message sent by the local users, before it is routed
+///
+/// the handler runs in the task of sender, so it should never block.
+/// a closure `Fn(&str, &str, String) -> Option<String>` is a [`MessageHandler`] too
+pub trait MessageHandler: Send + Sync + 'static {
+    /// the message to route, maybe rewritten, [`None`] to drop it
+    fn on_message(&self, sender: &str, receiver: &str, message: String) -> Option<String>;
+}
+
+impl<F> MessageHandler for F
+where
+    F: Fn(&str, &str, String) -> Option<String> + Send + Sync + 'static,
+{
+    fn on_message(&self, sender: &str, receiver: &str, message: String) -> Option<String> {
+        self(sender, receiver, message)
+    }
+}
+
+/// the message handlers, run in the order they are added
+#[derive(Clone, Default)]
+pub(crate) struct Handlers(Vec<Arc<dyn MessageHandler>>);
+
+impl Handlers {
+    pub fn push(&mut self, handler: Arc<dyn MessageHandler>) {
+        self.0.push(handler);
+    }
+
+    /// pass the message through every handler, [`None`] if any of them dropped it
+    pub fn handle(&self, sender: &str, receiver: &str, message: String) -> Option<String> {
+        self.0.iter().try_fold(message, |message, handler| {
+            handler.on_message(sender, receiver, message)
+        })
+    }
+}
+
+impl Debug for Handlers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Handlers").field(&self.0.len()).finish()
+    }
+}
+
+/// the custom behaviors of an embedded server
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    /// anyone could login if [`None`]
+    pub auth: Option<Arc<dyn Auth>>,
+    pub handlers: Handlers,
+    /// the virtual users, their names are reserved like the name of server
+    pub plugins: Plugins,
+}
+
+impl Hooks {
+    /// Ok if the user could login from `peer`, otherwise the reason told to the client
+    pub async fn authorize(&self, username: &str, peer: Option<SocketAddr>) -> Result<(), String> {
+        if username == SERVER_NAME || self.plugins.get(username).is_some() {
+            return Err(format!("{username} is reserved"));
+        }
+        match &self.auth {
+            Some(auth) => auth.login(username, peer).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Debug for Hooks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("auth", &self.auth.is_some())
+            .field("handlers", &self.handlers)
+            .field("plugins", &self.plugins)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handlers_run_in_order_until_dropped() {
+        let mut handlers = Handlers::default();
+        assert_eq!(
+            Some("hi".to_string()),
+            handlers.handle("alice", "bob", "hi".to_string())
+        );
+
+        handlers.push(Arc::new(|_: &str, _: &str, message: String| {
+            Some(message.to_uppercase())
+        }));
+        handlers.push(Arc::new(|_: &str, receiver: &str, message: String| {
+            (receiver != "carol").then(|| format!("{message}!"))
+        }));
+        assert_eq!(
+            Some("HI!".to_string()),
+            handlers.handle("alice", "bob", "hi".to_string())
+        );
+        assert_eq!(None, handlers.handle("alice", "carol", "hi".to_string()));
+    }
+
+    #[tokio::test]
+    async fn server_name_is_reserved() {
+        let hooks = Hooks::default();
+        assert!(hooks.authorize("alice", None).await.is_ok());
+        assert_eq!(
+            Err(format!("{SERVER_NAME} is reserved")),
+            hooks.authorize(SERVER_NAME, None).await
+        );
+    }
+}
@@ -1,4 +1,14 @@
-mod client;
-mod dctor;
+pub(crate) mod admission;
+pub(crate) mod client;
+pub(crate) mod cluster;
+pub(crate) mod connection;
+#[allow(clippy::module_inception)]
+pub(crate) mod dctor;
+pub(crate) mod hooks;
+pub(crate) mod plugin;
+pub(crate) mod rate_limit;
 pub(crate) mod server;
-mod supervisor;
+pub(crate) mod shard;
+pub(crate) mod supervisor;
+#[cfg(test)]
+pub(crate) mod testing;
@@ -0,0 +1,210 @@
+use std::{
+    fmt::{self, Debug, Formatter},
+    io,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use dvorak_message::message::{Message, MessageReader, MessageType};
+use tokio::{
+    io::{DuplexStream, ReadHalf, WriteHalf},
+    sync::mpsc::{self, error::TrySendError},
+};
+use tracing::Instrument;
+
+use super::{connection::Connection, hooks::MessageHandler};
+
+/// how many messages of a plugin could be queued for sending
+const PLUGIN_OUTBOX_CAPACITY: usize = 100;
+
+/// a bot living in the server, like an echo bot or a webhook relay
+///
+/// it is a virtual user named [`Plugin::name`], so users message it like anyone else,
+/// but its messages are not limited by the rate limits of users,
+/// only by its outbox, see [`PluginHandle::send_text`].
+/// nobody else could login as its name.
+/// it is started by the supervisor owning its name, and started again once disconnected
+#[async_trait]
+pub trait Plugin: Send + Sync + 'static {
+    /// username of the virtual user
+    fn name(&self) -> &str;
+
+    /// the plugin is online, keep the handle to send messages at any time, like reminders,
+    /// called again once the plugin is restarted
+    async fn started(&self, _handle: PluginHandle) {}
+
+    /// a message sent to the plugin, the next one waits until it returns,
+    /// so spawn the slow work
+    async fn on_message(&self, handle: &PluginHandle, sender: &str, message: String);
+
+    /// see every message sent by the local users before it is routed, like a [`MessageHandler`],
+    /// the message to route, maybe rewritten, [`None`] to drop it
+    fn on_route(&self, _sender: &str, _receiver: &str, message: String) -> Option<String> {
+        Some(message)
+    }
+}
+
+/// send messages as a plugin
+#[derive(Debug, Clone)]
+pub struct PluginHandle {
+    name: String,
+    outbox: mpsc::Sender<Message>,
+}
+
+impl PluginHandle {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// send the text to the user, as the plugin
+    ///
+    /// it never waits, the text is refused with [`io::ErrorKind::WouldBlock`]
+    /// if too many messages of the plugin are queued
+    pub fn send_text(&self, to: &str, text: &str) -> io::Result<()> {
+        let text = MessageType::Text(text.to_string());
+        let message = Message::new(text, self.name.clone(), to.to_string());
+        self.outbox.try_send(message).map_err(|e| match e {
+            TrySendError::Full(_) => {
+                io::Error::new(io::ErrorKind::WouldBlock, "plugin outbox is full")
+            }
+            TrySendError::Closed(_) => {
+                io::Error::new(io::ErrorKind::NotConnected, "plugin is offline")
+            }
+        })
+    }
+}
+
+/// the plugins of a server, their names are reserved
+#[derive(Clone, Default)]
+pub(crate) struct Plugins(Vec<Arc<dyn Plugin>>);
+
+impl Plugins {
+    pub fn push(&mut self, plugin: Arc<dyn Plugin>) {
+        self.0.push(plugin);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Plugin>> {
+        self.0.iter().find(|plugin| plugin.name() == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn Plugin>> {
+        self.0.iter()
+    }
+}
+
+impl Debug for Plugins {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Plugins").field(&self.0.len()).finish()
+    }
+}
+
+/// the plugin seeing the messages before routed
+pub(crate) struct Route(pub Arc<dyn Plugin>);
+
+impl MessageHandler for Route {
+    fn on_message(&self, sender: &str, receiver: &str, message: String) -> Option<String> {
+        self.0.on_route(sender, receiver, message)
+    }
+}
+
+/// the echo bot, replies every message to its sender
+#[derive(Debug, Clone)]
+pub struct Echo {
+    name: String,
+}
+
+impl Echo {
+    pub fn new(name: impl Into<String>) -> Self {
+        Echo { name: name.into() }
+    }
+}
+
+#[async_trait]
+impl Plugin for Echo {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn on_message(&self, handle: &PluginHandle, sender: &str, message: String) {
+        let _ = handle.send_text(sender, &message);
+    }
+}
+
+/// run the plugin over an in-memory pipe, the returned connection is its virtual user.
+///
+/// the pipe is closed once the plugin stops, or panics,
+/// and the plugin stops once the connection is closed
+pub(crate) fn start(plugin: Arc<dyn Plugin>) -> Connection {
+    let name = plugin.name().to_string();
+    let span = tracing::info_span!("plugin", name = %name);
+    let (connection, pipe) = Connection::pipe(None);
+    let (reader, writer) = tokio::io::split(pipe);
+    let (outbox, outgoing) = mpsc::channel(PLUGIN_OUTBOX_CAPACITY);
+    let handle = PluginHandle { name, outbox };
+
+    let outgoing = tokio::spawn(forward_outgoing(outgoing, writer).instrument(span.clone()));
+    let incoming = tokio::spawn(serve(plugin, handle, reader).instrument(span.clone()));
+    let watch = async move {
+        if let Err(e) = incoming.await {
+            if e.is_panic() {
+                tracing::error!("plugin panicked");
+            }
+        }
+        // the handles kept by the plugin never close the pipe
+        outgoing.abort();
+    };
+    tokio::spawn(watch.instrument(span));
+    connection
+}
+
+/// pass the messages sent to the plugin, until it is disconnected
+async fn serve(plugin: Arc<dyn Plugin>, handle: PluginHandle, reader: ReadHalf<DuplexStream>) {
+    plugin.started(handle.clone()).await;
+    tracing::info!("plugin started");
+    // frames from the server are always valid, no limits needed
+    let mut frames = MessageReader::new(reader);
+    while let Ok(Some(message)) = frames.read().await {
+        match message.message_type {
+            MessageType::Text(text) => {
+                plugin.on_message(&handle, &message.username, text).await;
+            }
+            MessageType::Error(report) => tracing::warn!(%report, "plugin reported"),
+            _ => {}
+        }
+    }
+    tracing::info!("plugin disconnected");
+}
+
+/// pass the messages sent by the plugin into the pipe
+async fn forward_outgoing(
+    mut outgoing: mpsc::Receiver<Message>,
+    mut writer: WriteHalf<DuplexStream>,
+) {
+    while let Some(message) = outgoing.recv().await {
+        if Message::send(&mut writer, message).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn full_outbox_never_waits() {
+        let (outbox, mut outgoing) = mpsc::channel(1);
+        let handle = PluginHandle {
+            name: "echo".to_string(),
+            outbox,
+        };
+        handle.send_text("alice", "first").unwrap();
+        let error = handle.send_text("alice", "second").unwrap_err();
+        assert_eq!(io::ErrorKind::WouldBlock, error.kind());
+
+        outgoing.recv().await.unwrap();
+        drop(outgoing);
+        let error = handle.send_text("alice", "third").unwrap_err();
+        assert_eq!(io::ErrorKind::NotConnected, error.kind());
+    }
+}
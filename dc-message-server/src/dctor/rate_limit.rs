@@ -0,0 +1,433 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// how many seconds of the rate a bucket holds, the burst allowed
+const BURST_SECONDS: f64 = 2.0;
+/// how many times a client is warned before muted
+const WARNINGS_BEFORE_MUTE: u32 = 2;
+/// how long a flooding client is muted
+const MUTE_DURATION: Duration = Duration::from_secs(10);
+/// strikes are forgotten if the client behaves for this long
+const STRIKE_RESET: Duration = Duration::from_secs(60);
+/// the buckets of ips are pruned once there are more of them
+const MAX_IDLE_IP_BUCKETS: usize = 1024;
+/// the buckets and strikes of users are pruned once there are more of them
+const MAX_IDLE_USERS: usize = 1024;
+
+/// limits of messages and bytes per second, 0 for unlimited
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RateLimits {
+    pub messages_per_second: f64,
+    pub bytes_per_second: f64,
+}
+
+/// a token bucket, refilled at `rate` per second up to `capacity`
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// [`None`] if the rate is unlimited
+    fn new(rate: f64, now: Instant) -> Option<Self> {
+        (rate > 0.0).then(|| {
+            let capacity = rate * BURST_SECONDS;
+            TokenBucket {
+                rate,
+                capacity,
+                tokens: capacity,
+                refilled_at: now,
+            }
+        })
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.capacity);
+        self.refilled_at = now;
+    }
+}
+
+/// the buckets of messages and bytes of a user or an ip
+#[derive(Debug)]
+struct Buckets {
+    messages: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl Buckets {
+    fn new(limits: RateLimits, now: Instant) -> Self {
+        Buckets {
+            messages: TokenBucket::new(limits.messages_per_second, now),
+            bytes: TokenBucket::new(limits.bytes_per_second, now),
+        }
+    }
+
+    /// the buckets limited, and the tokens a message of `bytes` takes from them
+    fn wanted(&mut self, bytes: usize) -> impl Iterator<Item = (&mut TokenBucket, f64)> + '_ {
+        [(&mut self.messages, 1.0), (&mut self.bytes, bytes as f64)]
+            .into_iter()
+            .filter_map(|(bucket, tokens)| bucket.as_mut().map(|bucket| (bucket, tokens)))
+    }
+
+    /// are there tokens for a message of `bytes`? nothing is taken
+    fn has_tokens(&mut self, bytes: usize, now: Instant) -> bool {
+        self.wanted(bytes).all(|(bucket, tokens)| {
+            bucket.refill(now);
+            // a message larger than the burst could never be sent otherwise
+            bucket.tokens >= tokens.min(bucket.capacity)
+        })
+    }
+
+    fn take(&mut self, bytes: usize) {
+        for (bucket, tokens) in self.wanted(bytes) {
+            bucket.tokens -= tokens;
+        }
+    }
+
+    /// are the buckets full, so forgetting them changes nothing?
+    fn is_idle(&mut self, now: Instant) -> bool {
+        [&mut self.messages, &mut self.bytes]
+            .into_iter()
+            .flatten()
+            .all(|bucket| {
+                bucket.refill(now);
+                bucket.tokens >= bucket.capacity
+            })
+    }
+}
+
+/// the buckets and strikes of a user, kept while the user reconnects
+#[derive(Debug)]
+struct UserLimit {
+    buckets: Buckets,
+    escalation: Escalation,
+}
+
+#[derive(Debug, Default)]
+struct Limited {
+    users: HashMap<String, UserLimit>,
+    ips: HashMap<IpAddr, Buckets>,
+}
+
+/// what the limiter decides for a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Allowed,
+    /// the limits of the user are exceeded
+    Exceeded(Penalty),
+    /// the limits of the ip are exceeded, maybe by other users of it,
+    /// the message is dropped but the user is not struck
+    Throttled,
+    /// the user is muted for a while still
+    Muted(Duration),
+}
+
+/// the limits of users and ips, shared by the clients of every supervisor
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    user_limits: RateLimits,
+    ip_limits: RateLimits,
+    limited: Arc<Mutex<Limited>>,
+}
+
+impl RateLimiter {
+    pub fn new(user_limits: RateLimits, ip_limits: RateLimits) -> Self {
+        RateLimiter {
+            user_limits,
+            ip_limits,
+            limited: Arc::default(),
+        }
+    }
+
+    /// apply the limits of the user and the ip to a message of `bytes`,
+    /// tokens are taken only if both of them allow it and the user is not muted,
+    /// the user is struck only if its own limits are exceeded
+    pub fn check(&self, username: &str, ip: Option<IpAddr>, bytes: usize, now: Instant) -> Verdict {
+        if self.user_limits == RateLimits::default() && self.ip_limits == RateLimits::default() {
+            return Verdict::Allowed;
+        }
+        let mut limited = self.limited.lock().unwrap();
+        let Limited { users, ips } = &mut *limited;
+
+        if users.len() > MAX_IDLE_USERS && !users.contains_key(username) {
+            users.retain(|_, user| !user.is_idle(now));
+        }
+        let user = users
+            .entry(username.to_string())
+            .or_insert_with(|| UserLimit {
+                buckets: Buckets::new(self.user_limits, now),
+                escalation: Escalation::default(),
+            });
+        let ip = ip.filter(|_| self.ip_limits != RateLimits::default());
+        let mut ip_buckets = ip.map(|ip| {
+            if ips.len() > MAX_IDLE_IP_BUCKETS && !ips.contains_key(&ip) {
+                ips.retain(|_, buckets| !buckets.is_idle(now));
+            }
+            ips.entry(ip)
+                .or_insert_with(|| Buckets::new(self.ip_limits, now))
+        });
+
+        // both are checked before any token is taken
+        if !user.buckets.has_tokens(bytes, now) {
+            return Verdict::Exceeded(user.escalation.strike(now));
+        }
+        // the messages dropped while muted take nothing,
+        // but flooding on is still struck once the tokens are used up
+        if let Some(left) = user.escalation.muted_for(now) {
+            return Verdict::Muted(left);
+        }
+        if let Some(buckets) = &mut ip_buckets {
+            if !buckets.has_tokens(bytes, now) {
+                return Verdict::Throttled;
+            }
+        }
+        user.buckets.take(bytes);
+        if let Some(buckets) = ip_buckets {
+            buckets.take(bytes);
+        }
+        Verdict::Allowed
+    }
+}
+
+impl UserLimit {
+    /// are the buckets full and the strikes forgotten, so forgetting the user changes nothing?
+    fn is_idle(&mut self, now: Instant) -> bool {
+        self.buckets.is_idle(now) && self.escalation.is_idle(now)
+    }
+}
+
+/// what a flooding client gets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Penalty {
+    /// the message is dropped
+    Warning,
+    /// every message is dropped for a while
+    Mute(Duration),
+    Disconnect,
+}
+
+/// escalate the penalty from warning to mute to disconnect,
+/// as the client keeps flooding
+#[derive(Debug, Default)]
+struct Escalation {
+    strikes: u32,
+    struck_at: Option<Instant>,
+    muted_until: Option<Instant>,
+}
+
+impl Escalation {
+    /// the client exceeded the limits
+    fn strike(&mut self, now: Instant) -> Penalty {
+        if matches!(self.struck_at, Some(at) if now.saturating_duration_since(at) > STRIKE_RESET) {
+            self.strikes = 0;
+        }
+        self.strikes += 1;
+        self.struck_at = Some(now);
+
+        match self.strikes {
+            strikes if strikes <= WARNINGS_BEFORE_MUTE => Penalty::Warning,
+            strikes if strikes == WARNINGS_BEFORE_MUTE + 1 => {
+                self.muted_until = Some(now + MUTE_DURATION);
+                Penalty::Mute(MUTE_DURATION)
+            }
+            _ => Penalty::Disconnect,
+        }
+    }
+
+    /// how long the client is still muted
+    fn muted_for(&self, now: Instant) -> Option<Duration> {
+        self.muted_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    /// are the strikes forgotten, and the client is not muted?
+    fn is_idle(&self, now: Instant) -> bool {
+        let forgotten = match self.struck_at {
+            Some(at) => now.saturating_duration_since(at) > STRIKE_RESET,
+            None => true,
+        };
+        forgotten && self.muted_for(now).is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Buckets {
+        fn allow(&mut self, bytes: usize, now: Instant) -> bool {
+            let allowed = self.has_tokens(bytes, now);
+            if allowed {
+                self.take(bytes);
+            }
+            allowed
+        }
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let now = Instant::now();
+        let limits = RateLimits {
+            messages_per_second: 1.0,
+            bytes_per_second: 0.0,
+        };
+        let mut buckets = Buckets::new(limits, now);
+
+        // burst of 2 seconds
+        assert!(buckets.allow(10, now));
+        assert!(buckets.allow(10, now));
+        assert!(!buckets.allow(10, now));
+        assert!(buckets.allow(10, now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn tokens_are_taken_only_if_all_buckets_allow() {
+        let now = Instant::now();
+        let limits = RateLimits {
+            messages_per_second: 10.0,
+            bytes_per_second: 5.0,
+        };
+        let mut buckets = Buckets::new(limits, now);
+
+        assert!(buckets.allow(10, now));
+        assert!(!buckets.allow(1, now));
+        assert_eq!(19.0, buckets.messages.as_ref().unwrap().tokens);
+    }
+
+    #[test]
+    fn ips_share_buckets() {
+        let now = Instant::now();
+        let limits = RateLimits {
+            messages_per_second: 1.0,
+            bytes_per_second: 0.0,
+        };
+        let limiter = RateLimiter::new(RateLimits::default(), limits);
+        let another = limiter.clone();
+        let ip = "127.0.0.1".parse().unwrap();
+
+        assert_eq!(Verdict::Allowed, limiter.check("alice", Some(ip), 1, now));
+        assert_eq!(Verdict::Allowed, another.check("bob", Some(ip), 1, now));
+        assert_eq!(Verdict::Throttled, limiter.check("alice", Some(ip), 1, now));
+        let ip = "127.0.0.2".parse().unwrap();
+        assert_eq!(Verdict::Allowed, limiter.check("alice", Some(ip), 1, now));
+    }
+
+    #[test]
+    fn user_is_limited_over_reconnects() {
+        let now = Instant::now();
+        let limits = RateLimits {
+            messages_per_second: 1.0,
+            bytes_per_second: 0.0,
+        };
+        let limiter = RateLimiter::new(limits, RateLimits::default());
+        let reconnected = limiter.clone();
+
+        assert_eq!(Verdict::Allowed, limiter.check("alice", None, 1, now));
+        assert_eq!(Verdict::Allowed, limiter.check("alice", None, 1, now));
+        for penalty in [
+            Penalty::Warning,
+            Penalty::Warning,
+            Penalty::Mute(MUTE_DURATION),
+        ] {
+            assert_eq!(
+                Verdict::Exceeded(penalty),
+                reconnected.check("alice", None, 1, now)
+            );
+        }
+        let later = now + Duration::from_secs(1);
+        assert_eq!(
+            Verdict::Muted(MUTE_DURATION - Duration::from_secs(1)),
+            reconnected.check("alice", None, 1, later)
+        );
+        assert_eq!(Verdict::Allowed, limiter.check("bob", None, 1, now));
+    }
+
+    #[test]
+    fn muted_user_keeps_tokens() {
+        let now = Instant::now();
+        let limits = RateLimits {
+            messages_per_second: 1.0,
+            bytes_per_second: 0.0,
+        };
+        let limiter = RateLimiter::new(limits, RateLimits::default());
+        limiter.check("alice", None, 1, now);
+        limiter.check("alice", None, 1, now);
+        for _ in 0..3 {
+            limiter.check("alice", None, 1, now);
+        }
+
+        // the bucket refills while muted, nothing is taken by the messages dropped
+        for second in 1..10 {
+            let later = now + Duration::from_secs(second);
+            assert!(matches!(
+                limiter.check("alice", None, 1, later),
+                Verdict::Muted(_)
+            ));
+        }
+        let unmuted = now + MUTE_DURATION;
+        assert_eq!(Verdict::Allowed, limiter.check("alice", None, 1, unmuted));
+        assert_eq!(Verdict::Allowed, limiter.check("alice", None, 1, unmuted));
+        // the strikes are not forgotten yet
+        assert_eq!(
+            Verdict::Exceeded(Penalty::Disconnect),
+            limiter.check("alice", None, 1, unmuted)
+        );
+    }
+
+    #[test]
+    fn tokens_are_taken_only_if_user_and_ip_allow() {
+        let now = Instant::now();
+        let limits = RateLimits {
+            messages_per_second: 1.0,
+            bytes_per_second: 0.0,
+        };
+        let limiter = RateLimiter::new(limits, limits);
+        let ip = "127.0.0.1".parse().unwrap();
+
+        // the ip is used up by bob
+        limiter.check("bob", Some(ip), 1, now);
+        limiter.check("bob", Some(ip), 1, now);
+        // alice is throttled, but not struck for bob
+        for _ in 0..5 {
+            assert_eq!(Verdict::Throttled, limiter.check("alice", Some(ip), 1, now));
+        }
+        // alice keeps her tokens for another ip
+        let ip = "127.0.0.2".parse().unwrap();
+        assert_eq!(Verdict::Allowed, limiter.check("alice", Some(ip), 1, now));
+        assert_eq!(Verdict::Allowed, limiter.check("alice", Some(ip), 1, now));
+    }
+
+    #[test]
+    fn escalate_from_warning_to_disconnect() {
+        let now = Instant::now();
+        let mut escalation = Escalation::default();
+
+        assert_eq!(Penalty::Warning, escalation.strike(now));
+        assert_eq!(Penalty::Warning, escalation.strike(now));
+        assert_eq!(None, escalation.muted_for(now));
+        assert_eq!(Penalty::Mute(MUTE_DURATION), escalation.strike(now));
+        assert_eq!(Some(MUTE_DURATION), escalation.muted_for(now));
+        assert_eq!(None, escalation.muted_for(now + MUTE_DURATION));
+        assert_eq!(Penalty::Disconnect, escalation.strike(now));
+    }
+
+    #[test]
+    fn strikes_are_forgotten() {
+        let now = Instant::now();
+        let mut escalation = Escalation::default();
+        escalation.strike(now);
+        escalation.strike(now);
+
+        let later = now + STRIKE_RESET + Duration::from_secs(1);
+        assert_eq!(Penalty::Warning, escalation.strike(later));
+    }
+}
@@ -1,138 +1,627 @@
-use std::sync::Arc;
+use std::{future::Future, io, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
-use super::dctor::Dctor;
-use super::supervisor::{SupervisorMessage, SupervisorSender};
+use super::admission::{Admission, Permit, Refusal};
+use super::client::ClientConfig;
+use super::cluster::{Cluster, ClusterMessage, ClusterSender};
+use super::connection::{self, Connection};
+use super::dctor::{self, Context, Dctor};
+use super::hooks::Hooks;
+use super::rate_limit::RateLimiter;
+use super::shard::Shards;
+use super::supervisor::SupervisorMessage;
 
-use dvorak_message::message::{Message, MessageType};
-use tokio::io::{stdin, AsyncBufReadExt};
-use tokio::sync::mpsc;
-use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::{
-    io::BufReader,
-    net::{TcpListener, TcpStream},
-};
+use async_trait::async_trait;
+use bytes::BytesMut;
+use dvorak_message::message::{DecodeLimits, Message, MessageReader, MessageType};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::time::timeout;
+use tracing::{field, Instrument, Span};
 
 use super::supervisor::ClientSupervisor;
+use crate::builder::Config;
+use crate::metrics::{self, Counters};
+use crate::{console, control, json, websocket};
+
+pub(crate) use dvorak_message::message::SERVER_NAME;
+
+/// the transport an incoming client comes over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Transport {
+    Tcp,
+    /// the frames are carried as binary WebSocket messages
+    WebSocket,
+    /// the messages are encoded as JSON lines
+    Json,
+    /// the frames over a Unix-domain socket, for the local clients
+    Unix,
+    /// the frames over a connection handed by [`ServerMessage::Connect`]
+    Other,
+}
+
+/// the transports over a TCP listener, whose connections are established by [`Server::connect`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TcpTransport {
+    Tcp,
+    WebSocket,
+    Json,
+}
+
+impl From<TcpTransport> for Transport {
+    fn from(transport: TcpTransport) -> Self {
+        match transport {
+            TcpTransport::Tcp => Transport::Tcp,
+            TcpTransport::WebSocket => Transport::WebSocket,
+            TcpTransport::Json => Transport::Json,
+        }
+    }
+}
+
+/// why an incoming client is disconnected before it logins
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DisconnectReason {
+    /// it sent something else than Login
+    NeedLogin,
+    /// it did not send Login in time
+    LoginTimeout,
+    /// it is refused by the auth hook
+    Refused,
+}
+
+impl DisconnectReason {
+    pub const ALL: [Self; 3] = [Self::NeedLogin, Self::LoginTimeout, Self::Refused];
+
+    /// told to the client, and labels the metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NeedLogin => "need login",
+            Self::LoginTimeout => "login timeout",
+            Self::Refused => "refused",
+        }
+    }
+}
+
+/// Actor Message for Server
+#[derive(Debug)]
+pub(crate) enum ServerMessage {
+    /// a client over the transport the server does not listen itself, like an in-memory pipe,
+    /// it logs in as the clients of listeners
+    Connect(Connection),
+    /// quit the whole application
+    Quit,
+}
 
 /// representing the server,
 /// listening the incoming client and io,
 /// start and terminal the whole application
 ///
 /// # example
-/// ```
-/// let server = Server::new("127.0.0.1:9998");
-/// server.listen();
+/// ```ignore
+/// let server = Server::new(&Config::default(), Hooks::default()).await?;
+/// dctor::spawn(server, Context::new(1)).await;
 /// ```
 pub struct Server {
     tcp_listener: TcpListener,
-    supervisor_sender: SupervisorSender,
-    sender: Arc<Sender<bool>>,
-    inbox: Receiver<bool>,
+    /// listener of the browser clients, [`None`] if not listened
+    websocket_listener: Option<TcpListener>,
+    /// listener of the clients speaking JSON lines, [`None`] if not listened
+    json_listener: Option<TcpListener>,
+    /// listener of the local clients, [`None`] if not listened
+    unix_listener: Option<UnixListener>,
+    /// path of the Unix-domain socket of clients, removed once the server quits
+    unix_socket: Option<PathBuf>,
+    /// admit the incoming connections by the allow and deny lists and the limits
+    admission: Admission,
+    /// how long an incoming client may take to login
+    login_timeout: Duration,
+    /// decide who could login
+    hooks: Arc<Hooks>,
+    /// limits of the frames from clients
+    decode_limits: DecodeLimits,
+    /// supervisors of all shards
+    shards: Shards,
+    /// link to other nodes, [`None`] if the server runs alone
+    cluster: Option<ClusterSender>,
+    /// read the admin commands from stdin
+    console: bool,
+    /// file of the banned users and ips
+    ban_file: Option<PathBuf>,
+    /// listener of the control socket, taken once the server started
+    control_listener: Option<UnixListener>,
+    /// path of the control socket, removed once the server quits
+    control_socket: Option<PathBuf>,
+    /// counters of this server, served as metrics
+    counters: Arc<Counters>,
 }
 
 impl Server {
-    /// construct a Server
-    pub async fn new(host: &str) -> Self {
-        let tcp_listener = TcpListener::bind(host).await.unwrap();
-        let (mut client_supervisor, supervisor_sender) = ClientSupervisor::new();
-        let (tx, rx) = mpsc::channel(1);
-
-        tokio::spawn(async move {
-            client_supervisor.listen().await;
-        });
-
-        println!("Server construct.");
-        Server {
+    /// construct a Server, and start the supervisors
+    pub async fn new(config: &Config, hooks: Hooks) -> io::Result<Self> {
+        let tcp_listener = TcpListener::bind(&config.host).await?;
+        let websocket_listener = match &config.websocket_addr {
+            Some(websocket_addr) => Some(TcpListener::bind(websocket_addr).await?),
+            None => None,
+        };
+        let json_listener = match &config.json_addr {
+            Some(json_addr) => Some(TcpListener::bind(json_addr).await?),
+            None => None,
+        };
+        let unix_listener = match &config.unix_socket {
+            Some(path) => Some(connection::bind_unix(path, config.unix_socket_mode)?),
+            None => None,
+        };
+
+        let cluster_ctx = config.cluster_listen.as_ref().map(|_| Context::new(100));
+        let cluster_sender = cluster_ctx.as_ref().map(Context::addr);
+        let shards = ClientSupervisor::start_shards(
+            config.shards,
+            config.overflow_policy,
+            cluster_sender.clone(),
+            ClientConfig {
+                rate_limiter: RateLimiter::new(config.user_limits, config.ip_limits),
+                decode_limits: config.decode_limits,
+                handlers: hooks.handlers.clone(),
+                plugins: hooks.plugins.clone(),
+                counters: config.counters.clone(),
+                log_bodies: config.log_bodies,
+                overflow_policies: Arc::new(config.overflow_policies.clone()),
+            },
+        );
+
+        if let (Some(cluster_listen), Some(ctx)) = (&config.cluster_listen, cluster_ctx) {
+            let listener = TcpListener::bind(cluster_listen).await?;
+            let cluster = Cluster::new(
+                cluster_listen.clone(),
+                config.cluster_secret.clone(),
+                listener,
+                config.peers.clone(),
+                shards.clone(),
+                config.log_bodies,
+            );
+            let _span = tracing::info_span!("cluster", node = %cluster_listen).entered();
+            dctor::spawn(cluster, ctx);
+        }
+
+        if let Some(ban_file) = &config.ban_file {
+            let bans = console::load_bans(ban_file).await?;
+            shards.reload(&bans).await;
+        }
+
+        let control_listener = match &config.control_socket {
+            Some(path) => {
+                let listener = control::bind(path)?;
+                tracing::info!(path = %path.display(), "control socket listening");
+                Some(listener)
+            }
+            None => None,
+        };
+
+        if let Some(metrics_addr) = &config.metrics_addr {
+            let listener = TcpListener::bind(metrics_addr).await?;
+            tracing::info!(address = %metrics_addr, "serving metrics");
+            tokio::spawn(metrics::serve(
+                listener,
+                shards.clone(),
+                config.counters.clone(),
+            ));
+        }
+
+        Ok(Server {
             tcp_listener,
-            supervisor_sender,
-            sender: Arc::new(tx),
-            inbox: rx,
+            websocket_listener,
+            json_listener,
+            unix_listener,
+            unix_socket: config.unix_socket.clone(),
+            admission: Admission::new(config.admission_policy.clone()),
+            login_timeout: config.login_timeout,
+            hooks: Arc::new(hooks),
+            decode_limits: config.decode_limits,
+            shards,
+            cluster: cluster_sender,
+            console: config.console,
+            ban_file: config.ban_file.clone(),
+            control_listener,
+            control_socket: config.control_socket.clone(),
+            counters: config.counters.clone(),
+        })
+    }
+
+    /// address of the TCP listener
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp_listener.local_addr()
+    }
+
+    pub fn shards(&self) -> &Shards {
+        &self.shards
+    }
+
+    /// establish the connection of incoming client over its transport,
+    /// [`None`] if the handshake of transport failed
+    async fn connect(
+        incoming_client: TcpStream,
+        transport: TcpTransport,
+        login_timeout: Duration,
+        limits: DecodeLimits,
+    ) -> Option<Connection> {
+        match transport {
+            TcpTransport::Tcp => Some(incoming_client.into()),
+            TcpTransport::Json => Some(json::accept(incoming_client, limits)),
+            TcpTransport::WebSocket => {
+                match timeout(login_timeout, websocket::accept(incoming_client, limits)).await {
+                    Ok(Ok(connection)) => Some(connection),
+                    Ok(Err(e)) => {
+                        tracing::warn!(error = %e, "websocket handshake failure");
+                        None
+                    }
+                    Err(_) => {
+                        tracing::warn!("websocket handshake timeout");
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// wait for the Login message of the incoming client, and ask `hooks` whether it could login,
+    /// forward the client to the supervisor owning it if login success, otherwise drop it
+    async fn handshake(
+        mut incoming_client: Connection,
+        permit: Permit,
+        shards: Shards,
+        login_timeout: Duration,
+        limits: DecodeLimits,
+        hooks: Arc<Hooks>,
+        counters: Arc<Counters>,
+    ) {
+        let check_login = Server::check_login(&mut incoming_client, limits, &counters);
+        let reason = match timeout(login_timeout, check_login).await {
+            Ok(Ok((username, buffered))) => {
+                Span::current().record("user", username.as_str());
+                let authorized = hooks.authorize(&username, incoming_client.peer()).await;
+                if let Err(reason) = authorized {
+                    counters.login_failure(DisconnectReason::Refused);
+                    tracing::warn!(%reason, "login refused");
+                    Server::notify(&mut incoming_client, MessageType::Error(reason)).await;
+                    return;
+                }
+                // the frames sent right after Login
+                let incoming_client = incoming_client.unread(buffered);
+                tracing::info!("login success");
+
+                let supervisor = shards.sender_of(&username);
+                let new_client =
+                    SupervisorMessage::NewClient(username, incoming_client, Some(permit));
+                if supervisor.send(new_client).await.is_err() {
+                    // the server is shutting down, the connection is dropped with the message
+                    tracing::warn!("supervisor is gone, drop the client");
+                }
+                return;
+            }
+            Ok(Err(())) => DisconnectReason::NeedLogin,
+            Err(_) => DisconnectReason::LoginTimeout,
+        };
+        counters.login_failure(reason);
+        tracing::warn!(reason = reason.as_str(), "login failure");
+
+        let error = MessageType::Error(reason.as_str().to_string());
+        Server::notify(&mut incoming_client, error).await;
+    }
+
+    /// tell the incoming client why it is refused, and drop the connection
+    async fn refuse(mut incoming_client: Connection, refusal: Refusal) {
+        Server::notify(
+            &mut incoming_client,
+            MessageType::Error(refusal.to_string()),
+        )
+        .await;
+    }
+
+    /// send a notice or an error from server to the client not logged in
+    async fn notify(incoming_client: &mut Connection, notice: MessageType) {
+        let notice = Message::new(notice, SERVER_NAME.to_string(), String::new());
+        let _ = Message::send(incoming_client, notice).await;
+    }
+
+    /// read the Login message
+    ///
+    /// # Return
+    /// tuple: (username, bytes read after the Login message)
+    async fn check_login(
+        connection: &mut Connection,
+        limits: DecodeLimits,
+        counters: &Counters,
+    ) -> Result<(String, BytesMut), ()> {
+        let mut reader = MessageReader::with_limits(connection, limits);
+        let message = reader.read().await.map_err(|e| {
+            counters.decode_error();
+            tracing::debug!(error = %e, "decode login failure");
+        })?;
+        let Some(message) = message else {
+            return Err(());
+        };
+        counters.received(&message.message_type);
+        if message.message_type != MessageType::Login {
+            return Err(());
+        }
+
+        let (_, buffered) = reader.into_parts();
+        Ok((message.username, buffered))
+    }
+
+    /// accept from the listener, never complete if there is no listener
+    async fn accept(listener: Option<&TcpListener>) -> io::Result<(TcpStream, SocketAddr)> {
+        match listener {
+            Some(listener) => listener.accept().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// accept from the Unix-domain socket, never complete if it is not listened
+    async fn accept_local(listener: Option<&UnixListener>) -> io::Result<UnixStream> {
+        match listener {
+            Some(listener) => listener.accept().await.map(|(stream, _)| stream),
+            None => std::future::pending().await,
         }
     }
 
-    pub async fn listen(&mut self) {
-        println!("Server Listening...");
+    /// admit the incoming client over TCP, and spawn its handshake
+    fn incoming(&self, incoming: io::Result<(TcpStream, SocketAddr)>, transport: TcpTransport) {
+        let (incoming_client, socket) = match incoming {
+            Ok(incoming) => incoming,
+            Err(e) => {
+                tracing::warn!(error = %e, ?transport, "accept client failure");
+                return;
+            }
+        };
+
+        let span = tracing::info_span!(
+            "connection",
+            peer = %socket,
+            transport = ?Transport::from(transport),
+            user = field::Empty,
+        );
+        span.in_scope(|| tracing::debug!("client incoming"));
+
+        let connect = Server::connect(
+            incoming_client,
+            transport,
+            self.login_timeout,
+            self.decode_limits,
+        );
+        self.spawn_handshake(self.admission.admit(socket.ip()), connect, span);
+    }
+
+    /// admit the incoming client whose connection is established already, and spawn its handshake
+    ///
+    /// a client with an ip is admitted by the allow and deny lists and the limits,
+    /// otherwise it is a local one
+    fn incoming_connection(&self, connection: Connection, transport: Transport) {
+        let peer = connection.peer();
+        let span = tracing::info_span!(
+            "connection",
+            peer = peer.map_or_else(|| "local".to_string(), |peer| peer.to_string()),
+            ?transport,
+            user = field::Empty,
+        );
+        span.in_scope(|| tracing::debug!("client incoming"));
 
-        let input_handler = {
-            let supervisor_sender = Arc::clone(&self.supervisor_sender);
-            let server_sender = Arc::clone(&self.sender);
-            tokio::spawn(async {
-                Self::listen_input(supervisor_sender, server_sender).await;
-            })
+        let admitted = match peer {
+            Some(peer) => self.admission.admit(peer.ip()),
+            None => self.admission.admit_local(),
         };
+        self.spawn_handshake(admitted, async move { Some(connection) }, span);
+    }
+
+    /// once the connection is established, spawn the handshake if admitted,
+    /// otherwise tell the client why it is refused
+    fn spawn_handshake<F>(&self, admitted: Result<Permit, Refusal>, connect: F, span: Span)
+    where
+        F: Future<Output = Option<Connection>> + Send + 'static,
+    {
+        match admitted {
+            Ok(permit) => {
+                let (shards, login_timeout, limits, hooks, counters) = (
+                    self.shards.clone(),
+                    self.login_timeout,
+                    self.decode_limits,
+                    self.hooks.clone(),
+                    self.counters.clone(),
+                );
+                let handshake = async move {
+                    if let Some(incoming_client) = connect.await {
+                        Server::handshake(
+                            incoming_client,
+                            permit,
+                            shards,
+                            login_timeout,
+                            limits,
+                            hooks,
+                            counters,
+                        )
+                        .await;
+                    }
+                };
+                tokio::spawn(handshake.instrument(span));
+            }
+            Err(refusal) => {
+                self.counters.refused_connection();
+                span.in_scope(|| tracing::info!(reason = refusal.reason(), "connection refused"));
+
+                let refuse = async move {
+                    if let Some(incoming_client) = connect.await {
+                        Server::refuse(incoming_client, refusal).await;
+                    }
+                };
+                tokio::spawn(refuse.instrument(span));
+            }
+        }
+    }
+}
 
-        self.listen_incoming_client().await;
+#[async_trait]
+impl Dctor for Server {
+    type InboxItem = ServerMessage;
 
-        input_handler.await.unwrap();
+    async fn started(&mut self, ctx: &mut Context<Self>) {
+        tracing::info!(address = ?self.tcp_listener.local_addr().ok(), "server listening");
+        if let Some(listener) = &self.websocket_listener {
+            tracing::info!(address = ?listener.local_addr().ok(), "websocket listening");
+        }
+        if let Some(listener) = &self.json_listener {
+            tracing::info!(address = ?listener.local_addr().ok(), "json lines listening");
+        }
+        if let Some(path) = &self.unix_socket {
+            tracing::info!(path = %path.display(), "unix socket listening");
+        }
+
+        if self.console {
+            let console = console::listen(ctx.addr(), self.shards.clone(), self.ban_file.clone());
+            tokio::spawn(console);
+        }
+
+        if let Some(listener) = self.control_listener.take() {
+            tokio::spawn(control::serve(listener, ctx.addr(), self.shards.clone()));
+        }
     }
 
-    /// listen clients, and forward to supervisor
-    async fn listen_incoming_client(&mut self) {
+    /// listen clients over every transport, and forward to supervisor
+    ///
+    /// the login of every incoming client is handled in its own task,
+    /// so a client that never logs in does not block the others
+    async fn listen(&mut self, ctx: &mut Context<Self>) {
         loop {
             tokio::select! {
-                tcp_message = self.tcp_listener.accept() => {
-                    let (mut incoming_client, socket) = tcp_message.unwrap();
-
-                    println!("Client incoming: {socket}");
-
-                    let first = Server::check_login(&mut incoming_client).await;
-                    if first.is_err() {
-                        Message::send(
-                            &mut incoming_client,
-                            Message::new(
-                                MessageType::Text("need login".to_string()),
-                                "<Server>".to_string(),
-                                String::new(),
-                            ),
-                        )
-                        .await
-                        .unwrap();
-                        continue;
-                    };
-                    let username = first.unwrap();
-                    println!("Client login success: {username}");
-
-                    println!("Send message to supervisor");
-                    self.supervisor_sender
-                        .send(SupervisorMessage::NewClient(username, incoming_client))
-                        .await
-                        .unwrap();
+                incoming = self.tcp_listener.accept() => {
+                    self.incoming(incoming, TcpTransport::Tcp);
                 }
-                is_quit = (self.inbox.recv()) => {
-                    if let Some(true) = is_quit {
-                        println!("Server received: QUIT, Server quit. Bye!");
+                incoming = Server::accept(self.websocket_listener.as_ref()) => {
+                    self.incoming(incoming, TcpTransport::WebSocket);
+                }
+                incoming = Server::accept(self.json_listener.as_ref()) => {
+                    self.incoming(incoming, TcpTransport::Json);
+                }
+                incoming = Server::accept_local(self.unix_listener.as_ref()) => match incoming {
+                    Ok(stream) => {
+                        self.incoming_connection(Connection::new(stream, None), Transport::Unix);
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %e, transport = ?Transport::Unix, "accept client failure");
+                    }
+                },
+                message = ctx.recv() => match message {
+                    Some(ServerMessage::Connect(connection)) => {
+                        self.incoming_connection(connection, Transport::Other);
+                    }
+                    Some(ServerMessage::Quit) | None => {
+                        tracing::info!("server quit");
                         break;
                     }
-                }
+                },
             };
         }
     }
 
-    /// if user type 'quit' in terminal, quit the application
-    async fn listen_input(supervisor_sender: SupervisorSender, server_sender: Arc<Sender<bool>>) {
-        let mut lines = BufReader::new(stdin()).lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            if line.trim() == "quit" {
-                server_sender.send(true).await.unwrap();
-                supervisor_sender
-                    .send(SupervisorMessage::Terminate)
-                    .await
-                    .unwrap();
-                return;
-            }
+    /// terminate the supervisors and the link to other nodes, and remove the Unix-domain sockets
+    async fn stopping(&mut self, _ctx: &mut Context<Self>) {
+        let stats = self.shards.stats().await;
+        tracing::info!(?stats, "routing stats");
+        self.shards.terminate().await;
+        if let Some(cluster) = &self.cluster {
+            let _ = cluster.send(ClusterMessage::Terminate).await;
+        }
+        for path in [&self.control_socket, &self.unix_socket]
+            .into_iter()
+            .flatten()
+        {
+            let _ = std::fs::remove_file(path);
         }
     }
+}
 
-    async fn check_login(tcp_stream: &mut TcpStream) -> Result<String, ()> {
-        let message = Message::read_from(tcp_stream).await.unwrap();
-        if message.is_none() {
-            return Err(());
-        }
-        let message = message.unwrap();
-        if message.message_type != MessageType::Login {
-            return Err(());
-        }
+#[cfg(test)]
+mod tests {
+    use tokio::io::DuplexStream;
+
+    use super::*;
+
+    async fn start_server() -> dctor::Addr<Server> {
+        let config = Config {
+            host: "127.0.0.1:0".to_string(),
+            login_timeout: Duration::from_secs(1),
+            shards: 2,
+            ..Config::default()
+        };
+        let server = Server::new(&config, Hooks::default()).await.unwrap();
+        let ctx = Context::new(10);
+        let addr = ctx.addr();
+        dctor::spawn(server, ctx);
+        addr
+    }
+
+    /// hand a client over an in-memory pipe to the server
+    async fn connect(server: &dctor::Addr<Server>) -> MessageReader<DuplexStream> {
+        let (client, pipe) = tokio::io::duplex(4096);
+        let peer = "127.0.0.1:50000".parse().ok();
+        server
+            .send(ServerMessage::Connect(Connection::new(pipe, peer)))
+            .await
+            .unwrap();
+        MessageReader::new(client)
+    }
+
+    async fn login(server: &dctor::Addr<Server>, username: &str) -> MessageReader<DuplexStream> {
+        let mut client = connect(server).await;
+        let login = Message::new(MessageType::Login, username.to_string(), String::new());
+        Message::send(client.get_mut(), login).await.unwrap();
+        let accepted = client.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Login, accepted.message_type);
+        assert_eq!(SERVER_NAME, accepted.username);
+        client
+    }
+
+    #[tokio::test]
+    async fn users_talk_over_duplex() {
+        let server = start_server().await;
+        let mut alice = login(&server, "alice").await;
+        let mut bob = login(&server, "bob").await;
+
+        // both are logged in once told so
+        let text = MessageType::Text("hello bob".to_string());
+        let message = Message::new(text.clone(), "alice".to_string(), "bob".to_string());
+        Message::send(alice.get_mut(), message).await.unwrap();
+        let message = bob.read().await.unwrap().unwrap();
+        assert_eq!("alice", message.username);
+        assert_eq!(text, message.message_type);
+
+        server.send(ServerMessage::Quit).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_must_login_first() {
+        let server = start_server().await;
+        let mut client = connect(&server).await;
+
+        let text = MessageType::Text("hello".to_string());
+        let message = Message::new(text, "alice".to_string(), "bob".to_string());
+        Message::send(client.get_mut(), message).await.unwrap();
+
+        let notice = client.read().await.unwrap().unwrap();
+        assert_eq!(SERVER_NAME, notice.username);
+        assert_eq!(
+            MessageType::Error("need login".to_string()),
+            notice.message_type
+        );
+        assert!(client.read().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn silent_client_times_out() {
+        let server = start_server().await;
+        let mut client = connect(&server).await;
 
-        Ok(message.username)
+        let notice = client.read().await.unwrap().unwrap();
+        assert_eq!(
+            MessageType::Error("login timeout".to_string()),
+            notice.message_type
+        );
     }
 }
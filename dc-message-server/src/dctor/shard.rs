@@ -0,0 +1,283 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use super::dctor::Outbox;
+use super::supervisor::{
+    Ban, ClientSupervisor, OnlineUser, RoutingStats, SupervisorMessage, SupervisorSender,
+};
+
+/// how many points every shard owns in the ring,
+/// more points spread usernames more evenly
+const VIRTUAL_NODES_PER_SHARD: usize = 64;
+/// how many messages could be kept for a single shard whose inbox is full,
+/// messages beyond it would be dropped
+const MAX_OUTBOX_MESSAGES: usize = 10_000;
+
+/// consistent hashing of usernames to shards
+#[derive(Debug)]
+struct ShardRing {
+    /// tuple: (point in the ring, shard index)
+    points: BTreeMap<u64, usize>,
+}
+
+impl ShardRing {
+    fn new(shard_count: usize) -> Self {
+        let mut points = BTreeMap::new();
+        for shard in 0..shard_count {
+            for node in 0..VIRTUAL_NODES_PER_SHARD {
+                points.insert(hash(&(shard, node)), shard);
+            }
+        }
+
+        ShardRing { points }
+    }
+
+    /// the first point at or after the hash of username owns it
+    fn shard_of(&self, username: &str) -> usize {
+        let point = hash(&username);
+        self.points
+            .range(point..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, shard)| *shard)
+            .unwrap_or(0)
+    }
+}
+
+fn hash(value: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// handle of all supervisor shards,
+/// dispatch the message to the shard owning the username
+///
+/// # example
+/// ```ignore
+/// let shards = ClientSupervisor::start_shards(4, OverflowPolicy::Spill, None, ClientConfig::default());
+/// shards.sender_of("dvorak").send(SupervisorMessage::DisconnectClient("dvorak".to_string()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Shards {
+    ring: Arc<ShardRing>,
+    senders: Arc<Vec<SupervisorSender>>,
+}
+
+impl Shards {
+    pub(crate) fn new(senders: Vec<SupervisorSender>) -> Self {
+        Shards {
+            ring: Arc::new(ShardRing::new(senders.len())),
+            senders: Arc::new(senders),
+        }
+    }
+
+    /// index of the shard owning the username
+    pub fn shard_of(&self, username: &str) -> usize {
+        self.ring.shard_of(username)
+    }
+
+    /// sender of the shard owning the username
+    pub fn sender_of(&self, username: &str) -> &SupervisorSender {
+        &self.senders[self.shard_of(username)]
+    }
+
+    /// the counters of routing summed over every shard
+    pub async fn stats(&self) -> RoutingStats {
+        let mut total = RoutingStats::default();
+        for sender in self.senders.iter() {
+            if let Some(stats) = sender.request(SupervisorMessage::Stats).await {
+                total.routed += stats.routed;
+                total.dropped += stats.dropped;
+                total.spilled += stats.spilled;
+                total.expired += stats.expired;
+                total.disconnected += stats.disconnected;
+                total.exited += stats.exited;
+                total.panicked += stats.panicked;
+                total.undeliverable += stats.undeliverable;
+            }
+        }
+        total
+    }
+
+    /// the count of queued messages in the inbox of every connected client,
+    /// tuple: (username, count)
+    pub async fn inboxes(&self) -> Vec<(String, usize)> {
+        let mut inboxes = vec![];
+        for sender in self.senders.iter() {
+            if let Some(shard_inboxes) = sender.request(SupervisorMessage::Inboxes).await {
+                inboxes.extend(shard_inboxes);
+            }
+        }
+        inboxes
+    }
+
+    /// the users connected to every shard
+    pub async fn list(&self) -> Vec<OnlineUser> {
+        let mut users = vec![];
+        for sender in self.senders.iter() {
+            if let Some(shard_users) = sender.request(SupervisorMessage::List).await {
+                users.extend(shard_users);
+            }
+        }
+        users
+    }
+
+    /// disconnect the user after telling the reason,
+    /// return whether the user is connected
+    pub async fn kick(&self, username: &str, reason: &str) -> bool {
+        let (username, reason) = (username.to_string(), reason.to_string());
+        self.sender_of(&username)
+            .request(|reply| SupervisorMessage::Kick {
+                username,
+                reason,
+                reply,
+            })
+            .await
+            .unwrap_or(false)
+    }
+
+    /// ban the user or ip on every shard, return the usernames disconnected
+    pub async fn ban(&self, ban: &Ban) -> Vec<String> {
+        let mut kicked = vec![];
+        for sender in self.senders.iter() {
+            let ban = ban.clone();
+            if let Some(usernames) = sender
+                .request(|reply| SupervisorMessage::Ban { ban, reply })
+                .await
+            {
+                kicked.extend(usernames);
+            }
+        }
+        kicked
+    }
+
+    /// replace the bans of every shard, return the usernames disconnected
+    pub async fn reload(&self, bans: &[Ban]) -> Vec<String> {
+        let mut kicked = vec![];
+        for sender in self.senders.iter() {
+            let bans = bans.to_vec();
+            if let Some(usernames) = sender
+                .request(|reply| SupervisorMessage::Reload { bans, reply })
+                .await
+            {
+                kicked.extend(usernames);
+            }
+        }
+        kicked
+    }
+
+    /// send the text from server to every client, return the count of clients
+    pub async fn say(&self, text: &str) -> usize {
+        let mut count = 0;
+        for sender in self.senders.iter() {
+            let text = text.to_string();
+            if let Some(shard_count) = sender
+                .request(|reply| SupervisorMessage::Say { text, reply })
+                .await
+            {
+                count += shard_count;
+            }
+        }
+        count
+    }
+
+    /// an outbox to every shard, for sending without waiting
+    pub(crate) fn outboxes(&self) -> ShardOutboxes {
+        let outboxes = self.senders.iter().cloned();
+        ShardOutboxes(
+            outboxes
+                .map(|sender| Outbox::new(sender, MAX_OUTBOX_MESSAGES))
+                .collect(),
+        )
+    }
+
+    /// send Terminate to every shard
+    pub async fn terminate(&self) {
+        for sender in self.senders.iter() {
+            let _ = sender.send(SupervisorMessage::Terminate).await;
+        }
+    }
+}
+
+/// the outboxes to every shard, an actor never waits for a shard,
+/// two shards waiting each other would deadlock
+///
+/// the messages to a shard are kept in order once its inbox is full,
+/// until [`ShardOutboxes::flush`] sends them
+pub(crate) struct ShardOutboxes(Vec<Outbox<ClientSupervisor>>);
+
+impl ShardOutboxes {
+    /// # Return
+    /// is the message sent or kept? false if it is dropped
+    pub fn send(&mut self, shard: usize, message: SupervisorMessage) -> bool {
+        self.0[shard].send(message)
+    }
+
+    /// tell the subscribers of every shard that the user comes or goes
+    ///
+    /// # Return
+    /// count of the shards missing it
+    pub fn announce(&mut self, username: &str, online: bool) -> usize {
+        let mut dropped = 0;
+        for outbox in self.0.iter_mut() {
+            let presence = SupervisorMessage::Presence {
+                username: username.to_string(),
+                online,
+            };
+            if !outbox.send(presence) {
+                dropped += 1;
+            }
+        }
+        dropped
+    }
+
+    /// send the kept messages of every shard in order
+    ///
+    /// # Return
+    /// count of the messages dropped because the shard stopped
+    pub fn flush(&mut self) -> usize {
+        self.0.iter_mut().map(Outbox::flush).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShardRing;
+
+    #[test]
+    fn single_shard_owns_everyone() {
+        let ring = ShardRing::new(1);
+
+        assert_eq!(0, ring.shard_of("dvorak"));
+        assert_eq!(0, ring.shard_of("anduin"));
+    }
+
+    #[test]
+    fn usernames_spread_over_shards() {
+        let ring = ShardRing::new(4);
+        let mut counts = [0; 4];
+        for i in 0..4000 {
+            counts[ring.shard_of(&format!("user{i}"))] += 1;
+        }
+
+        for count in counts {
+            assert!(count > 500, "unbalanced shards: {counts:?}");
+        }
+    }
+
+    #[test]
+    fn adding_shard_moves_few_usernames() {
+        let before = ShardRing::new(4);
+        let after = ShardRing::new(5);
+        let moved = (0..4000)
+            .map(|i| format!("user{i}"))
+            .filter(|username| before.shard_of(username) != after.shard_of(username))
+            .count();
+
+        assert!(moved < 2000, "too many usernames moved: {moved}");
+    }
+}
@@ -1,23 +1,72 @@
 use async_trait::async_trait;
-use tokio::{
-    net::TcpStream,
-    sync::mpsc::{self, Receiver, Sender},
-};
+use clap::ValueEnum;
+use serde::Serialize;
+use tokio::sync::{mpsc::error::TrySendError, oneshot, watch};
 
+use super::admission::Permit;
 use super::client::Client;
+use super::cluster::{Cluster, ClusterMessage, ClusterSender};
+use super::connection::Connection;
+use super::plugin;
+use super::shard::{ShardOutboxes, Shards};
+
+use super::client::{ClientConfig, ClientMessage, CLIENT_INBOX_CAPACITY};
+use super::dctor::{self, Addr, Context, Dctor, Exit, Outbox, SupervisionStrategy};
+use super::server::SERVER_NAME;
+use crate::logging::{Logged, Redact};
+use dvorak_message::message::{Message as Frame, MessageType};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::{Debug, Display, Formatter},
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
 
-use super::client::ClientMessage;
-use super::dctor::Dctor;
-use std::{collections::HashMap, sync::Arc};
+/// how many messages could be spilled for a single user,
+/// messages beyond it would be dropped
+const MAX_SPILLED_MESSAGES: usize = 1000;
+/// how many bytes of messages could be spilled for a single user,
+/// messages beyond it would be dropped
+const MAX_SPILLED_BYTES: usize = 1 << 20;
+/// how many bytes of messages could be spilled by a supervisor for all of users,
+/// messages beyond it would be dropped
+const MAX_SPILLED_TOTAL_BYTES: usize = 64 << 20;
+/// how many of the newest messages wait for a client dropping the oldest ones
+const MAX_WAITING_MESSAGES: usize = CLIENT_INBOX_CAPACITY;
+/// how long a spilled message waits for its receiver, it expires after that
+const SPILL_TTL: Duration = Duration::from_secs(10 * 60);
+/// how long a user is remembered after logging out,
+/// messages are spilled only for the users online or seen in that time
+const SEEN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// how many offline receivers could have spilled messages,
+/// messages to other offline receivers would be dropped
+const MAX_OFFLINE_RECEIVERS: usize = 100;
+/// how many messages could be kept for the cluster whose inbox is full,
+/// messages beyond it would be dropped
+const MAX_CLUSTER_OUTBOX_MESSAGES: usize = 10_000;
+/// how often the supervisor retries delivering spilled messages,
+/// and sending the messages kept for other shards, the cluster and stopping clients
+const SPILL_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+/// the reason told to a user who logins on another node
+const LOGIN_ELSEWHERE: &str = "logged in on another node";
+/// told to the client replaced by a newer login of its user
+const LOGIN_AGAIN: &str = "logged in again elsewhere";
+/// how many times a panicked supervisor shard is restarted
+const MAX_SHARD_RESTARTS: usize = 10;
+/// how many times a disconnected plugin is restarted by its supervisor
+const MAX_PLUGIN_RESTARTS: usize = 10;
+/// a plugin online this long is healthy, its restarts are counted from zero again
+const PLUGIN_HEALTHY_PERIOD: Duration = Duration::from_secs(60);
 
-pub type SupervisorSender = Arc<Sender<SupervisorMessage>>;
+pub(crate) type SupervisorSender = Addr<ClientSupervisor>;
 
 /// Actor Message for ClientSupervisor
-#[derive(Debug)]
 pub enum SupervisorMessage {
     /// representing a new client established
-    /// tuple parameters: (client username, TcpStream)
-    NewClient(String, TcpStream),
+    /// tuple parameters: (client username, connection, permit of the connection)
+    ///
+    /// the connection is not counted if there is no permit
+    NewClient(String, Connection, Option<Permit>),
     /// client send message to another client
     Message {
         /// username who send this message
@@ -27,34 +76,900 @@ pub enum SupervisorMessage {
         /// message for sending
         message: String,
     },
+    /// message forwarded from another shard, where the sender lives
+    Forward {
+        sender: String,
+        receiver: String,
+        message: String,
+    },
+    /// message from another node of the cluster, where the sender lives
+    Remote {
+        sender: String,
+        receiver: String,
+        message: String,
+    },
     /// representing client disconnecting to server
     DisconnectClient(String),
+    /// the user logins on another node of the cluster, disconnect the local client,
+    /// the subscribers are not told since the user is still online
+    Moved(String),
+    /// the client subscribes the presence of other users,
+    /// it is told who is online now, and who comes or goes since then
+    Subscribe(String),
+    /// a user comes or goes, on any shard or node, tell the subscribers
+    Presence { username: String, online: bool },
+    /// the actor of client stopped, by itself or by panic
+    ClientExited {
+        username: String,
+        client: Addr<Client>,
+        exit: Exit,
+    },
+    /// reply the counters of routing
+    Stats(oneshot::Sender<RoutingStats>),
+    /// reply the count of queued messages in the inbox of every client,
+    /// tuple: (username, count)
+    Inboxes(oneshot::Sender<Vec<(String, usize)>>),
+    /// reply the users connected to this supervisor
+    List(oneshot::Sender<Vec<OnlineUser>>),
+    /// tell the user the reason, and disconnect it,
+    /// reply whether the user is connected to this supervisor
+    Kick {
+        username: String,
+        reason: String,
+        reply: oneshot::Sender<bool>,
+    },
+    /// add a ban, and disconnect the banned clients, reply their usernames
+    Ban {
+        ban: Ban,
+        reply: oneshot::Sender<Vec<String>>,
+    },
+    /// replace all of bans, and disconnect the banned clients, reply their usernames
+    Reload {
+        bans: Vec<Ban>,
+        reply: oneshot::Sender<Vec<String>>,
+    },
+    /// send the text from server to every client, reply the count of clients
+    Say {
+        text: String,
+        reply: oneshot::Sender<usize>,
+    },
     /// close all of clients, and this Supervisor
     Terminate,
 }
 
-/// this actor manager all of clients
+/// the bodies of messages are always redacted
+impl Debug for SupervisorMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.fmt_redacted(f, false)
+    }
+}
+
+impl Redact for SupervisorMessage {
+    fn fmt_redacted(&self, f: &mut Formatter<'_>, show_bodies: bool) -> std::fmt::Result {
+        use SupervisorMessage::*;
+
+        let text = |f: &mut Formatter<'_>, name, sender, receiver, message: &String| {
+            f.debug_struct(name)
+                .field("sender", sender)
+                .field("receiver", receiver)
+                .field("message", &Logged(message.as_str(), show_bodies))
+                .finish()
+        };
+        match self {
+            NewClient(username, connection, _) => f
+                .debug_tuple("NewClient")
+                .field(username)
+                .field(&connection.peer())
+                .finish(),
+            Message {
+                sender,
+                receiver,
+                message,
+            } => text(f, "Message", sender, receiver, message),
+            Forward {
+                sender,
+                receiver,
+                message,
+            } => text(f, "Forward", sender, receiver, message),
+            Remote {
+                sender,
+                receiver,
+                message,
+            } => text(f, "Remote", sender, receiver, message),
+            DisconnectClient(username) => {
+                f.debug_tuple("DisconnectClient").field(username).finish()
+            }
+            Moved(username) => f.debug_tuple("Moved").field(username).finish(),
+            Subscribe(username) => f.debug_tuple("Subscribe").field(username).finish(),
+            Presence { username, online } => f
+                .debug_struct("Presence")
+                .field("username", username)
+                .field("online", online)
+                .finish(),
+            ClientExited {
+                username,
+                client,
+                exit,
+            } => f
+                .debug_struct("ClientExited")
+                .field("username", username)
+                .field("client", client)
+                .field("exit", exit)
+                .finish(),
+            Stats(_) => f.write_str("Stats"),
+            Inboxes(_) => f.write_str("Inboxes"),
+            List(_) => f.write_str("List"),
+            Kick {
+                username, reason, ..
+            } => f
+                .debug_struct("Kick")
+                .field("username", username)
+                .field("reason", reason)
+                .finish(),
+            Ban { ban, .. } => f.debug_struct("Ban").field("ban", ban).finish(),
+            Reload { bans, .. } => f.debug_struct("Reload").field("bans", bans).finish(),
+            Say { text, .. } => f
+                .debug_struct("Say")
+                .field("text", &Logged(text.as_str(), show_bodies))
+                .finish(),
+            Terminate => f.write_str("Terminate"),
+        }
+    }
+}
+
+/// what to do when the inbox of a receiver is full,
+/// the supervisor never waits for a slow client
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OverflowPolicy {
+    /// keep the newest messages waiting for the receiver,
+    /// and drop the oldest one waiting if there are too many
+    DropOldest,
+    /// disconnect the slow client
+    Disconnect,
+    /// keep the message in the queue of the receiver,
+    /// and deliver it once the receiver catches up or logins,
+    /// also for the receiver offline on a standalone server if it is seen lately
+    #[default]
+    Spill,
+}
+
+/// counters of the routing in supervisor
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct RoutingStats {
+    /// messages delivered into the inbox of receiver
+    pub routed: u64,
+    /// messages dropped because the receiver is too slow
+    pub dropped: u64,
+    /// messages kept in the spilled queue
+    pub spilled: u64,
+    /// spilled messages dropped because the receiver did not take them in time
+    pub expired: u64,
+    /// clients disconnected because they are too slow
+    pub disconnected: u64,
+    /// clients gone without logout, including the panicked ones
+    pub exited: u64,
+    /// clients whose actor panicked
+    pub panicked: u64,
+    /// messages whose receiver is not connected to any node
+    pub undeliverable: u64,
+}
+
+/// a user or an ip refused to connect
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Ban {
+    User(String),
+    Ip(IpAddr),
+}
+
+impl Ban {
+    /// an ip if `target` could be parsed as, otherwise a username
+    pub fn parse(target: &str) -> Self {
+        match target.parse() {
+            Ok(ip) => Ban::Ip(ip),
+            Err(_) => Ban::User(target.to_string()),
+        }
+    }
+
+    fn matches(&self, username: &str, peer: Option<SocketAddr>) -> bool {
+        match self {
+            Ban::User(user) => user == username,
+            Ban::Ip(ip) => peer.map(|peer| peer.ip()) == Some(*ip),
+        }
+    }
+}
+
+impl Display for Ban {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ban::User(username) => f.write_str(username),
+            Ban::Ip(ip) => write!(f, "{ip}"),
+        }
+    }
+}
+
+/// a user connected to the server
+#[derive(Debug, Clone)]
+pub struct OnlineUser {
+    pub username: String,
+    /// address of the connection, [`None`] if unknown
+    pub peer: Option<SocketAddr>,
+    /// how long the user has been connected
+    pub online: Duration,
+}
+
+/// the actor of a connected client, and where it comes from
+struct ConnectedClient {
+    addr: Addr<Client>,
+    peer: Option<SocketAddr>,
+    connected_at: Instant,
+}
+
+/// this actor manager all of clients,
+/// or the clients of a single shard if there are several supervisors
 pub struct ClientSupervisor {
-    clients: HashMap<String, Sender<ClientMessage>>,
-    inbox: Receiver<<Self as Dctor>::InboxItem>,
-    /// should keep a supervisor sender, for distribute to all clients
-    sender: SupervisorSender,
+    /// index of this supervisor in shards
+    shard: usize,
+    shards: Shards,
+    /// messages to other shards, kept in order while their inboxes are full
+    outboxes: ShardOutboxes,
+    clients: HashMap<String, ConnectedClient>,
+    /// the local clients subscribing the presence of other users
+    subscribers: HashSet<String>,
+    /// users and ips refused to connect
+    bans: HashSet<Ban>,
+    /// messages waiting for the receivers
+    spilled: SpillQueues,
+    /// when the users of this shard were online last, [`Instant`] of login for the online ones
+    seen: HashMap<String, Instant>,
+    /// the policy of the clients not in [`ClientConfig::overflow_policies`]
+    overflow_policy: OverflowPolicy,
+    stats: RoutingStats,
+    /// link to other nodes, [`None`] if the server runs alone
+    cluster: Option<Outbox<Cluster>>,
+    /// clients to be terminated, whose inboxes were full
+    terminating: Vec<Addr<Client>>,
+    /// how many times every plugin of this supervisor is restarted lately,
+    /// tuple: (count of restarts, when it is started last)
+    plugin_restarts: HashMap<String, (usize, Instant)>,
+    /// limits of the clients
+    client_config: ClientConfig,
+    /// dropped with this supervisor, the clients watching it disconnect,
+    /// so the clients of a panicked supervisor are not left behind its restart
+    alive: watch::Sender<()>,
 }
 
 impl ClientSupervisor {
-    pub fn new() -> (Self, SupervisorSender) {
-        let (tx, rx) = mpsc::channel(100);
-        let supervisor_sender = Arc::new(tx);
+    pub fn new(
+        shard: usize,
+        shards: Shards,
+        overflow_policy: OverflowPolicy,
+        cluster: Option<ClusterSender>,
+        client_config: ClientConfig,
+    ) -> Self {
+        ClientSupervisor {
+            shard,
+            outboxes: shards.outboxes(),
+            shards,
+            clients: HashMap::new(),
+            subscribers: HashSet::new(),
+            bans: HashSet::new(),
+            spilled: SpillQueues::default(),
+            seen: HashMap::new(),
+            overflow_policy,
+            stats: RoutingStats::default(),
+            cluster: cluster.map(|cluster| Outbox::new(cluster, MAX_CLUSTER_OUTBOX_MESSAGES)),
+            terminating: vec![],
+            plugin_restarts: HashMap::new(),
+            client_config,
+            alive: watch::channel(()).0,
+        }
+    }
+
+    /// start supervisors of `shard_count` shards,
+    /// every username is owned by one of them.
+    ///
+    /// a panicked supervisor is restarted, the clients it managed are disconnected
+    pub fn start_shards(
+        shard_count: usize,
+        overflow_policy: OverflowPolicy,
+        cluster: Option<ClusterSender>,
+        client_config: ClientConfig,
+    ) -> Shards {
+        let contexts: Vec<Context<Self>> =
+            (0..shard_count.max(1)).map(|_| Context::new(100)).collect();
+        let shards = Shards::new(contexts.iter().map(Context::addr).collect());
+
+        for (shard, ctx) in contexts.into_iter().enumerate() {
+            let _span = tracing::info_span!("supervisor", shard).entered();
+            let factory = {
+                let (shards, cluster, config) =
+                    (shards.clone(), cluster.clone(), client_config.clone());
+                move || {
+                    let (shards, cluster) = (shards.clone(), cluster.clone());
+                    Self::new(shard, shards, overflow_policy, cluster, config.clone())
+                }
+            };
+            let strategy = SupervisionStrategy::Restart {
+                max_restarts: MAX_SHARD_RESTARTS,
+            };
+            dctor::spawn_supervised(factory, ctx, strategy);
+        }
+
+        shards
+    }
+
+    /// route the message locally, or forward it to the shard owning the receiver
+    fn dispatch(&mut self, sender: String, receiver: String, message: String) {
+        let shard = self.shards.shard_of(&receiver);
+        if shard == self.shard {
+            self.deliver(sender, receiver, message);
+            return;
+        }
+
+        let forward = SupervisorMessage::Forward {
+            sender,
+            receiver,
+            message,
+        };
+        if !self.outboxes.send(shard, forward) {
+            self.stats.dropped += 1;
+        }
+    }
+
+    /// route the message to the local receiver,
+    /// or to the node where the receiver lives
+    fn deliver(&mut self, sender: String, receiver: String, message: String) {
+        let local = self.clients.contains_key(&receiver);
+        let Some(cluster) = self.cluster.as_mut().filter(|_| !local) else {
+            self.route(sender, receiver, message);
+            return;
+        };
+
+        let forward = ClusterMessage::Forward {
+            sender,
+            receiver,
+            message,
+        };
+        if !cluster.send(forward) {
+            self.stats.dropped += 1;
+        }
+    }
+
+    fn notify_cluster(&mut self, message: ClusterMessage) {
+        let Some(cluster) = &mut self.cluster else {
+            return;
+        };
+        if !cluster.send(message) {
+            tracing::warn!("cluster outbox is full, notice dropped");
+        }
+    }
+
+    fn announce(&mut self, username: &str, online: bool) {
+        if self.outboxes.announce(username, online) > 0 {
+            tracing::warn!(user = %username, online, "shard outbox is full, presence dropped");
+        }
+    }
+
+    fn remove_client(&mut self, username: &str) -> Option<Addr<Client>> {
+        let client = self.clients.remove(username)?;
+        self.seen.insert(username.to_string(), Instant::now());
+        self.subscribers.remove(username);
+        self.notify_cluster(ClusterMessage::UserOffline(username.to_string()));
+        self.announce(username, false);
+        Some(client.addr)
+    }
+
+    /// log the client out for the reason, so it does not come back, and disconnect it
+    ///
+    /// # Return
+    /// is the user connected?
+    fn kick(&mut self, username: &str, reason: &str) -> bool {
+        let Some(client) = self.remove_client(username) else {
+            return false;
+        };
+        tracing::info!(user = %username, reason, "kick client");
+        // the reason is skipped if the inbox is full, the client is disconnected anyway
+        let logged_out = ClientMessage::LoggedOut(reason.to_string());
+        if client.try_send(logged_out).is_err() {
+            self.terminate_client(client);
+        }
+        true
+    }
+
+    /// disconnect the clients matching any of bans
+    ///
+    /// # Return
+    /// usernames of the clients disconnected
+    fn kick_banned(&mut self) -> Vec<String> {
+        let banned: Vec<String> = self
+            .clients
+            .iter()
+            .filter(|(username, client)| self.is_banned(username, client.peer))
+            .map(|(username, _)| username.clone())
+            .collect();
+        for username in banned.iter() {
+            self.kick(username, "banned");
+        }
+        banned
+    }
+
+    /// tell the client who is online now on every shard, and who comes or goes since then
+    fn subscribe(&mut self, username: String) {
+        let Some(client) = self.clients.get(&username) else {
+            return;
+        };
+        let (client, shards) = (client.addr.clone(), self.shards.clone());
+        self.subscribers.insert(username.clone());
+        tokio::spawn(async move {
+            for user in shards.list().await {
+                if user.username == username {
+                    continue;
+                }
+                let presence = ClientMessage::Presence(user.username, true);
+                if client.send(presence).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    fn is_banned(&self, username: &str, peer: Option<SocketAddr>) -> bool {
+        self.bans.iter().any(|ban| ban.matches(username, peer))
+    }
+
+    /// hand the connection to a new actor of client,
+    /// the client logged in before by the same username is logged out
+    fn connect(
+        &mut self,
+        username: String,
+        connection: Connection,
+        permit: Option<Permit>,
+        ctx: &Context<Self>,
+    ) {
+        let peer = connection.peer();
+        if self.is_banned(&username, peer) {
+            tracing::info!(user = %username, ?peer, "refuse banned client");
+            Self::refuse(connection, permit);
+            return;
+        }
+
+        let client = ConnectedClient {
+            addr: self.spawn_client(&username, connection, permit, ctx),
+            peer,
+            connected_at: Instant::now(),
+        };
+        self.seen.insert(username.clone(), Instant::now());
+        match self.clients.insert(username.clone(), client) {
+            Some(replaced) => {
+                self.subscribers.remove(&username);
+                let logged_out = ClientMessage::LoggedOut(LOGIN_AGAIN.to_string());
+                if replaced.addr.try_send(logged_out).is_err() {
+                    self.terminate_client(replaced.addr);
+                }
+            }
+            None => self.announce(&username, true),
+        }
+        self.notify_cluster(ClusterMessage::UserOnline(username.clone()));
+        self.flush_spilled(&username);
+    }
+
+    /// start the plugins whose names are owned by this supervisor
+    fn start_plugins(&mut self, ctx: &Context<Self>) {
+        let plugins: Vec<String> = self
+            .client_config
+            .plugins
+            .iter()
+            .map(|plugin| plugin.name().to_string())
+            .filter(|name| self.shards.shard_of(name) == self.shard)
+            .collect();
+        for name in plugins {
+            self.start_plugin(name, ctx);
+        }
+    }
+
+    fn start_plugin(&mut self, name: String, ctx: &Context<Self>) {
+        let Some(plugin) = self.client_config.plugins.get(&name).cloned() else {
+            return;
+        };
+        let connection = {
+            let _span = tracing::info_span!("plugin", name = %name).entered();
+            plugin::start(plugin)
+        };
+        let restarts = self
+            .plugin_restarts
+            .entry(name.clone())
+            .or_insert((0, Instant::now()));
+        restarts.1 = Instant::now();
+        self.connect(name, connection, None, ctx);
+    }
+
+    /// start the disconnected plugin again, unless it is restarted too many times
+    fn restart_plugin(&mut self, name: &str, ctx: &Context<Self>) {
+        if self.client_config.plugins.get(name).is_none() || self.clients.contains_key(name) {
+            return;
+        }
+        let (restarts, started_at) = self
+            .plugin_restarts
+            .entry(name.to_string())
+            .or_insert((0, Instant::now()));
+        // a plugin online long enough is not failing again and again
+        if started_at.elapsed() >= PLUGIN_HEALTHY_PERIOD {
+            *restarts = 0;
+        }
+        if *restarts >= MAX_PLUGIN_RESTARTS {
+            tracing::error!(plugin = %name, "plugin restarted too many times, give up");
+            return;
+        }
+        *restarts += 1;
+        tracing::warn!(plugin = %name, restarts = *restarts, "restart plugin");
+        self.start_plugin(name.to_string(), ctx);
+    }
+
+    /// tell the banned incoming client, and drop the connection
+    fn refuse(mut connection: Connection, permit: Option<Permit>) {
+        tokio::spawn(async move {
+            let _permit = permit;
+            let notice = Frame::new(
+                MessageType::Error("banned".to_string()),
+                SERVER_NAME.to_string(),
+                String::new(),
+            );
+            let _ = Frame::send(&mut connection, notice).await;
+        });
+    }
+
+    /// spawn the actor of client, and watch it,
+    /// the supervisor receives [`SupervisorMessage::ClientExited`] once it stops or panics,
+    /// the permit of connection is kept until then
+    fn spawn_client(
+        &self,
+        username: &str,
+        connection: Connection,
+        permit: Option<Permit>,
+        ctx: &Context<Self>,
+    ) -> Addr<Client> {
+        let client_ctx = Context::new(CLIENT_INBOX_CAPACITY);
+        let client = client_ctx.addr();
+        let span = tracing::info_span!(
+            "client",
+            user = %username,
+            peer = ?connection.peer(),
+        );
+
+        // a client is never restarted, its connection is gone with it
+        let mut connection = Some(connection);
+        let (supervisor_sender, config) = (ctx.addr(), self.client_config.clone());
+        let alive = self.alive.subscribe();
+        let name = username.to_string();
+        let factory = move || {
+            let connection = connection.take().expect("client is never restarted");
+            Client::new(
+                name.clone(),
+                connection,
+                supervisor_sender.clone(),
+                alive.clone(),
+                config.clone(),
+            )
+        };
+        let handler = span
+            .in_scope(|| dctor::spawn_supervised(factory, client_ctx, SupervisionStrategy::Stop));
+
+        let (username, watched, supervisor_sender) =
+            (username.to_string(), client.clone(), ctx.addr());
+        tokio::spawn(async move {
+            let exit = handler.await.unwrap_or(Exit::Panicked);
+            drop(permit);
+            let _ = supervisor_sender
+                .send(SupervisorMessage::ClientExited {
+                    username,
+                    client: watched,
+                    exit,
+                })
+                .await;
+        });
+
+        client
+    }
+
+    /// the inbox of client may be full, never wait for it,
+    /// the client is told again by [`Self::flush_terminating`]
+    fn terminate_client(&mut self, client: Addr<Client>) {
+        if let Err(TrySendError::Full(_)) = client.try_send(ClientMessage::Terminate) {
+            self.terminating.push(client);
+        }
+    }
+
+    fn flush_terminating(&mut self) {
+        self.terminating.retain(|client| {
+            matches!(
+                client.try_send(ClientMessage::Terminate),
+                Err(TrySendError::Full(_))
+            )
+        });
+    }
+
+    /// send the messages kept for other shards and the cluster,
+    /// and tell the stopping clients again
+    fn flush_outboxes(&mut self) {
+        let mut dropped = self.outboxes.flush();
+        if let Some(cluster) = &mut self.cluster {
+            dropped += cluster.flush();
+        }
+        self.stats.dropped += dropped as u64;
+        self.flush_terminating();
+    }
+
+    /// deliver the message to receiver without waiting,
+    /// apply the [`OverflowPolicy`] of receiver if its inbox is full
+    fn route(&mut self, sender: String, receiver: String, message: String) {
+        let policy = self.overflow_policy_of(&receiver);
+        if !self.clients.contains_key(&receiver) {
+            if policy == OverflowPolicy::Spill && self.is_seen(&receiver) {
+                self.spill(receiver, sender, message);
+            } else {
+                self.stats.undeliverable += 1;
+            }
+            return;
+        }
+
+        // keep the order, messages spilled before should be delivered first
+        if !self.flush_spilled(&receiver) {
+            self.overflow(policy, receiver, sender, message);
+            return;
+        }
+
+        let client = &self.clients[&receiver].addr;
+        match client.try_send(ClientMessage::ReceiveMessage(sender, message)) {
+            Ok(()) => self.stats.routed += 1,
+            Err(TrySendError::Closed(_)) => {
+                self.stats.dropped += 1;
+                self.remove_client(&receiver);
+            }
+            Err(TrySendError::Full(ClientMessage::ReceiveMessage(sender, message))) => {
+                self.overflow(policy, receiver, sender, message)
+            }
+            Err(TrySendError::Full(_)) => unreachable!(),
+        }
+    }
+
+    /// the policy configured for the user, or the one of the server
+    fn overflow_policy_of(&self, username: &str) -> OverflowPolicy {
+        self.client_config
+            .overflow_policies
+            .get(username)
+            .copied()
+            .unwrap_or(self.overflow_policy)
+    }
+
+    /// is the user online, or seen lately?
+    fn is_seen(&self, username: &str) -> bool {
+        self.seen
+            .get(username)
+            .is_some_and(|seen| self.clients.contains_key(username) || seen.elapsed() < SEEN_TTL)
+    }
+
+    /// apply the policy to the message the receiver could not take now
+    fn overflow(
+        &mut self,
+        policy: OverflowPolicy,
+        receiver: String,
+        sender: String,
+        message: String,
+    ) {
+        match policy {
+            OverflowPolicy::DropOldest => self.keep_newest(receiver, sender, message),
+            OverflowPolicy::Disconnect => {
+                self.stats.dropped += 1;
+                self.stats.disconnected += 1;
+                tracing::warn!(%receiver, "inbox is full, disconnect it");
+                self.stats.dropped += self.spilled.remove(&receiver) as u64;
+                if let Some(client) = self.remove_client(&receiver) {
+                    self.terminate_client(client);
+                }
+            }
+            OverflowPolicy::Spill => self.spill(receiver, sender, message),
+        }
+    }
+
+    /// queue the message at the end, the message is dropped if the queues are full
+    fn spill(&mut self, receiver: String, sender: String, message: String) {
+        let offline = !self.clients.contains_key(&receiver) && !self.spilled.contains(&receiver);
+        if offline && self.offline_spilled() >= MAX_OFFLINE_RECEIVERS {
+            self.stats.dropped += 1;
+            return;
+        }
+        let spilled = Spilled::new(sender, message);
+        let (count, bytes) = self.spilled.size_of(&receiver);
+        if count >= MAX_SPILLED_MESSAGES
+            || bytes + spilled.size() > MAX_SPILLED_BYTES
+            || self.spilled.bytes + spilled.size() > MAX_SPILLED_TOTAL_BYTES
+        {
+            self.stats.dropped += 1;
+            return;
+        }
+        self.spilled.push_back(receiver, spilled);
+        self.stats.spilled += 1;
+    }
+
+    /// queue the message at the end, and drop the oldest ones beyond the limits
+    fn keep_newest(&mut self, receiver: String, sender: String, message: String) {
+        let spilled = Spilled::new(sender, message);
+        if spilled.size() > MAX_SPILLED_BYTES {
+            self.stats.dropped += 1;
+            return;
+        }
+        self.spilled.push_back(receiver.clone(), spilled);
+        self.stats.spilled += 1;
+        loop {
+            let (count, bytes) = self.spilled.size_of(&receiver);
+            let full = count > MAX_WAITING_MESSAGES
+                || bytes > MAX_SPILLED_BYTES
+                || self.spilled.bytes > MAX_SPILLED_TOTAL_BYTES;
+            if !full || self.spilled.pop_front(&receiver).is_none() {
+                return;
+            }
+            self.stats.dropped += 1;
+        }
+    }
+
+    /// deliver the spilled messages of receiver as many as possible,
+    /// the expired ones are dropped
+    ///
+    /// # Return
+    /// is all of spilled messages delivered?
+    fn flush_spilled(&mut self, receiver: &str) -> bool {
+        let Some(ConnectedClient { addr: client, .. }) = self.clients.get(receiver) else {
+            return true;
+        };
+
+        while let Some(Spilled {
+            sender,
+            message,
+            spilled_at,
+        }) = self.spilled.pop_front(receiver)
+        {
+            if spilled_at.elapsed() >= SPILL_TTL {
+                self.stats.expired += 1;
+                continue;
+            }
+            match client.try_send(ClientMessage::ReceiveMessage(sender, message)) {
+                Ok(()) => self.stats.routed += 1,
+                Err(TrySendError::Full(ClientMessage::ReceiveMessage(sender, message))) => {
+                    let spilled = Spilled {
+                        sender,
+                        message,
+                        spilled_at,
+                    };
+                    self.spilled.push_front(receiver, spilled);
+                    return false;
+                }
+                Err(_) => {
+                    self.stats.dropped += 1;
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// count of offline receivers having spilled messages
+    fn offline_spilled(&self) -> usize {
+        let offline = |receiver: &&String| !self.clients.contains_key(*receiver);
+        self.spilled.queues.keys().filter(offline).count()
+    }
+
+    /// drop the spilled messages waiting too long, and forget the users gone long ago
+    fn expire_spilled(&mut self) {
+        self.stats.expired += self.spilled.expire() as u64;
+        let clients = &self.clients;
+        self.seen
+            .retain(|username, seen| clients.contains_key(username) || seen.elapsed() < SEEN_TTL);
+    }
+
+    fn flush_all_spilled(&mut self) {
+        let receivers: Vec<String> = self.spilled.queues.keys().cloned().collect();
+        for receiver in receivers {
+            self.flush_spilled(&receiver);
+        }
+    }
+}
+
+/// a message waiting for its receiver
+struct Spilled {
+    sender: String,
+    message: String,
+    spilled_at: Instant,
+}
 
-        println!("Supervisor construct");
-        (
-            ClientSupervisor {
-                clients: HashMap::new(),
-                inbox: rx,
-                sender: Arc::clone(&supervisor_sender),
-            },
-            supervisor_sender,
-        )
+impl Spilled {
+    fn new(sender: String, message: String) -> Self {
+        Spilled {
+            sender,
+            message,
+            spilled_at: Instant::now(),
+        }
+    }
+
+    /// bytes counted to the limits
+    fn size(&self) -> usize {
+        self.sender.len() + self.message.len()
+    }
+}
+
+/// the messages waiting for every receiver, oldest first, and their bytes
+#[derive(Default)]
+struct SpillQueues {
+    /// receiver -> tuple: (messages, bytes of them)
+    queues: HashMap<String, (VecDeque<Spilled>, usize)>,
+    /// bytes of all of queues
+    bytes: usize,
+}
+
+impl SpillQueues {
+    fn contains(&self, receiver: &str) -> bool {
+        self.queues.contains_key(receiver)
+    }
+
+    /// tuple returned: (count of messages, bytes of them) waiting for receiver
+    fn size_of(&self, receiver: &str) -> (usize, usize) {
+        self.queues
+            .get(receiver)
+            .map_or((0, 0), |(queue, bytes)| (queue.len(), *bytes))
+    }
+
+    fn push_back(&mut self, receiver: String, spilled: Spilled) {
+        let (queue, bytes) = self.queues.entry(receiver).or_default();
+        *bytes += spilled.size();
+        self.bytes += spilled.size();
+        queue.push_back(spilled);
+    }
+
+    fn push_front(&mut self, receiver: &str, spilled: Spilled) {
+        let (queue, bytes) = self.queues.entry(receiver.to_string()).or_default();
+        *bytes += spilled.size();
+        self.bytes += spilled.size();
+        queue.push_front(spilled);
+    }
+
+    /// the oldest message waiting for receiver, the queue is removed once empty
+    fn pop_front(&mut self, receiver: &str) -> Option<Spilled> {
+        let (queue, bytes) = self.queues.get_mut(receiver)?;
+        let spilled = queue.pop_front()?;
+        *bytes -= spilled.size();
+        self.bytes -= spilled.size();
+        if queue.is_empty() {
+            self.queues.remove(receiver);
+        }
+        Some(spilled)
+    }
+
+    /// drop every message waiting for receiver, return the count of them
+    fn remove(&mut self, receiver: &str) -> usize {
+        let Some((queue, bytes)) = self.queues.remove(receiver) else {
+            return 0;
+        };
+        self.bytes -= bytes;
+        queue.len()
+    }
+
+    /// drop the messages waiting longer than [`SPILL_TTL`], return the count of them
+    fn expire(&mut self) -> usize {
+        let mut expired = 0;
+        let total = &mut self.bytes;
+        self.queues.retain(|_, (queue, bytes)| {
+            while queue
+                .front()
+                .is_some_and(|spilled| spilled.spilled_at.elapsed() >= SPILL_TTL)
+            {
+                let spilled = queue.pop_front().expect("front is checked");
+                *bytes -= spilled.size();
+                *total -= spilled.size();
+                expired += 1;
+            }
+            !queue.is_empty()
+        });
+        expired
     }
 }
 
@@ -62,54 +977,741 @@ impl ClientSupervisor {
 impl Dctor for ClientSupervisor {
     type InboxItem = SupervisorMessage;
 
-    async fn listen(&mut self) {
+    async fn started(&mut self, ctx: &mut Context<Self>) {
+        tracing::debug!("supervisor started");
+        self.start_plugins(ctx);
+    }
+
+    async fn listen(&mut self, ctx: &mut Context<Self>) {
         use SupervisorMessage::*;
 
-        println!("Supervisor listening...");
-        'listen: while let Some(msg) = self.inbox.recv().await {
-            println!("Supervisor received message: {:?}", msg);
+        let mut flush_interval = tokio::time::interval(SPILL_FLUSH_INTERVAL);
+        'listen: loop {
+            let msg = tokio::select! {
+                msg = ctx.recv() => match msg {
+                    Some(msg) => msg,
+                    None => break 'listen,
+                },
+                _ = flush_interval.tick() => {
+                    self.flush_outboxes();
+                    self.expire_spilled();
+                    self.flush_all_spilled();
+                    continue 'listen;
+                }
+            };
+            tracing::trace!(
+                message = ?Logged(&msg, self.client_config.log_bodies),
+                "supervisor received"
+            );
             match msg {
-                NewClient(username, tcp_stream) => {
-                    let (mut client, client_sender) =
-                        Client::new(tcp_stream, Arc::clone(&self.sender));
-
-                    tokio::spawn(async move {
-                        client.listen().await;
-                    });
-
-                    self.clients.insert(username, client_sender);
+                NewClient(username, connection, permit) => {
+                    self.connect(username, connection, permit, ctx)
                 }
                 Message {
                     sender,
                     receiver,
                     message,
                 } => {
-                    if !self.clients.contains_key(&sender) || !self.clients.contains_key(&receiver)
-                    {
+                    if !self.clients.contains_key(&sender) {
                         continue;
                     }
 
-                    let receiver = self.clients.get_mut(&receiver).unwrap();
-                    receiver
-                        .send(ClientMessage::ReceiveMessage(sender, message))
-                        .await
-                        .unwrap();
+                    self.dispatch(sender, receiver, message);
                 }
+                Forward {
+                    sender,
+                    receiver,
+                    message,
+                } => self.deliver(sender, receiver, message),
+                Remote {
+                    sender,
+                    receiver,
+                    message,
+                } => self.route(sender, receiver, message),
                 DisconnectClient(username) => {
-                    if let Some(client) = self.clients.remove(&username) {
-                        client.send(ClientMessage::Terminate).await.unwrap();
+                    if let Some(client) = self.remove_client(&username) {
+                        self.terminate_client(client);
+                    }
+                }
+                Moved(username) => {
+                    let Some(client) = self.clients.remove(&username) else {
+                        continue;
+                    };
+                    // the cluster forgot the user already
+                    self.subscribers.remove(&username);
+                    let logged_out = ClientMessage::LoggedOut(LOGIN_ELSEWHERE.to_string());
+                    if client.addr.try_send(logged_out).is_err() {
+                        self.terminate_client(client.addr);
                     }
                 }
-                Terminate => {
-                    for (username, stored_client) in self.clients.iter_mut() {
-                        println!("{username} terminating...");
-                        stored_client.send(ClientMessage::Terminate).await.unwrap();
+                Subscribe(username) => self.subscribe(username),
+                Presence { username, online } => {
+                    let subscribers = self.subscribers.iter().filter(|s| **s != username);
+                    for client in subscribers.filter_map(|s| self.clients.get(s)) {
+                        let presence = ClientMessage::Presence(username.clone(), online);
+                        // presence is told at most once, a slow subscriber misses it
+                        let _ = client.addr.try_send(presence);
                     }
-                    self.clients.clear();
-                    println!("Supervisor terminated.");
-                    break 'listen;
                 }
+                ClientExited {
+                    username,
+                    client,
+                    exit,
+                } => {
+                    if exit == Exit::Panicked {
+                        self.stats.panicked += 1;
+                        tracing::error!(user = %username, "client panicked");
+                    }
+                    // the username may login again with another client already
+                    if matches!(self.clients.get(&username), Some(stored) if stored.addr.same_actor(&client))
+                    {
+                        self.stats.exited += 1;
+                        self.remove_client(&username);
+                    }
+                    self.restart_plugin(&username, ctx);
+                }
+                Stats(reply) => {
+                    let _ = reply.send(self.stats.clone());
+                }
+                Inboxes(reply) => {
+                    let inboxes = self
+                        .clients
+                        .iter()
+                        .map(|(username, client)| (username.clone(), client.addr.queued()))
+                        .collect();
+                    let _ = reply.send(inboxes);
+                }
+                List(reply) => {
+                    let users = self
+                        .clients
+                        .iter()
+                        .map(|(username, client)| OnlineUser {
+                            username: username.clone(),
+                            peer: client.peer,
+                            online: client.connected_at.elapsed(),
+                        })
+                        .collect();
+                    let _ = reply.send(users);
+                }
+                Kick {
+                    username,
+                    reason,
+                    reply,
+                } => {
+                    let _ = reply.send(self.kick(&username, &reason));
+                }
+                Ban { ban, reply } => {
+                    self.bans.insert(ban);
+                    let _ = reply.send(self.kick_banned());
+                }
+                Reload { bans, reply } => {
+                    self.bans = bans.into_iter().collect();
+                    let _ = reply.send(self.kick_banned());
+                }
+                Say { text, reply } => {
+                    let receivers: Vec<String> = self.clients.keys().cloned().collect();
+                    let count = receivers.len();
+                    for receiver in receivers {
+                        self.route(SERVER_NAME.to_string(), receiver, text.clone());
+                    }
+                    let _ = reply.send(count);
+                }
+                Terminate => break 'listen,
             }
         }
     }
+
+    /// close all of clients
+    async fn stopping(&mut self, _ctx: &mut Context<Self>) {
+        let clients: Vec<(String, ConnectedClient)> = self.clients.drain().collect();
+        for (username, stored_client) in clients {
+            tracing::debug!(user = %username, "terminate client");
+            self.terminate_client(stored_client.addr);
+        }
+        // nobody tells them again once the supervisor stopped
+        let terminating = std::mem::take(&mut self.terminating);
+        if !terminating.is_empty() {
+            tokio::spawn(async move {
+                for client in terminating {
+                    let _ = client.send(ClientMessage::Terminate).await;
+                }
+            });
+        }
+        tracing::debug!("supervisor stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use dvorak_message::message::{Message, MessageReader, MessageType};
+    use tokio::{io::AsyncReadExt, net::TcpStream};
+
+    use super::*;
+    use crate::dctor::testing::new_client;
+
+    /// read the frame telling the login is accepted
+    async fn accepted(reader: &mut MessageReader<TcpStream>) {
+        let message = reader.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Login, message.message_type);
+        assert_eq!(SERVER_NAME, message.username);
+    }
+
+    /// flood a client which never reads, and make sure another client still receives
+    async fn stalled_client_does_not_block_routing(policy: OverflowPolicy) -> RoutingStats {
+        let shards = ClientSupervisor::start_shards(1, policy, None, ClientConfig::default());
+        // a single shard owns everyone
+        let sender = shards.sender_of("");
+
+        let _stalled_peer = new_client(&shards, "stalled").await;
+        let mut fast_peer = MessageReader::new(new_client(&shards, "fast").await);
+        let _flooder_peer = new_client(&shards, "flooder").await;
+
+        let payload = "x".repeat(64 * 1024);
+        let flood = async {
+            for _ in 0..1000 {
+                sender
+                    .send(SupervisorMessage::Message {
+                        sender: "flooder".to_string(),
+                        receiver: "stalled".to_string(),
+                        message: payload.clone(),
+                    })
+                    .await
+                    .unwrap();
+            }
+            sender
+                .send(SupervisorMessage::Message {
+                    sender: "flooder".to_string(),
+                    receiver: "fast".to_string(),
+                    message: "hello".to_string(),
+                })
+                .await
+                .unwrap();
+            accepted(&mut fast_peer).await;
+            fast_peer.read().await.unwrap().unwrap()
+        };
+        let message = tokio::time::timeout(Duration::from_secs(10), flood)
+            .await
+            .expect("routing is blocked by the stalled client");
+
+        assert_eq!(MessageType::Text("hello".to_string()), message.message_type);
+
+        let stats = sender.request(SupervisorMessage::Stats).await.unwrap();
+        shards.terminate().await;
+        stats
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_policy_isolates_stalled_client() {
+        let stats = stalled_client_does_not_block_routing(OverflowPolicy::DropOldest).await;
+
+        assert!(stats.dropped > 0);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_policy_of_user_keeps_the_newest_messages() {
+        let config = ClientConfig {
+            overflow_policies: Arc::new(HashMap::from([(
+                "slow".to_string(),
+                OverflowPolicy::DropOldest,
+            )])),
+            ..ClientConfig::default()
+        };
+        let shards = ClientSupervisor::start_shards(1, OverflowPolicy::Disconnect, None, config);
+        let sender = shards.sender_of("");
+        let mut reader = MessageReader::new(new_client(&shards, "slow").await);
+        let _flooder_peer = new_client(&shards, "flooder").await;
+        accepted(&mut reader).await;
+
+        let padding = "x".repeat(16 * 1024);
+        for i in 0..1000 {
+            sender
+                .send(SupervisorMessage::Message {
+                    sender: "flooder".to_string(),
+                    receiver: "slow".to_string(),
+                    message: format!("{i} {padding}"),
+                })
+                .await
+                .unwrap();
+        }
+
+        let (mut received, mut last) = (0, String::new());
+        while let Ok(message) = tokio::time::timeout(Duration::from_secs(1), reader.read()).await {
+            let MessageType::Text(text) = message.unwrap().unwrap().message_type else {
+                panic!("unexpected message");
+            };
+            received += 1;
+            last = text;
+        }
+        assert!(received < 1000);
+        assert!(last.starts_with("999 "));
+        let stats = sender.request(SupervisorMessage::Stats).await.unwrap();
+        assert!(stats.dropped > 0);
+        assert_eq!(0, stats.disconnected);
+        shards.terminate().await;
+    }
+
+    #[tokio::test]
+    async fn messages_to_user_never_seen_are_not_spilled() {
+        let shards =
+            ClientSupervisor::start_shards(1, OverflowPolicy::Spill, None, ClientConfig::default());
+        let _alice_peer = new_client(&shards, "alice").await;
+        let sender = shards.sender_of("alice");
+        sender
+            .send(SupervisorMessage::Message {
+                sender: "alice".to_string(),
+                receiver: "nobody".to_string(),
+                message: "hello".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let stats = sender.request(SupervisorMessage::Stats).await.unwrap();
+        assert_eq!(0, stats.spilled);
+        assert_eq!(1, stats.undeliverable);
+        shards.terminate().await;
+    }
+
+    #[test]
+    fn spilled_messages_expire() {
+        let old = || Spilled {
+            spilled_at: Instant::now() - SPILL_TTL,
+            ..Spilled::new("alice".to_string(), "old".to_string())
+        };
+        let mut queues = SpillQueues::default();
+        queues.push_back("bob".to_string(), old());
+        queues.push_back(
+            "bob".to_string(),
+            Spilled::new("alice".to_string(), "new".to_string()),
+        );
+        queues.push_back("carol".to_string(), old());
+
+        assert_eq!(2, queues.expire());
+        assert!(!queues.contains("carol"));
+        assert_eq!((1, "alicenew".len()), queues.size_of("bob"));
+        assert_eq!("alicenew".len(), queues.bytes);
+    }
+
+    #[tokio::test]
+    async fn disconnect_policy_isolates_stalled_client() {
+        let stats = stalled_client_does_not_block_routing(OverflowPolicy::Disconnect).await;
+
+        assert_eq!(1, stats.disconnected);
+    }
+
+    #[tokio::test]
+    async fn spill_policy_isolates_stalled_client() {
+        let stats = stalled_client_does_not_block_routing(OverflowPolicy::Spill).await;
+
+        assert!(stats.spilled > 0);
+    }
+
+    #[tokio::test]
+    async fn closed_client_is_removed() {
+        let shards =
+            ClientSupervisor::start_shards(1, OverflowPolicy::Spill, None, ClientConfig::default());
+        // a single shard owns everyone
+        let sender = shards.sender_of("");
+        let _alice_peer = new_client(&shards, "alice").await;
+        let bob_peer = new_client(&shards, "bob").await;
+
+        drop(bob_peer);
+
+        let mut stats = RoutingStats::default();
+        for _ in 0..50 {
+            stats = sender.request(SupervisorMessage::Stats).await.unwrap();
+            if stats.exited > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(1, stats.exited);
+        assert_eq!(0, stats.panicked);
+
+        // bob logins again
+        let mut bob_peer = MessageReader::new(new_client(&shards, "bob").await);
+        sender
+            .send(SupervisorMessage::Message {
+                sender: "alice".to_string(),
+                receiver: "bob".to_string(),
+                message: "hello".to_string(),
+            })
+            .await
+            .unwrap();
+        accepted(&mut bob_peer).await;
+        let message = tokio::time::timeout(Duration::from_secs(5), bob_peer.read())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(MessageType::Text("hello".to_string()), message.message_type);
+        assert_eq!("alice", message.username);
+        assert_eq!("bob", message.receiver);
+        shards.terminate().await;
+    }
+
+    #[tokio::test]
+    async fn clients_of_lost_supervisor_are_disconnected() {
+        let ctx = Context::new(100);
+        let supervisor = ctx.addr();
+        let shards = Shards::new(vec![supervisor.clone()]);
+        let config = ClientConfig::default();
+        let handle = dctor::spawn(
+            ClientSupervisor::new(0, shards.clone(), OverflowPolicy::Spill, None, config),
+            ctx,
+        );
+        let alice_peer = new_client(&shards, "alice").await;
+        assert_eq!(
+            1,
+            supervisor
+                .request(SupervisorMessage::List)
+                .await
+                .unwrap()
+                .len()
+        );
+
+        // dropped without stopping, as a panicked one
+        handle.abort();
+
+        let mut reader = MessageReader::new(alice_peer);
+        accepted(&mut reader).await;
+        let read = tokio::time::timeout(Duration::from_secs(5), reader.read()).await;
+        assert!(read.unwrap().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn offline_receiver_gets_spilled_messages_on_login() {
+        let shards =
+            ClientSupervisor::start_shards(2, OverflowPolicy::Spill, None, ClientConfig::default());
+        // bob is seen before, messages are kept for him once he is gone
+        let bob_peer = new_client(&shards, "bob").await;
+        accepted(&mut MessageReader::new(bob_peer)).await;
+        for _ in 0..50 {
+            if shards.list().await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let _alice_peer = new_client(&shards, "alice").await;
+        for text in ["first", "second"] {
+            shards
+                .sender_of("alice")
+                .send(SupervisorMessage::Message {
+                    sender: "alice".to_string(),
+                    receiver: "bob".to_string(),
+                    message: text.to_string(),
+                })
+                .await
+                .unwrap();
+        }
+
+        let mut stats = RoutingStats::default();
+        for _ in 0..50 {
+            stats = shards.stats().await;
+            if stats.spilled == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(2, stats.spilled);
+        assert_eq!(0, stats.undeliverable);
+
+        let bob_peer = new_client(&shards, "bob").await;
+        let mut reader = MessageReader::new(bob_peer);
+        accepted(&mut reader).await;
+        for text in ["first", "second"] {
+            let message = tokio::time::timeout(Duration::from_secs(5), reader.read())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert_eq!(MessageType::Text(text.to_string()), message.message_type);
+            assert_eq!("alice", message.username);
+        }
+        shards.terminate().await;
+    }
+
+    #[tokio::test]
+    async fn messages_to_another_shard_keep_order() {
+        let shards =
+            ClientSupervisor::start_shards(2, OverflowPolicy::Spill, None, ClientConfig::default());
+        let receiver = (0..)
+            .map(|i| format!("user{i}"))
+            .find(|username| shards.shard_of(username) != shards.shard_of("alice"))
+            .unwrap();
+        let _alice_peer = new_client(&shards, "alice").await;
+        let bob_peer = new_client(&shards, &receiver).await;
+
+        // more than the inbox of a shard holds
+        for i in 0..1000 {
+            shards
+                .sender_of("alice")
+                .send(SupervisorMessage::Message {
+                    sender: "alice".to_string(),
+                    receiver: receiver.clone(),
+                    message: i.to_string(),
+                })
+                .await
+                .unwrap();
+        }
+
+        let mut reader = MessageReader::new(bob_peer);
+        accepted(&mut reader).await;
+        for i in 0..1000 {
+            let message = tokio::time::timeout(Duration::from_secs(5), reader.read())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert_eq!(MessageType::Text(i.to_string()), message.message_type);
+        }
+        assert_eq!(0, shards.stats().await.dropped);
+        shards.terminate().await;
+    }
+
+    #[tokio::test]
+    async fn panicked_client_is_removed_and_logins_again() {
+        let mut config = ClientConfig::default();
+        let boom = |_: &str, _: &str, message: String| {
+            assert_ne!("boom", message, "client panics");
+            Some(message)
+        };
+        config.handlers.push(std::sync::Arc::new(boom));
+        let shards = ClientSupervisor::start_shards(2, OverflowPolicy::Spill, None, config);
+        let login = |username: &'static str| {
+            let shards = shards.clone();
+            async move {
+                let peer = new_client(&shards, username).await;
+                let mut reader = MessageReader::new(peer);
+                accepted(&mut reader).await;
+                reader
+            }
+        };
+        let mut alice = login("alice").await;
+        let mut bob = login("bob").await;
+
+        let boom = Message::new(
+            MessageType::Text("boom".to_string()),
+            "alice".to_string(),
+            "bob".to_string(),
+        );
+        Message::send(alice.get_mut(), boom).await.unwrap();
+        assert!(alice.read().await.unwrap().is_none());
+
+        let mut stats = RoutingStats::default();
+        for _ in 0..50 {
+            stats = shards.stats().await;
+            if stats.exited > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(1, stats.panicked);
+        assert_eq!(1, stats.exited);
+        let users = shards.list().await;
+        assert_eq!(1, users.len());
+        assert_eq!("bob", users[0].username);
+
+        // alice logins again, and the server still serves everyone
+        let mut alice = login("alice").await;
+        let hello = Message::new(
+            MessageType::Text("hello".to_string()),
+            "alice".to_string(),
+            "bob".to_string(),
+        );
+        Message::send(alice.get_mut(), hello).await.unwrap();
+        let message = tokio::time::timeout(Duration::from_secs(5), bob.read())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!("alice", message.username);
+        assert_eq!(MessageType::Text("hello".to_string()), message.message_type);
+        assert_eq!(2, shards.list().await.len());
+        shards.terminate().await;
+    }
+
+    /// messages routed per second between users spread over `shard_count` shards
+    async fn routed_per_second(shard_count: usize) -> f64 {
+        const USERS: usize = 64;
+        const MESSAGES: usize = 20_000;
+        // |type|username_len|"user0000"|receiver_len|"user0000"|body_len|"ping"|
+        const FRAME_LEN: usize = 1 + 1 + 8 + 1 + 8 + 4 + 4;
+
+        let shards = ClientSupervisor::start_shards(
+            shard_count,
+            OverflowPolicy::Spill,
+            None,
+            ClientConfig::default(),
+        );
+
+        let usernames: Vec<String> = (0..USERS).map(|i| format!("user{i:04}")).collect();
+        let mut peers = vec![];
+        for username in usernames.iter() {
+            let peer = new_client(&shards, username).await;
+            // the frame telling the login is accepted comes first
+            let accepted = Message::new(
+                MessageType::Login,
+                SERVER_NAME.to_string(),
+                username.clone(),
+            );
+            peers.push((peer, accepted.to_bytes().unwrap().len()));
+        }
+
+        let route = |m: usize| (m % USERS, (m * 7 + 1) % USERS);
+        let mut expected = vec![0; USERS];
+        (0..MESSAGES).for_each(|m| expected[route(m).1] += 1);
+        let readers: Vec<_> = peers
+            .into_iter()
+            .zip(expected)
+            .map(|((mut peer, accepted), count)| {
+                tokio::spawn(async move {
+                    let mut buf = vec![0; accepted + count * FRAME_LEN];
+                    peer.read_exact(&mut buf).await.unwrap();
+                })
+            })
+            .collect();
+
+        let start = Instant::now();
+        for m in 0..MESSAGES {
+            let (sender, receiver) = route(m);
+            shards
+                .sender_of(&usernames[sender])
+                .send(SupervisorMessage::Message {
+                    sender: usernames[sender].clone(),
+                    receiver: usernames[receiver].clone(),
+                    message: "ping".to_string(),
+                })
+                .await
+                .unwrap();
+        }
+        for reader in readers {
+            reader.await.unwrap();
+        }
+        let per_second = MESSAGES as f64 / start.elapsed().as_secs_f64();
+
+        shards.terminate().await;
+        per_second
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture`"]
+    async fn bench_sharded_routing() {
+        for shard_count in [1, 2, 4, 8] {
+            let per_second = routed_per_second(shard_count).await;
+            println!("{shard_count} shard(s): {per_second:.0} messages/s");
+        }
+    }
+
+    #[tokio::test]
+    async fn kicked_client_is_told_and_disconnected() {
+        let shards =
+            ClientSupervisor::start_shards(2, OverflowPolicy::Spill, None, ClientConfig::default());
+        let alice_peer = new_client(&shards, "alice").await;
+
+        assert!(shards.kick("alice", "too noisy").await);
+        assert!(!shards.kick("nobody", "too noisy").await);
+
+        let mut reader = MessageReader::new(alice_peer);
+        accepted(&mut reader).await;
+        let notice = reader.read().await.unwrap().unwrap();
+        assert_eq!(SERVER_NAME, notice.username);
+        assert_eq!(
+            MessageType::Text("too noisy".to_string()),
+            notice.message_type
+        );
+        let logout = reader.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Logout, logout.message_type);
+        assert_eq!(SERVER_NAME, logout.username);
+        assert!(reader.read().await.unwrap().is_none());
+        assert!(shards.list().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn replaced_client_is_logged_out() {
+        let shards =
+            ClientSupervisor::start_shards(2, OverflowPolicy::Spill, None, ClientConfig::default());
+        let mut readers = vec![];
+        for _ in 0..2 {
+            let alice_peer = new_client(&shards, "alice").await;
+            let mut reader = MessageReader::new(alice_peer);
+            accepted(&mut reader).await;
+            readers.push(reader);
+        }
+
+        let mut replaced = readers.remove(0);
+        let notice = replaced.read().await.unwrap().unwrap();
+        assert_eq!(
+            MessageType::Text(LOGIN_AGAIN.to_string()),
+            notice.message_type
+        );
+        let logout = replaced.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Logout, logout.message_type);
+        assert_eq!(SERVER_NAME, logout.username);
+        assert!(replaced.read().await.unwrap().is_none());
+        assert_eq!(1, shards.list().await.len());
+    }
+
+    #[tokio::test]
+    async fn subscriber_is_told_who_comes_and_goes() {
+        let shards =
+            ClientSupervisor::start_shards(2, OverflowPolicy::Spill, None, ClientConfig::default());
+        let mut peers = HashMap::new();
+        for username in ["alice", "bob"] {
+            let peer = new_client(&shards, username).await;
+            let mut reader = MessageReader::new(peer);
+            accepted(&mut reader).await;
+            peers.insert(username, reader);
+        }
+        let mut alice = peers.remove("alice").unwrap();
+        let presence = Message::new(MessageType::Presence, "alice".to_string(), String::new());
+        Message::send(alice.get_mut(), presence).await.unwrap();
+
+        // bob is online already
+        let message = alice.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Login, message.message_type);
+        assert_eq!("bob", message.username);
+
+        assert!(shards.kick("bob", "bye").await);
+        let message = alice.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Logout, message.message_type);
+        assert_eq!("bob", message.username);
+
+        let _carol_peer = new_client(&shards, "carol").await;
+        let message = alice.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Login, message.message_type);
+        assert_eq!("carol", message.username);
+    }
+
+    #[tokio::test]
+    async fn banned_ip_is_refused() {
+        let shards =
+            ClientSupervisor::start_shards(2, OverflowPolicy::Spill, None, ClientConfig::default());
+        let _alice_peer = new_client(&shards, "alice").await;
+
+        let kicked = shards.ban(&Ban::parse("127.0.0.1")).await;
+        assert_eq!(vec!["alice".to_string()], kicked);
+
+        let bob_peer = new_client(&shards, "bob").await;
+        let mut reader = MessageReader::new(bob_peer);
+        let notice = reader.read().await.unwrap().unwrap();
+        assert_eq!(
+            MessageType::Error("banned".to_string()),
+            notice.message_type
+        );
+        assert!(shards.list().await.is_empty());
+
+        // bans are replaced by reload
+        assert!(shards.reload(&[]).await.is_empty());
+        let _bob_peer = new_client(&shards, "bob").await;
+        let users = shards.list().await;
+        assert_eq!(1, users.len());
+        assert_eq!("bob", users[0].username);
+    }
 }
@@ -0,0 +1,35 @@
+//! helpers shared by the tests
+
+use tokio::net::{TcpListener, TcpStream};
+
+use super::{connection::Connection, shard::Shards, supervisor::SupervisorMessage};
+
+/// tuple returned: (stream in server side, stream in peer side)
+pub(crate) async fn connect() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let peer = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (stream, _) = listener.accept().await.unwrap();
+    (stream, peer)
+}
+
+/// hand the connection of a client logged in as the user to its supervisor
+pub(crate) async fn hand_over(shards: &Shards, username: &str, connection: Connection) {
+    shards
+        .sender_of(username)
+        .send(SupervisorMessage::NewClient(
+            username.to_string(),
+            connection,
+            None,
+        ))
+        .await
+        .unwrap();
+}
+
+/// connect a client logged in as the user over TCP, return the stream in peer side
+pub(crate) async fn new_client(shards: &Shards, username: &str) -> TcpStream {
+    let (stream, peer) = connect().await;
+    hand_over(shards, username, stream.into()).await;
+    peer
+}
@@ -0,0 +1,247 @@
+//! the JSON lines encoding, for debugging and scripting
+//!
+//! every [`Message`] is a JSON object on a single line,
+//! like `{"type": "text", "body": "hello", "username": "alice", "receiver": "bob"}`,
+//! so the server could be driven by `nc`:
+//!
+//! ```text
+//! $ nc 127.0.0.1 8234
+//! {"type": "login", "username": "alice"}
+//! {"type": "text", "body": "hello", "username": "alice", "receiver": "bob"}
+//! ```
+//!
+//! the lines are bridged to the frames over a pipe, whose end is a [`Connection`]
+//! like any TCP client, so the users of every encoding are handled by the same supervisors
+
+use bytes::Bytes;
+use dvorak_message::message::{DecodeLimits, Message, MessageReader, MessageType};
+use tokio::{
+    io::{
+        AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf, WriteHalf,
+    },
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::mpsc,
+};
+use tracing::Instrument;
+
+use crate::dctor::{connection::Connection, server::SERVER_NAME};
+
+/// accept an incoming client speaking JSON lines, and bridge it to a [`Connection`]
+pub(crate) fn accept(stream: TcpStream, limits: DecodeLimits) -> Connection {
+    let (connection, pipe) = Connection::pipe(stream.peer_addr().ok());
+    tokio::spawn(bridge(stream, pipe, limits).in_current_span());
+    connection
+}
+
+/// how many error reports could wait for the peer, the reports beyond are dropped
+const MAX_PENDING_REPORTS: usize = 16;
+
+/// pass the lines into the pipe as frames, and the frames out of the pipe as lines,
+/// until either side is closed
+///
+/// each direction runs in its own task, a peer not reading never stops its lines being read.
+/// a line which is not a message is answered with an error, a line too long closes the bridge
+async fn bridge(stream: TcpStream, pipe: DuplexStream, limits: DecodeLimits) {
+    let (read_half, write_half) = stream.into_split();
+    let (pipe_reader, pipe_writer) = tokio::io::split(pipe);
+    let (report_sender, reports) = mpsc::channel(MAX_PENDING_REPORTS);
+
+    let incoming = tokio::spawn(
+        forward_incoming(read_half, pipe_writer, report_sender, limits).in_current_span(),
+    );
+    let outgoing =
+        tokio::spawn(forward_outgoing(pipe_reader, write_half, reports).in_current_span());
+    let _ = outgoing.await;
+    // the server closed its end, nobody takes the lines anymore
+    incoming.abort();
+}
+
+/// pass the lines into the pipe as frames until the peer is gone,
+/// the pipe is shut down after, so the server closes its end
+async fn forward_incoming(
+    read_half: OwnedReadHalf,
+    mut pipe_writer: WriteHalf<DuplexStream>,
+    reports: mpsc::Sender<Message>,
+    limits: DecodeLimits,
+) {
+    let mut lines = BufReader::new(read_half);
+    let mut line = Vec::new();
+    loop {
+        let mut line_reader = (&mut lines).take((limits.max_frame_size + 1 - line.len()) as u64);
+        match line_reader.read_until(b'\n', &mut line).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) if line.last() != Some(&b'\n') => {
+                if line.len() > limits.max_frame_size {
+                    let report = format!("line exceeds limit {}", limits.max_frame_size);
+                    let _ = reports.try_send(report_message(report));
+                }
+                break;
+            }
+            Ok(_) => {}
+        }
+        let frame = serde_json::from_slice::<Message>(&line)
+            .map_err(|e| format!("invalid message: {e}"))
+            .and_then(|message| encode(&message, &limits));
+        line.clear();
+        match frame {
+            Ok(frame) => {
+                if pipe_writer.write_all(&frame).await.is_err() {
+                    break;
+                }
+            }
+            Err(report) => {
+                if reports.try_send(report_message(report)).is_err() {
+                    tracing::debug!("error report dropped");
+                }
+            }
+        }
+    }
+    let _ = pipe_writer.shutdown().await;
+}
+
+/// encode the message to a frame, if its names are within the limits
+fn encode(message: &Message, limits: &DecodeLimits) -> Result<Bytes, String> {
+    let max = limits.max_name_length.min(u8::MAX as usize);
+    for (name, value) in [
+        ("username", &message.username),
+        ("receiver", &message.receiver),
+    ] {
+        if value.len() > max {
+            return Err(format!("{name} length {} exceeds limit {max}", value.len()));
+        }
+    }
+    message.to_bytes().map_err(|e| e.description)
+}
+
+/// pass the frames out of the pipe as lines until the server closes its end,
+/// with the error reports of [`forward_incoming`] in between
+async fn forward_outgoing(
+    pipe_reader: ReadHalf<DuplexStream>,
+    mut write_half: OwnedWriteHalf,
+    mut reports: mpsc::Receiver<Message>,
+) {
+    // frames from the server are always valid, no limits needed
+    let mut frames = MessageReader::new(pipe_reader);
+    loop {
+        let message = tokio::select! {
+            // the report of a line closing the bridge is written before the close
+            biased;
+            Some(report) = reports.recv() => report,
+            outgoing = frames.read() => match outgoing {
+                Ok(Some(message)) => message,
+                _ => break,
+            },
+        };
+        if write_line(&mut write_half, &message).await.is_err() {
+            return;
+        }
+    }
+    while let Ok(report) = reports.try_recv() {
+        if write_line(&mut write_half, &report).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn report_message(report: String) -> Message {
+    Message::new(
+        MessageType::Error(report),
+        SERVER_NAME.to_string(),
+        String::new(),
+    )
+}
+
+async fn write_line(write_half: &mut OwnedWriteHalf, message: &Message) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    write_half.write_all(&line).await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::dctor::{
+        client::ClientConfig,
+        supervisor::{ClientSupervisor, OverflowPolicy},
+        testing::{connect, hand_over, new_client},
+    };
+
+    #[tokio::test]
+    async fn json_lines_and_binary_users_talk_to_each_other() {
+        let shards =
+            ClientSupervisor::start_shards(1, OverflowPolicy::Spill, None, ClientConfig::default());
+
+        // alice types JSON lines
+        let (stream, alice) = connect().await;
+        hand_over(&shards, "alice", accept(stream, DecodeLimits::default())).await;
+        let mut alice = BufReader::new(alice);
+
+        let mut bob = MessageReader::new(new_client(&shards, "bob").await);
+        let accepted = bob.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Login, accepted.message_type);
+        let mut line = String::new();
+        alice.read_line(&mut line).await.unwrap();
+        let accepted: Message = serde_json::from_str(&line).unwrap();
+        assert_eq!(MessageType::Login, accepted.message_type);
+
+        alice
+            .write_all(b"{\"type\": \"text\", \"body\": \"hi\", \"username\": \"alice\", \"receiver\": \"bob\"}\n")
+            .await
+            .unwrap();
+        let message = bob.read().await.unwrap().unwrap();
+        assert_eq!("alice", message.username);
+        assert_eq!(MessageType::Text("hi".to_string()), message.message_type);
+
+        let text = MessageType::Text("hello".to_string());
+        let message = Message::new(text, "bob".to_string(), "alice".to_string());
+        Message::send(bob.get_mut(), message).await.unwrap();
+        line.clear();
+        alice.read_line(&mut line).await.unwrap();
+        let message: Message = serde_json::from_str(&line).unwrap();
+        assert_eq!("bob", message.username);
+        assert_eq!(MessageType::Text("hello".to_string()), message.message_type);
+
+        alice.write_all(b"hello\n").await.unwrap();
+        line.clear();
+        alice.read_line(&mut line).await.unwrap();
+        let message: Message = serde_json::from_str(&line).unwrap();
+        assert!(
+            matches!(message.message_type, MessageType::Error(report) if report.starts_with("invalid message"))
+        );
+    }
+
+    #[tokio::test]
+    async fn long_name_is_rejected() {
+        let (stream, peer) = connect().await;
+        let limits = DecodeLimits {
+            max_name_length: 8,
+            ..DecodeLimits::default()
+        };
+        let connection = accept(stream, limits);
+        let mut peer = BufReader::new(peer);
+
+        let login = format!(
+            "{{\"type\": \"login\", \"username\": \"{}\"}}\n",
+            "x".repeat(300)
+        );
+        peer.write_all(login.as_bytes()).await.unwrap();
+        let mut line = String::new();
+        peer.read_line(&mut line).await.unwrap();
+        let message: Message = serde_json::from_str(&line).unwrap();
+        assert!(
+            matches!(message.message_type, MessageType::Error(report) if report == "username length 300 exceeds limit 8")
+        );
+
+        // nothing truncated reaches the server
+        let (mut reader, _writer) = connection.into_split();
+        drop(peer);
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+}
@@ -0,0 +1,23 @@
+//! the server of Dvorak Message
+//!
+//! it runs as the `dc_message_server` binary, or embedded in another service by [`ServerBuilder`]
+
+mod builder;
+mod console;
+mod control;
+mod dctor;
+mod json;
+pub mod logging;
+mod metrics;
+mod websocket;
+
+pub use builder::{ServerBuilder, ServerHandle, DEFAULT_LISTEN};
+pub use control::{ctl, default_socket as default_control_socket, Ctl};
+pub use dctor::{
+    admission::{AdmissionPolicy, Cidr},
+    connection::Connection,
+    hooks::{Auth, MessageHandler},
+    plugin::{Echo, Plugin, PluginHandle},
+    rate_limit::RateLimits,
+    supervisor::{OnlineUser, OverflowPolicy, RoutingStats},
+};
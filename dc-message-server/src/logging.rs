@@ -0,0 +1,112 @@
+//! logging of the server, built on [`tracing`]
+//!
+//! every connection runs in a `connection` span, which becomes a `client` span once it logins,
+//! and every message read from a client is handled in a `message` span.
+//! the bodies of messages are redacted unless the server is built to log them, by `--log-bodies`
+
+use std::fmt::{Debug, Formatter, Result};
+
+use dvorak_message::message::Message;
+use tracing_subscriber::EnvFilter;
+
+/// install the global subscriber, writing to stdout,
+/// `filter` is a level or directives like `dc_message_server=debug`
+pub fn init(filter: &str, json: bool) {
+    let builder = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(filter));
+    if json {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
+/// written in logs with the bodies of messages redacted, unless `show_bodies`
+pub(crate) trait Redact {
+    fn fmt_redacted(&self, f: &mut Formatter<'_>, show_bodies: bool) -> Result;
+}
+
+/// an item in logs, its bodies are shown only if the server logs them
+pub(crate) struct Logged<'a, T: ?Sized>(pub &'a T, pub bool);
+
+impl<T: Redact + ?Sized> Debug for Logged<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        self.0.fmt_redacted(f, self.1)
+    }
+}
+
+/// the body of a text message, only its length is shown if redacted
+impl Redact for str {
+    fn fmt_redacted(&self, f: &mut Formatter<'_>, show_bodies: bool) -> Result {
+        if show_bodies {
+            Debug::fmt(self, f)
+        } else {
+            write!(f, "<{} bytes>", self.len())
+        }
+    }
+}
+
+impl Redact for Message {
+    fn fmt_redacted(&self, f: &mut Formatter<'_>, show_bodies: bool) -> Result {
+        let mut frame = f.debug_struct("Message");
+        frame
+            .field("kind", &self.message_type.name())
+            .field("username", &self.username)
+            .field("receiver", &self.receiver);
+        if let Some(body) = self.get_body() {
+            frame.field("body", &Logged(body.as_str(), show_bodies));
+        }
+        frame.finish()
+    }
+}
+
+/// the body of a text message in logs, always redacted
+pub(crate) struct Body<'a>(pub &'a str);
+
+impl Debug for Body<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        self.0.fmt_redacted(f, false)
+    }
+}
+
+/// a frame in logs, with its body always redacted
+pub(crate) struct Frame<'a>(pub &'a Message);
+
+impl Debug for Frame<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        self.0.fmt_redacted(f, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dvorak_message::message::MessageType;
+
+    use super::*;
+
+    #[test]
+    fn body_is_redacted_by_default() {
+        let message = Message::new(
+            MessageType::Text("secret".to_string()),
+            "alice".to_string(),
+            "bob".to_string(),
+        );
+
+        let logged = format!("{:?}", Frame(&message));
+
+        assert!(!logged.contains("secret"));
+        assert!(logged.contains("<6 bytes>"));
+        assert!(logged.contains("alice"));
+    }
+
+    #[test]
+    fn body_is_shown_if_logged() {
+        let message = Message::new(
+            MessageType::Text("secret".to_string()),
+            "alice".to_string(),
+            "bob".to_string(),
+        );
+
+        assert!(format!("{:?}", Logged(&message, true)).contains("\"secret\""));
+        assert!(!format!("{:?}", Logged(&message, false)).contains("secret"));
+    }
+}
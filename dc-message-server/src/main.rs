@@ -1,15 +1,22 @@
-use dctor::server;
+use args::Args;
+use dc_message_server::logging;
 
 mod args;
-mod dctor;
 
 #[tokio::main]
 async fn main() {
-    let args = args::Args::parse();
+    let mut args = Args::parse();
+    if let Some(ctl) = args.ctl.take() {
+        std::process::exit(dc_message_server::ctl(ctl).await);
+    }
+    logging::init(&args.log_level, args.log_json);
 
-    // let mut server = Server::new(&args.host).await;
-    let mut server = server::Server::new(&args.host).await;
-
-    println!("Start");
-    server.listen().await;
+    let server = match args.into_builder().build().await {
+        Ok(server) => server,
+        Err(e) => {
+            tracing::error!(error = %e, "start server failure");
+            std::process::exit(1);
+        }
+    };
+    server.wait().await;
 }
@@ -0,0 +1,282 @@
+//! metrics of the server in the text format of Prometheus,
+//! served over HTTP on `--metrics-addr`
+//!
+//! the counters of routing and the inboxes of clients are requested from the supervisors
+//! on every scrape, the other counters are kept in the [`Counters`] of server
+
+use std::{
+    fmt::Write,
+    io::ErrorKind,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use dvorak_message::message::MessageType;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+use crate::dctor::{client::CLIENT_INBOX_CAPACITY, server::DisconnectReason, shard::Shards};
+
+/// how long a scraper may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// a request larger than it is cut, only the request line is used
+const MAX_REQUEST_SIZE: u64 = 8 * 1024;
+
+/// names of [`MessageType`], indexed by its value
+const MESSAGE_TYPES: [&str; 6] = ["heart", "text", "login", "logout", "error", "presence"];
+
+/// counters updated outside of the supervisors, owned by a server
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    /// frames received from clients, indexed by the value of [`MessageType`]
+    received: [AtomicU64; 6],
+    /// incoming clients which did not login, indexed by [`DisconnectReason`]
+    login_failures: [AtomicU64; 3],
+    /// frames which could not be decoded
+    decode_errors: AtomicU64,
+    /// incoming connections refused by the allow and deny lists or the limits
+    refused_connections: AtomicU64,
+}
+
+impl Counters {
+    pub fn received(&self, message_type: &MessageType) {
+        self.received[message_type.value() as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// an incoming client did not login
+    pub fn login_failure(&self, reason: DisconnectReason) {
+        self.login_failures[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn decode_error(&self) {
+        self.decode_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn refused_connection(&self) {
+        self.refused_connections.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// answer the scrapes on `listener` until the server quits
+pub(crate) async fn serve(listener: TcpListener, shards: Shards, counters: Arc<Counters>) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let (shards, counters) = (shards.clone(), counters.clone());
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, &shards, &counters).await {
+                tracing::debug!(error = %e, "metrics scrape failure");
+            }
+        });
+    }
+}
+
+/// answer a single HTTP request, only `GET /metrics` is supported
+async fn respond(stream: TcpStream, shards: &Shards, counters: &Counters) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream.take(MAX_REQUEST_SIZE));
+    let mut request_line = String::new();
+    let read_request = async {
+        stream.read_line(&mut request_line).await?;
+        // the headers are not used
+        let mut header = String::new();
+        while stream.read_line(&mut header).await? > 2 {
+            header.clear();
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    timeout(REQUEST_TIMEOUT, read_request)
+        .await
+        .map_err(|_| std::io::Error::new(ErrorKind::TimedOut, "request timeout"))??;
+
+    let (status, body) = match request_line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", render(shards, counters).await),
+        _ => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream
+        .get_mut()
+        .get_mut()
+        .write_all(response.as_bytes())
+        .await
+}
+
+/// render all of metrics in the text format of Prometheus
+pub(crate) async fn render(shards: &Shards, counters: &Counters) -> String {
+    let stats = shards.stats().await;
+    let inboxes = shards.inboxes().await;
+    let mut text = String::new();
+
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, u64)]| {
+        let _ = writeln!(text, "# HELP {name} {help}");
+        let _ = writeln!(text, "# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(text, "{name}{labels} {value}");
+        }
+    };
+    let single = |value: u64| [(String::new(), value)];
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+    metric(
+        "dc_connected_clients",
+        "gauge",
+        "clients connected to this node",
+        &single(inboxes.len() as u64),
+    );
+    let received: Vec<_> = MESSAGE_TYPES
+        .iter()
+        .zip(&counters.received)
+        .map(|(name, counter)| (format!("{{type=\"{name}\"}}"), load(counter)))
+        .collect();
+    metric(
+        "dc_messages_received_total",
+        "counter",
+        "frames received from clients by message type",
+        &received,
+    );
+    metric(
+        "dc_messages_routed_total",
+        "counter",
+        "messages delivered into the inbox of receiver",
+        &single(stats.routed),
+    );
+    metric(
+        "dc_messages_dropped_total",
+        "counter",
+        "messages dropped because the receiver is too slow",
+        &single(stats.dropped),
+    );
+    metric(
+        "dc_messages_spilled_total",
+        "counter",
+        "messages kept in the spilled queue of a slow receiver",
+        &single(stats.spilled),
+    );
+    metric(
+        "dc_messages_expired_total",
+        "counter",
+        "spilled messages dropped because the receiver did not take them in time",
+        &single(stats.expired),
+    );
+    metric(
+        "dc_messages_undeliverable_total",
+        "counter",
+        "messages whose receiver is not connected",
+        &single(stats.undeliverable),
+    );
+    metric(
+        "dc_clients_disconnected_total",
+        "counter",
+        "clients disconnected because they are too slow",
+        &single(stats.disconnected),
+    );
+    metric(
+        "dc_clients_exited_total",
+        "counter",
+        "clients gone without logout",
+        &single(stats.exited),
+    );
+    metric(
+        "dc_clients_panicked_total",
+        "counter",
+        "clients whose actor panicked",
+        &single(stats.panicked),
+    );
+    metric(
+        "dc_login_failures_total",
+        "counter",
+        "incoming clients which did not login",
+        &DisconnectReason::ALL.map(|reason| {
+            (
+                format!("{{reason=\"{}\"}}", reason.as_str()),
+                load(&counters.login_failures[reason as usize]),
+            )
+        }),
+    );
+    metric(
+        "dc_decode_errors_total",
+        "counter",
+        "frames which could not be decoded",
+        &single(load(&counters.decode_errors)),
+    );
+    metric(
+        "dc_connections_refused_total",
+        "counter",
+        "incoming connections refused by the allow and deny lists or the limits",
+        &single(load(&counters.refused_connections)),
+    );
+    metric(
+        "dc_client_inbox_capacity",
+        "gauge",
+        "how many messages could be queued in the inbox of a client",
+        &single(CLIENT_INBOX_CAPACITY as u64),
+    );
+    // aggregated over the clients, a series for each user would grow without bound
+    let queued = inboxes.iter().map(|(_, queued)| *queued as u64);
+    metric(
+        "dc_client_inbox_messages",
+        "gauge",
+        "messages queued in the inboxes of all clients",
+        &single(queued.clone().sum()),
+    );
+    metric(
+        "dc_client_inbox_messages_max",
+        "gauge",
+        "messages queued in the fullest inbox of a client",
+        &single(queued.max().unwrap_or_default()),
+    );
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::dctor::{
+        client::ClientConfig,
+        supervisor::{ClientSupervisor, OverflowPolicy},
+        testing::new_client,
+    };
+
+    #[tokio::test]
+    async fn scrape_metrics() {
+        let shards =
+            ClientSupervisor::start_shards(2, OverflowPolicy::Spill, None, ClientConfig::default());
+        let _peer = new_client(&shards, "alice").await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let counters = Arc::new(Counters::default());
+        counters.decode_error();
+        tokio::spawn(serve(listener, shards, counters));
+
+        let mut scraper = TcpStream::connect(address).await.unwrap();
+        scraper
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        scraper.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("\ndc_connected_clients 1\n"));
+        assert!(response.contains("\ndc_client_inbox_messages 0\n"));
+        assert!(response.contains("\ndc_client_inbox_messages_max 0\n"));
+        assert!(response.contains("# TYPE dc_login_failures_total counter"));
+        assert!(response.contains("\ndc_decode_errors_total 1\n"));
+    }
+}
@@ -0,0 +1,227 @@
+//! the WebSocket transport, for browser clients
+//!
+//! every frame of [`Message`] is carried as a binary WebSocket message.
+//! the WebSocket is bridged to a pipe, whose end is a [`Connection`] like any TCP client,
+//! so the users of both transports are handled by the same supervisors
+//!
+//! [`Message`]: dvorak_message::message::Message
+
+use std::time::Duration;
+
+use dvorak_message::message::{DecodeLimits, MessageReader};
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use tokio::{
+    io::{AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf},
+    net::TcpStream,
+    sync::oneshot,
+    time::timeout,
+};
+use tokio_tungstenite::{
+    accept_async_with_config,
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame, WebSocketConfig},
+        Error, Message as WsMessage,
+    },
+    WebSocketStream,
+};
+use tracing::Instrument;
+
+use crate::dctor::connection::Connection;
+
+/// accept the WebSocket handshake of an incoming client, and bridge it to a [`Connection`]
+pub(crate) async fn accept(stream: TcpStream, limits: DecodeLimits) -> Result<Connection, Error> {
+    let peer = stream.peer_addr().ok();
+    let config = WebSocketConfig::default()
+        .max_message_size(Some(limits.max_frame_size))
+        .max_frame_size(Some(limits.max_frame_size));
+    let websocket = accept_async_with_config(stream, Some(config)).await?;
+
+    let (connection, pipe) = Connection::pipe(peer);
+    tokio::spawn(bridge(websocket, pipe).in_current_span());
+    Ok(connection)
+}
+
+/// how long the peer has to answer the close of the bridge
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// pass the binary messages into the pipe, and the frames out of the pipe as binary messages,
+/// until either side is closed
+///
+/// each direction runs in its own task, a peer not reading never stops its messages being read
+async fn bridge(websocket: WebSocketStream<TcpStream>, pipe: DuplexStream) {
+    let (sink, source) = websocket.split();
+    let (pipe_reader, pipe_writer) = tokio::io::split(pipe);
+    let (close_sender, close) = oneshot::channel();
+
+    let mut incoming =
+        tokio::spawn(forward_incoming(source, pipe_writer, close_sender).in_current_span());
+    let outgoing = tokio::spawn(forward_outgoing(pipe_reader, sink, close).in_current_span());
+    let _ = outgoing.await;
+    // the close is sent, wait for the peer to answer it
+    if timeout(CLOSE_TIMEOUT, &mut incoming).await.is_err() {
+        incoming.abort();
+    }
+}
+
+/// pass the binary messages into the pipe until the peer is gone,
+/// the pipe is shut down after, so the server closes its end
+async fn forward_incoming(
+    mut source: SplitStream<WebSocketStream<TcpStream>>,
+    mut pipe_writer: WriteHalf<DuplexStream>,
+    close_sender: oneshot::Sender<CloseFrame>,
+) {
+    loop {
+        match source.next().await {
+            Some(Ok(WsMessage::Binary(bytes))) => {
+                if pipe_writer.write_all(&bytes).await.is_err() {
+                    break;
+                }
+            }
+            Some(Ok(WsMessage::Text(_))) => {
+                let _ = close_sender.send(CloseFrame {
+                    code: CloseCode::Unsupported,
+                    reason: "only binary messages are supported".into(),
+                });
+                break;
+            }
+            Some(Ok(WsMessage::Close(_))) | None => break,
+            // pings are answered by tungstenite itself
+            Some(Ok(_)) => {}
+            Some(Err(e)) => {
+                tracing::debug!(error = %e, "websocket failure");
+                break;
+            }
+        }
+    }
+    let _ = pipe_writer.shutdown().await;
+}
+
+/// pass the frames out of the pipe as binary messages until the server closes its end,
+/// then close the WebSocket, with the reason given by [`forward_incoming`] if any
+async fn forward_outgoing(
+    pipe_reader: ReadHalf<DuplexStream>,
+    mut sink: SplitSink<WebSocketStream<TcpStream>, WsMessage>,
+    mut close: oneshot::Receiver<CloseFrame>,
+) {
+    // frames from the server are always valid, no limits needed
+    let mut frames = MessageReader::new(pipe_reader);
+    while let Ok(Some(message)) = frames.read().await {
+        // a decoded frame is always encoded again
+        let Ok(frame) = message.to_bytes() else {
+            continue;
+        };
+        if sink.send(WsMessage::Binary(frame)).await.is_err() {
+            return;
+        }
+    }
+    let _ = sink.send(WsMessage::Close(close.try_recv().ok())).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use dvorak_message::message::{Message, MessageType};
+    use tokio_tungstenite::client_async;
+
+    use super::*;
+    use crate::dctor::{
+        client::ClientConfig,
+        supervisor::{ClientSupervisor, OverflowPolicy},
+        testing::{connect, hand_over, new_client},
+    };
+
+    #[tokio::test]
+    async fn websocket_and_tcp_users_talk_to_each_other() {
+        let shards =
+            ClientSupervisor::start_shards(1, OverflowPolicy::Spill, None, ClientConfig::default());
+
+        // alice comes from a browser
+        let (stream, browser) = connect().await;
+        let url = format!("ws://{}/", browser.peer_addr().unwrap());
+        let (handshake, accepted) = tokio::join!(
+            client_async(url, browser),
+            accept(stream, DecodeLimits::default())
+        );
+        let (mut browser, _) = handshake.unwrap();
+        hand_over(&shards, "alice", accepted.unwrap()).await;
+
+        // bob comes over TCP
+        let mut bob = MessageReader::new(new_client(&shards, "bob").await);
+        let accepted = bob.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Login, accepted.message_type);
+        let Some(Ok(WsMessage::Binary(_accepted))) = browser.next().await else {
+            panic!("binary message expected");
+        };
+
+        let text = MessageType::Text("hello bob".to_string());
+        let message = Message::new(text, "alice".to_string(), "bob".to_string());
+        browser
+            .send(WsMessage::Binary(message.to_bytes().unwrap()))
+            .await
+            .unwrap();
+        let message = bob.read().await.unwrap().unwrap();
+        assert_eq!("alice", message.username);
+        assert_eq!(
+            MessageType::Text("hello bob".to_string()),
+            message.message_type
+        );
+
+        let text = MessageType::Text("hello alice".to_string());
+        let message = Message::new(text, "bob".to_string(), "alice".to_string());
+        Message::send(bob.get_mut(), message).await.unwrap();
+        let Some(Ok(WsMessage::Binary(frame))) = browser.next().await else {
+            panic!("binary message expected");
+        };
+        let message = MessageReader::new(&frame[..])
+            .read()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            MessageType::Text("hello alice".to_string()),
+            message.message_type
+        );
+    }
+
+    #[tokio::test]
+    async fn peer_not_reading_is_still_read() {
+        let (stream, browser) = connect().await;
+        let url = format!("ws://{}/", browser.peer_addr().unwrap());
+        let (handshake, accepted) = tokio::join!(
+            client_async(url, browser),
+            accept(stream, DecodeLimits::default())
+        );
+        let (mut browser, _) = handshake.unwrap();
+        let (reader, mut writer) = accepted.unwrap().into_split();
+
+        // the server writes far more than the socket buffers, the browser reads none of it
+        tokio::spawn(async move {
+            for _ in 0..10_000 {
+                let text = MessageType::Text("x".repeat(2048));
+                let message = Message::new(text, "bob".to_string(), "alice".to_string());
+                if Message::send(&mut writer, message).await.is_err() {
+                    return;
+                }
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let text = MessageType::Text("hello bob".to_string());
+        let message = Message::new(text, "alice".to_string(), "bob".to_string());
+        browser
+            .send(WsMessage::Binary(message.to_bytes().unwrap()))
+            .await
+            .unwrap();
+        let message = timeout(Duration::from_secs(5), MessageReader::new(reader).read())
+            .await
+            .expect("the bridge stopped reading")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            MessageType::Text("hello bob".to_string()),
+            message.message_type
+        );
+    }
+}
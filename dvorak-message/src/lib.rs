@@ -15,6 +15,7 @@
 //!
 //! # Features
 //! - message: wrap the data into Massage, and allow both send and receive
+//! - serde: `Serialize` and `Deserialize` of Message, for the text encodings like JSON lines
 
 #[cfg(feature = "message")]
 pub mod message;
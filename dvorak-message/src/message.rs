@@ -10,30 +10,59 @@
 //!
 //! |1 byte(indicated message type)|1 byte(indicated username length)|bytes, length depended in username length(indicated username who sending)|
 //! |4 bytes(indicated body length)|bytes, length depended in body content length(indicated body which communicating)|
+//!
+//! # Tracing
+//! every frame encoded or decoded emits a `TRACE` event of [`tracing`],
+//! with the type, username, receiver and lengths, but never the body
 
 use std::fmt::Display;
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
 
 mod message_type;
+mod reader;
 pub use message_type::MessageType;
+pub use reader::MessageReader;
 
 const MESSAGE_TYPE_BYTE_LENGTH: usize = 1;
 const MESSAGE_USERNAME_LENGTH_BYTE_LENGTH: usize = 1;
 const MESSAGE_RECEIVER_LENGTH_BYTE_LENGTH: usize = 1;
 const MESSAGE_BODY_LENGTH_BYTE_LENGTH: usize = 4;
 const DEFAULT_BUFFER_CAPACITY: usize = 512;
+/// the max size of a frame by default, 1 MiB
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+/// username of the messages sent by the server itself, like notices and errors
+pub const SERVER_NAME: &str = "<Server>";
+
+/// what kind of [`Error`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// the stream failed, or the frame is malformed
+    #[default]
+    Other,
+    /// the frame is beyond [`DecodeLimits`], rejected before buffering its body
+    FrameTooLarge,
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Error {
     pub description: String,
+    pub kind: ErrorKind,
 }
 
 impl Error {
     pub fn new(description: &str) -> Self {
         Error {
             description: String::from(description),
+            kind: ErrorKind::Other,
+        }
+    }
+
+    fn frame_too_large(description: String) -> Self {
+        Error {
+            description,
+            kind: ErrorKind::FrameTooLarge,
         }
     }
 }
@@ -46,9 +75,51 @@ impl Display for Error {
 
 pub type Result<T> = core::result::Result<T, Error>;
 
+/// limits of the frames decoded by [`MessageReader`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// max bytes of a whole frame, including its header
+    pub max_frame_size: usize,
+    /// max bytes of username and receiver
+    pub max_name_length: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_name_length: u8::MAX as usize,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// check the length of a field as soon as it is known
+    ///
+    /// `frame_size` is the size of frame known so far
+    fn check(&self, field: usize, length: usize, frame_size: usize) -> Result<()> {
+        const NAMES: [&str; 2] = ["username", "receiver"];
+        if let Some(name) = NAMES.get(field) {
+            if length > self.max_name_length {
+                return Err(Error::frame_too_large(format!(
+                    "{name} length {length} exceeds limit {}",
+                    self.max_name_length
+                )));
+            }
+        }
+        if frame_size > self.max_frame_size {
+            return Err(Error::frame_too_large(format!(
+                "frame size {frame_size} exceeds limit {}",
+                self.max_frame_size
+            )));
+        }
+        Ok(())
+    }
+}
+
 /// representing single message
 ///
-/// constructed from TcpStream use [`Message::read_from`]
+/// read from a stream by [`MessageReader`]
 ///
 /// # Protocol
 /// There is the protocol struct below
@@ -59,9 +130,17 @@ pub type Result<T> = core::result::Result<T, Error>;
 /// |receiver_length(u8)|username(receiver_length)
 /// |body_length(u32)|body(body_length)|
 ///
+/// # Serde
+/// with feature `serde`, the fields of [`MessageType`] are flattened into the message,
+/// and the receiver could be omitted if it is empty, like
+/// `{"type": "text", "body": "hello", "username": "dvorak", "receiver": "anduin"}`
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Message {
+    #[cfg_attr(feature = "serde", serde(flatten))]
     pub message_type: MessageType,
     pub username: String,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub receiver: String,
 }
 
@@ -78,56 +157,62 @@ impl Message {
         tcp_stream: &mut (impl AsyncWriteExt + std::marker::Unpin),
         message: Self,
     ) -> Result<()> {
-        let mut bytes = message.to_bytes();
+        let mut bytes = message.to_bytes()?;
 
-        tcp_stream.write_buf(&mut bytes).await.unwrap();
-        Ok(())
+        tcp_stream
+            .write_all_buf(&mut bytes)
+            .await
+            .map_err(|e| Error::new(&format!("send message failure: {}", e.kind())))
     }
 
-    pub async fn read_from(stream: &mut (impl AsyncReadExt + Unpin)) -> Result<Option<Self>> {
-        let mut bytes = BytesMut::with_capacity(DEFAULT_BUFFER_CAPACITY);
-
-        let len = stream.read_buf(&mut bytes).await.map_err(|e| {
-            let description = format!("read message failure: {}", e.kind());
-            Error {
-                description: String::from(description),
+    /// decode a message from the front of `bytes`
+    ///
+    /// return `Ok(None)` and keep `bytes` untouched if the message is incomplete yet,
+    /// otherwise the bytes of message are removed from `bytes`.
+    /// the lengths in header are checked against `limits` as soon as they are read,
+    /// so an oversize frame is rejected before its body arrives
+    pub(crate) fn decode(bytes: &mut BytesMut, limits: &DecodeLimits) -> Result<Option<Self>> {
+        let mut offset = MESSAGE_TYPE_BYTE_LENGTH;
+        let mut lengths = [0usize; 3];
+        let length_bytes = [
+            MESSAGE_USERNAME_LENGTH_BYTE_LENGTH,
+            MESSAGE_RECEIVER_LENGTH_BYTE_LENGTH,
+            MESSAGE_BODY_LENGTH_BYTE_LENGTH,
+        ];
+        for (i, (length, length_byte)) in lengths.iter_mut().zip(length_bytes).enumerate() {
+            if bytes.len() < offset + length_byte {
+                return Ok(None);
             }
-        })?;
-
-        if len == 0 {
+            let mut field = &bytes[offset..offset + length_byte];
+            *length = field.get_uint(length_byte) as usize;
+            offset += length_byte + *length;
+            limits.check(i, *length, offset)?;
+        }
+        if bytes.len() < offset {
             return Ok(None);
         }
 
-        Message::varify_len(MESSAGE_TYPE_BYTE_LENGTH, bytes.len())?;
+        let [username_len, receiver_len, body_len] = lengths;
         let message_type = bytes.get_u8();
-
-        Message::varify_len(MESSAGE_USERNAME_LENGTH_BYTE_LENGTH, bytes.len())?;
-        let username_len = bytes.get_u8();
-
-        Message::varify_len(username_len as usize, bytes.len())?;
-        let username = bytes.split_to(username_len as usize);
-        let username = String::from_utf8(username.to_vec()).unwrap();
-
-        Message::varify_len(MESSAGE_RECEIVER_LENGTH_BYTE_LENGTH, bytes.len())?;
-
-        let receiver_len = bytes.get_u8();
-
-        Message::varify_len(receiver_len as usize, bytes.len())?;
-        let receiver = bytes.split_to(receiver_len as usize);
-        let receiver = String::from_utf8(receiver.to_vec()).unwrap();
-
-        Message::varify_len(MESSAGE_BODY_LENGTH_BYTE_LENGTH, bytes.len())?;
-
-        let body_len = bytes.get_u32();
-
-        Message::varify_len(body_len as usize, bytes.len())?;
-        let body = bytes.split_to(body_len as usize);
-
-        Ok(Some(Message {
-            message_type: MessageType::parse(message_type, Some(body.freeze())).unwrap(),
+        bytes.advance(MESSAGE_USERNAME_LENGTH_BYTE_LENGTH);
+        let username = Message::decode_string(bytes.split_to(username_len))?;
+        bytes.advance(MESSAGE_RECEIVER_LENGTH_BYTE_LENGTH);
+        let receiver = Message::decode_string(bytes.split_to(receiver_len))?;
+        bytes.advance(MESSAGE_BODY_LENGTH_BYTE_LENGTH);
+        let body = bytes.split_to(body_len);
+        let message_type = MessageType::parse(message_type, Some(body.freeze()))?;
+
+        let message = Message {
+            message_type,
             username,
             receiver,
-        }))
+        };
+        message.trace("frame decoded", offset);
+        Ok(Some(message))
+    }
+
+    fn decode_string(bytes: BytesMut) -> Result<String> {
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::new("invalid utf-8 string"))
     }
 
     /// get the body of message
@@ -139,27 +224,21 @@ impl Message {
         }
     }
 
-    fn varify_len(expect_len: usize, actual_len: usize) -> Result<()> {
-        if actual_len < expect_len {
-            Err(Error {
-                description: String::from(format!(
-                    "actual length {} lower than expect length {}",
-                    actual_len, expect_len
-                )),
-            })
-        } else {
-            Ok(())
-        }
-    }
-
-    fn to_bytes(self) -> Bytes {
+    /// encode the message to a frame,
+    /// for the transports carrying frames in their own messages, like WebSocket
+    ///
+    /// fail if a name is longer than its length byte, or the body than its 4 bytes,
+    /// instead of truncating them
+    pub fn to_bytes(&self) -> Result<Bytes> {
         let body = self.message_type.as_bytes();
         let username = Bytes::from(self.username.clone());
-        let username_length = username.len() as u8;
+        let username_length = Message::encode_len("username", username.len())?;
         let receiver = Bytes::from(self.receiver.clone());
-        let receiver_length = receiver.len() as u8;
+        let receiver_length = Message::encode_len("receiver", receiver.len())?;
 
-        let body_length = body.len() as u32;
+        let body_length = u32::try_from(body.len()).map_err(|_| {
+            Error::frame_too_large(format!("body length {} exceeds limit", body.len()))
+        })?;
 
         let capacity_length = MESSAGE_TYPE_BYTE_LENGTH + body.len() + username.len() + 2;
         let mut bytes = BytesMut::with_capacity(capacity_length);
@@ -171,7 +250,25 @@ impl Message {
         bytes.put_u32(body_length);
         bytes.put(body);
 
-        bytes.freeze()
+        self.trace("frame encoded", bytes.len());
+        Ok(bytes.freeze())
+    }
+
+    fn encode_len(name: &str, length: usize) -> Result<u8> {
+        u8::try_from(length).map_err(|_| {
+            Error::frame_too_large(format!("{name} length {length} exceeds limit {}", u8::MAX))
+        })
+    }
+
+    fn trace(&self, event: &str, frame_len: usize) {
+        tracing::trace!(
+            kind = self.message_type.name(),
+            username = %self.username,
+            receiver = %self.receiver,
+            body_len = self.message_type.body_length(),
+            frame_len,
+            "{event}"
+        );
     }
 }
 
@@ -187,11 +284,11 @@ mod tests {
         let message_type = MessageType::Text(body.clone());
 
         let message = Message::new(message_type, username.clone(), receiver.clone());
-        let bytes = message.to_bytes();
+        let bytes = message.to_bytes().unwrap();
 
-        let expected_username_len = username.as_bytes().len() as u8;
+        let expected_username_len = username.len() as u8;
         let expected_username = username.as_bytes();
-        let expected_receiver_len = receiver.as_bytes().len() as u8;
+        let expected_receiver_len = receiver.len() as u8;
         let expected_receiver = receiver.as_bytes();
         let mut expected_bytes = BytesMut::with_capacity(bytes.len());
         expected_bytes.put_u8(1u8);
@@ -199,15 +296,23 @@ mod tests {
         expected_bytes.put(expected_username);
         expected_bytes.put_u8(expected_receiver_len);
         expected_bytes.put(expected_receiver);
-        expected_bytes.put_u32(body.as_bytes().len() as u32);
+        expected_bytes.put_u32(body.len() as u32);
         expected_bytes.put(body.as_bytes());
 
         assert_eq!(expected_bytes, bytes);
     }
 
+    #[test]
+    fn to_bytes_rejects_long_name() {
+        let message = Message::new(MessageType::Login, "x".repeat(256), String::new());
+        let error = message.to_bytes().unwrap_err();
+        assert_eq!(ErrorKind::FrameTooLarge, error.kind);
+        assert!(error.description.contains("username length 256"));
+    }
+
     #[tokio::test]
-    async fn read_from_duplex_stream() {
-        let (mut client, mut server) = tokio::io::duplex(64);
+    async fn read_by_reader_from_duplex_stream() {
+        let (mut client, server) = tokio::io::duplex(64);
 
         let username = String::from("dvorak");
         let receiver = String::from("anduin");
@@ -215,13 +320,13 @@ mod tests {
         let message_type = MessageType::Text(body.clone());
 
         let message = Message::new(message_type, username.clone(), receiver.clone());
-        let mut message_bytes = message.to_bytes();
+        let mut message_bytes = message.to_bytes().unwrap();
         let expected_len = message_bytes.len();
 
         let len = client.write_buf(&mut message_bytes).await.unwrap();
         assert_eq!(len, expected_len);
 
-        let message = Message::read_from(&mut server).await.unwrap().unwrap();
+        let message = MessageReader::new(server).read().await.unwrap().unwrap();
 
         assert_eq!(message.message_type, MessageType::Text(body));
         assert_eq!(message.username, username);
@@ -248,4 +353,23 @@ mod tests {
         let body_value = message.get_body();
         assert_eq!(None, body_value);
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_json_lines() {
+        let message = Message::new(
+            MessageType::Text(String::from("hello")),
+            String::from("dvorak"),
+            String::from("anduin"),
+        );
+        assert_eq!(
+            r#"{"type":"text","body":"hello","username":"dvorak","receiver":"anduin"}"#,
+            serde_json::to_string(&message).unwrap()
+        );
+
+        let login: Message =
+            serde_json::from_str(r#"{"type":"login","username":"dvorak"}"#).unwrap();
+        assert_eq!(MessageType::Login, login.message_type);
+        assert_eq!("", login.receiver);
+    }
 }
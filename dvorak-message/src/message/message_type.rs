@@ -2,7 +2,15 @@ use super::{Error, Result};
 use bytes::Bytes;
 
 /// representing the MessageType in `Message` protocol first byte
+///
+/// with feature `serde`, it is tagged by `type` and its text is in `body`,
+/// like `{"type": "text", "body": "hello"}`
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type", content = "body", rename_all = "lowercase")
+)]
 pub enum MessageType {
     /// no yet use
     Heart,
@@ -12,6 +20,11 @@ pub enum MessageType {
     Login,
     /// indicating the action that client disconnecting
     Logout,
+    /// indicating the peer did something wrong, the body describes it
+    Error(String),
+    /// from client, subscribing the presence of other users,
+    /// they are told by `Login` and `Logout` of their usernames since then
+    Presence,
 }
 
 impl MessageType {
@@ -21,21 +34,28 @@ impl MessageType {
     pub fn parse(value: u8, body: Option<Bytes>) -> Result<Self> {
         match value {
             0 => Ok(Self::Heart),
-            1 => Ok(Self::Text(
-                String::from_utf8(body.unwrap().to_vec()).unwrap(),
-            )),
+            1 => Ok(Self::Text(Self::parse_text(body)?)),
             2 => Ok(Self::Login),
             3 => Ok(Self::Logout),
+            4 => Ok(Self::Error(Self::parse_text(body)?)),
+            5 => Ok(Self::Presence),
             other => Err(Error::new(&format!("unsupported value: {}", other))),
         }
     }
 
+    fn parse_text(body: Option<Bytes>) -> Result<String> {
+        let body = body.unwrap_or_default().to_vec();
+        String::from_utf8(body).map_err(|_| Error::new("invalid utf-8 text"))
+    }
+
     pub fn body_length(&self) -> u32 {
         match self {
             Self::Heart => 0,
             Self::Text(body) => body.len() as u32,
             Self::Login => 0,
             Self::Logout => 0,
+            Self::Error(body) => body.len() as u32,
+            Self::Presence => 0,
         }
     }
 
@@ -45,6 +65,8 @@ impl MessageType {
             Self::Text(body) => Bytes::from(body.clone()),
             Self::Login => Bytes::new(),
             Self::Logout => Bytes::new(),
+            Self::Error(body) => Bytes::from(body.clone()),
+            Self::Presence => Bytes::new(),
         }
     }
 
@@ -54,6 +76,20 @@ impl MessageType {
             Self::Text(_) => 1,
             Self::Login => 2,
             Self::Logout => 3,
+            Self::Error(_) => 4,
+            Self::Presence => 5,
+        }
+    }
+
+    /// name of the type, for logging without the body
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Heart => "heart",
+            Self::Text(_) => "text",
+            Self::Login => "login",
+            Self::Logout => "logout",
+            Self::Error(_) => "error",
+            Self::Presence => "presence",
         }
     }
 }
@@ -73,6 +109,22 @@ mod tests {
         assert_eq!(Ok(MessageType::Text(String::from("test TEST"))), res);
     }
 
+    #[test]
+    fn parse_error_success() {
+        let body = Bytes::from("too fast");
+        let res = MessageType::parse(4, Some(body));
+
+        assert_eq!(Ok(MessageType::Error(String::from("too fast"))), res);
+    }
+
+    #[test]
+    fn parse_presence_success() {
+        let res = MessageType::parse(5, None);
+
+        assert_eq!(Ok(MessageType::Presence), res);
+        assert_eq!(5, MessageType::Presence.value());
+    }
+
     #[test]
     fn parse_heart_success() {
         let res = MessageType::parse(0, None);
@@ -83,7 +135,7 @@ mod tests {
     #[test]
     fn text_body_length_success() {
         let body = String::from("我I哒哒哒");
-        let len = body.as_bytes().len() as u32;
+        let len = body.len() as u32;
         let body = Bytes::from(body);
         let res = MessageType::parse(1, Some(body)).unwrap();
 
@@ -0,0 +1,182 @@
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::{DecodeLimits, Error, Message, Result, DEFAULT_BUFFER_CAPACITY};
+
+/// read messages from a stream one after another
+///
+/// the bytes following a message are kept for the next read,
+/// so messages arriving back-to-back are never lost.
+///
+/// [`MessageReader::read`] is cancel safe,
+/// it could be used as a branch of `tokio::select!`
+///
+/// frames beyond [`DecodeLimits`] are rejected with [`ErrorKind::FrameTooLarge`],
+/// the reader should be dropped then, since the rest of stream is not aligned to frames
+///
+/// [`ErrorKind::FrameTooLarge`]: super::ErrorKind::FrameTooLarge
+///
+/// # example
+/// ```ignore
+/// let mut reader = MessageReader::new(tcp_stream);
+/// while let Some(message) = reader.read().await? {
+///     println!("{}", message.username);
+/// }
+/// ```
+pub struct MessageReader<R> {
+    stream: R,
+    buffer: BytesMut,
+    limits: DecodeLimits,
+}
+
+impl<R: AsyncRead + Unpin> MessageReader<R> {
+    /// construct a reader with the default [`DecodeLimits`]
+    pub fn new(stream: R) -> Self {
+        Self::with_limits(stream, DecodeLimits::default())
+    }
+
+    pub fn with_limits(stream: R, limits: DecodeLimits) -> Self {
+        MessageReader {
+            stream,
+            buffer: BytesMut::with_capacity(DEFAULT_BUFFER_CAPACITY),
+            limits,
+        }
+    }
+
+    /// read next message
+    ///
+    /// return `Ok(None)` if the stream is closed
+    pub async fn read(&mut self) -> Result<Option<Message>> {
+        loop {
+            if let Some(message) = Message::decode(&mut self.buffer, &self.limits)? {
+                return Ok(Some(message));
+            }
+
+            let len = self
+                .stream
+                .read_buf(&mut self.buffer)
+                .await
+                .map_err(|e| Error::new(&format!("read message failure: {}", e.kind())))?;
+
+            if len == 0 {
+                return if self.buffer.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(Error::new("stream closed in the middle of message"))
+                };
+            }
+        }
+    }
+
+    /// the underlying stream, for writing to it
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.stream
+    }
+
+    /// the underlying stream, and the bytes read from it but not decoded yet
+    pub fn into_parts(self) -> (R, BytesMut) {
+        (self.stream, self.buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::message::{ErrorKind, MessageType};
+
+    #[tokio::test]
+    async fn read_back_to_back_messages() {
+        let (mut client, server) = tokio::io::duplex(256);
+        let first = Message::new(
+            MessageType::Text("first".to_string()),
+            "dvorak".to_string(),
+            "anduin".to_string(),
+        );
+        let second = Message::new(MessageType::Logout, "dvorak".to_string(), String::new());
+        let mut bytes = BytesMut::new();
+        bytes.extend_from_slice(&first.to_bytes().unwrap());
+        bytes.extend_from_slice(&second.to_bytes().unwrap());
+        client.write_all(&bytes).await.unwrap();
+        drop(client);
+
+        let mut reader = MessageReader::new(server);
+
+        let message = reader.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Text("first".to_string()), message.message_type);
+        assert_eq!("anduin", message.receiver);
+        let message = reader.read().await.unwrap().unwrap();
+        assert_eq!(MessageType::Logout, message.message_type);
+        assert!(reader.read().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn read_message_split_in_pieces() {
+        let (mut client, server) = tokio::io::duplex(256);
+        let message = Message::new(
+            MessageType::Text("split body".to_string()),
+            "dvorak".to_string(),
+            "anduin".to_string(),
+        );
+        let bytes = message.to_bytes().unwrap();
+
+        let mut reader = MessageReader::new(server);
+        let writer = tokio::spawn(async move {
+            for piece in bytes.chunks(3) {
+                client.write_all(piece).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let message = reader.read().await.unwrap().unwrap();
+        assert_eq!(Some(&"split body".to_string()), message.get_body());
+        writer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn closed_in_the_middle_of_message() {
+        let (mut client, server) = tokio::io::duplex(256);
+        client.write_all(&[1, 6, b'd']).await.unwrap();
+        drop(client);
+
+        let mut reader = MessageReader::new(server);
+
+        assert!(reader.read().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn oversize_body_is_rejected_before_it_arrives() {
+        let (mut client, server) = tokio::io::duplex(256);
+        // announce a body of 4 GiB, but never send it
+        client
+            .write_all(&[1, 1, b'd', 1, b'a', 0xff, 0xff, 0xff, 0xff])
+            .await
+            .unwrap();
+
+        let mut reader = MessageReader::new(server);
+
+        let error = reader.read().await.unwrap_err();
+        assert_eq!(ErrorKind::FrameTooLarge, error.kind);
+        assert!(reader.buffer.capacity() <= DEFAULT_BUFFER_CAPACITY);
+    }
+
+    #[tokio::test]
+    async fn long_username_is_rejected() {
+        let (mut client, server) = tokio::io::duplex(256);
+        let message = Message::new(MessageType::Login, "x".repeat(20), String::new());
+        client
+            .write_all(&message.to_bytes().unwrap())
+            .await
+            .unwrap();
+
+        let limits = DecodeLimits {
+            max_name_length: 16,
+            ..DecodeLimits::default()
+        };
+        let mut reader = MessageReader::with_limits(server, limits);
+
+        let error = reader.read().await.unwrap_err();
+        assert_eq!(ErrorKind::FrameTooLarge, error.kind);
+    }
+}